@@ -0,0 +1,330 @@
+use std::collections::{BTreeSet, HashMap};
+use serde::Serialize;
+
+use crate::diff::{ComparisonResult, LineDiff, parse_line_range};
+use crate::dom::{DomNode, DomNodeKind, build_dom_tree};
+use crate::ignore::IgnoreRules;
+use crate::selector::Selector;
+
+// Assertions on a comparison for gating deploys: a ceiling on the difference
+// percentage, on added and removed lines, and regions of the page that must
+// not change at all. A failed assertion exits with `EXIT_ASSERTION_FAILED`,
+// kept apart from the exit status 1 of usage and I/O errors.
+//
+// Added and removed lines are counted like `git diff --numstat`: the lines of
+// added line changes plus the new lines of modified ones are added, the lines
+// of removed changes plus the old lines of modified ones are removed.
+//
+// A protected region is every element matching a `--forbid-change` selector,
+// compared by DOM subtree hash (after `--ignore` masking). A matching element
+// whose subtree appears unchanged in the other version, even at another
+// position, passes; the others are reported as modified when both versions
+// have a matching element at the same path, else as added or removed.
+
+pub const EXIT_ASSERTION_FAILED: i32 = 3;
+
+#[derive(Debug, Clone, Default)]
+pub struct Assertions {
+    pub fail_above: Option<f64>,   // percent
+    pub max_added: Option<usize>,  // lines
+    pub max_removed: Option<usize>,
+    pub forbid_change: Vec<Selector>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtectedChange {
+    pub path: String,
+    pub change_type: String,       // "added", "removed", "modified"
+    pub line_a: Option<usize>,
+    pub line_b: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub assertion: String,         // "fail_above", "max_added", "max_removed", "forbid_change"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selector: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<ProtectedChange>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AssertionSummary {
+    pub passed: bool,
+    pub exit_code: i32,
+    pub version_a: String,
+    pub version_b: String,
+    pub difference_percent: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added_lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed_lines: Option<usize>,
+    pub violations: Vec<Violation>,
+}
+
+// Lines touched by the line changes: (added lines, removed lines)
+pub fn changed_lines(line_diffs: &[LineDiff]) -> (usize, usize) {
+    let mut added = BTreeSet::new();
+    let mut removed = BTreeSet::new();
+    let mark = |lines: &mut BTreeSet<usize>, range: Option<&str>| {
+        if let Some((start, end)) = range.and_then(parse_line_range) {
+            lines.extend(start..=end);
+        }
+    };
+    for diff in line_diffs {
+        match diff.change_type.as_str() {
+            "added" => mark(&mut added, Some(&diff.line_range)),
+            "removed" => mark(&mut removed, Some(&diff.line_range)),
+            _ => {
+                mark(&mut added, diff.new_line_range.as_deref());
+                mark(&mut removed, diff.old_line_range.as_deref());
+            }
+        }
+    }
+    (added.len(), removed.len())
+}
+
+// Outermost elements matching `selector`, with their XPath-style paths
+fn protected_elements<'a>(root: &'a DomNode, selector: &Selector) -> Vec<(String, &'a DomNode)> {
+    fn walk<'a>(node: &'a DomNode, path: &str, selector: &Selector, ancestors: &mut Vec<&'a DomNode>, out: &mut Vec<(String, &'a DomNode)>) {
+        for (child, step) in node.children.iter().zip(node.child_steps()) {
+            if child.kind != DomNodeKind::Element {
+                continue;
+            }
+            let child_path = format!("{}/{}", path, step);
+            if selector.matches(child, ancestors) {
+                out.push((child_path, child));
+            } else {
+                ancestors.push(child);
+                walk(child, &child_path, selector, ancestors, out);
+                ancestors.pop();
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    walk(root, "", selector, &mut Vec::new(), &mut out);
+    out
+}
+
+// Changes to the elements matching `selector` between two DOM trees
+pub fn protected_changes<'a>(tree_a: &'a DomNode, tree_b: &'a DomNode, selector: &Selector) -> Vec<ProtectedChange> {
+    let mut elements_a = protected_elements(tree_a, selector);
+    let mut elements_b = protected_elements(tree_b, selector);
+
+    // Subtrees present in both versions are unchanged, wherever they are
+    let counts = |elements: &[(String, &'a DomNode)]| {
+        let mut counts: HashMap<&'a str, usize> = HashMap::new();
+        for (_, node) in elements {
+            *counts.entry(node.hash.as_str()).or_default() += 1;
+        }
+        counts
+    };
+    let counts_b = counts(&elements_b);
+    let mut shared_a: HashMap<&str, usize> = counts(&elements_a)
+        .into_iter()
+        .filter_map(|(hash, n)| counts_b.get(hash).map(|&m| (hash, n.min(m))))
+        .collect();
+    let mut shared_b = shared_a.clone();
+    let take = |shared: &mut HashMap<&str, usize>, hash: &str| match shared.get_mut(hash) {
+        Some(n) if *n > 0 => {
+            *n -= 1;
+            true
+        }
+        _ => false,
+    };
+    elements_a.retain(|(_, node)| !take(&mut shared_a, &node.hash));
+    elements_b.retain(|(_, node)| !take(&mut shared_b, &node.hash));
+
+    let paths_b: HashMap<&str, &DomNode> = elements_b.iter().map(|(path, node)| (path.as_str(), *node)).collect();
+    let mut changes: Vec<ProtectedChange> = elements_a
+        .iter()
+        .map(|(path, node)| match paths_b.get(path.as_str()) {
+            Some(new) => ProtectedChange {
+                path: path.clone(),
+                change_type: "modified".to_string(),
+                line_a: Some(node.line_number),
+                line_b: Some(new.line_number),
+            },
+            None => ProtectedChange {
+                path: path.clone(),
+                change_type: "removed".to_string(),
+                line_a: Some(node.line_number),
+                line_b: None,
+            },
+        })
+        .collect();
+    let paths_a: BTreeSet<&str> = elements_a.iter().map(|(path, _)| path.as_str()).collect();
+    changes.extend(elements_b.iter().filter(|(path, _)| !paths_a.contains(path.as_str())).map(|(path, node)| {
+        ProtectedChange {
+            path: path.clone(),
+            change_type: "added".to_string(),
+            line_a: None,
+            line_b: Some(node.line_number),
+        }
+    }));
+    changes
+}
+
+impl Assertions {
+    pub fn is_empty(&self) -> bool {
+        self.fail_above.is_none() && self.max_added.is_none() && self.max_removed.is_none() && self.forbid_change.is_empty()
+    }
+
+    // Whether checking needs `ComparisonResult::line_diffs`
+    pub fn needs_line_diffs(&self) -> bool {
+        self.max_added.is_some() || self.max_removed.is_some()
+    }
+
+    // Check `result`, the comparison of `html_a` with `html_b` under `rules`.
+    // The documents are only read for `forbid_change`, and line limits need a
+    // comparison run with line diffs.
+    pub fn check(&self, result: &ComparisonResult, html_a: &str, html_b: &str, rules: &IgnoreRules) -> AssertionSummary {
+        let mut violations = Vec::new();
+        let percent = result.difference_percent;
+        if let Some(limit) = self.fail_above
+            && percent > limit
+        {
+            violations.push(Violation {
+                assertion: "fail_above".to_string(),
+                limit: Some(limit),
+                actual: Some(percent),
+                selector: None,
+                changes: Vec::new(),
+                message: format!("difference {:.2}% is above {}%", percent, limit),
+            });
+        }
+
+        let (added, removed) = changed_lines(&result.line_diffs);
+        for (assertion, limit, actual, what) in [
+            ("max_added", self.max_added, added, "added"),
+            ("max_removed", self.max_removed, removed, "removed"),
+        ] {
+            if let Some(limit) = limit
+                && actual > limit
+            {
+                violations.push(Violation {
+                    assertion: assertion.to_string(),
+                    limit: Some(limit as f64),
+                    actual: Some(actual as f64),
+                    selector: None,
+                    changes: Vec::new(),
+                    message: format!("{} {} lines, more than {}", actual, what, limit),
+                });
+            }
+        }
+
+        if !self.forbid_change.is_empty() {
+            let tree_a = build_dom_tree(html_a, rules);
+            let tree_b = build_dom_tree(html_b, rules);
+            for selector in &self.forbid_change {
+                let changes = protected_changes(&tree_a, &tree_b, selector);
+                if !changes.is_empty() {
+                    violations.push(Violation {
+                        assertion: "forbid_change".to_string(),
+                        limit: None,
+                        actual: None,
+                        selector: Some(selector.as_str().to_string()),
+                        message: format!("{} protected element(s) matching '{}' changed", changes.len(), selector.as_str()),
+                        changes,
+                    });
+                }
+            }
+        }
+
+        let counted = self.needs_line_diffs();
+        AssertionSummary {
+            passed: violations.is_empty(),
+            exit_code: if violations.is_empty() { 0 } else { EXIT_ASSERTION_FAILED },
+            version_a: result.version_a.clone(),
+            version_b: result.version_b.clone(),
+            difference_percent: percent,
+            added_lines: counted.then_some(added),
+            removed_lines: counted.then_some(removed),
+            violations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DomDiffer;
+
+    fn line_diff(change_type: &str, line_range: &str, old: Option<&str>, new: Option<&str>) -> LineDiff {
+        LineDiff {
+            line_range: line_range.to_string(),
+            change_type: change_type.to_string(),
+            old_line_range: old.map(str::to_string),
+            new_line_range: new.map(str::to_string),
+            ..LineDiff::default()
+        }
+    }
+
+    fn changes(html_a: &str, html_b: &str, selector: &str) -> Vec<ProtectedChange> {
+        let rules = IgnoreRules::default();
+        protected_changes(&build_dom_tree(html_a, &rules), &build_dom_tree(html_b, &rules), &Selector::parse(selector).unwrap())
+    }
+
+    #[test]
+    fn modified_ranges_count_on_both_sides() {
+        let diffs = [
+            line_diff("added", "L1-L2", None, None),
+            line_diff("removed", "L7", None, None),
+            line_diff("modified", "L10-L12", Some("L9-L10"), Some("L10-L12")),
+        ];
+        assert_eq!(changed_lines(&diffs), (5, 3));
+    }
+
+    #[test]
+    fn lines_touched_twice_count_once() {
+        let diffs = [
+            line_diff("added", "L4", None, None),
+            line_diff("modified", "L4-L5", Some("L4"), Some("L4-L5")),
+            line_diff("modified", "L5", Some("L4"), Some("L5")),
+        ];
+        assert_eq!(changed_lines(&diffs), (2, 1));
+    }
+
+    #[test]
+    fn moved_protected_subtree_passes() {
+        let a = "<body><nav><a href=\"/\">Home</a></nav><main><p>text</p></main></body>";
+        let b = "<body><main><p>other text</p></main><nav><a href=\"/\">Home</a></nav></body>";
+        assert!(changes(a, b, "nav").is_empty());
+        assert_eq!(changes(a, b, "main")[0].change_type, "modified");
+    }
+
+    #[test]
+    fn protected_elements_report_added_removed_and_modified() {
+        let a = "<body><h1>Title</h1><ul><li>a</li></ul></body>";
+        let b = "<body><h1>New title</h1><p>x</p></body>";
+        let found = changes(a, b, "ul, h1");
+        let kinds: Vec<_> = found.iter().map(|c| (c.path.as_str(), c.change_type.as_str())).collect();
+        assert_eq!(kinds.len(), 2);
+        assert!(kinds.iter().any(|(_, kind)| *kind == "modified"));
+        assert!(kinds.iter().any(|(path, kind)| path.contains("ul") && *kind == "removed"));
+    }
+
+    #[test]
+    fn check_reports_each_violated_limit() {
+        let a = "<ul>\n<li>a</li>\n</ul>";
+        let b = "<ul>\n<li>a</li>\n<li>b</li>\n<li>c</li>\n</ul>";
+        let result = DomDiffer::new().line_diffs(true).compare(a, b);
+        let assertions = Assertions {
+            fail_above: Some(100.0),
+            max_added: Some(1),
+            max_removed: Some(0),
+            forbid_change: vec![Selector::parse("ul").unwrap()],
+        };
+        let summary = assertions.check(&result, a, b, &IgnoreRules::default());
+        let failed: Vec<_> = summary.violations.iter().map(|v| v.assertion.as_str()).collect();
+        assert_eq!(failed, ["max_added", "forbid_change"]);
+        assert_eq!(summary.exit_code, EXIT_ASSERTION_FAILED);
+        assert_eq!(summary.added_lines, Some(2));
+    }
+}
@@ -0,0 +1,419 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::cache::CacheStats;
+use crate::diff::{DiffMethod, compare_with_method, detailed_diff};
+use crate::hashing::{get_perf_config, hash_cache_stats};
+use crate::ignore::IgnoreRules;
+use crate::normalize::{hash_spans, normalize_html_with_lines};
+
+// Heap accounting for benchmarks: the system allocator plus counters. The
+// binary installs it with `#[global_allocator]`; without that, every
+// counter stays at zero.
+pub struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+impl CountingAllocator {
+    fn record_alloc(size: usize) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+        let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+    }
+
+    fn record_dealloc(size: usize) {
+        LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            Self::record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            Self::record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        Self::record_dealloc(layout.size());
+    }
+
+    // Counted as freeing the old block and allocating the new one
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            Self::record_dealloc(layout.size());
+            Self::record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+// Heap counters since the process started
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct AllocStats {
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+    pub live_bytes: usize,
+    pub peak_bytes: usize,         // highest `live_bytes` since the last `reset_peak_heap`
+}
+
+pub fn alloc_stats() -> AllocStats {
+    AllocStats {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+        live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+// Start measuring the peak from the current heap size
+pub fn reset_peak_heap() {
+    PEAK_BYTES.store(LIVE_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+pub fn get_memory_usage() -> usize {
+    // This is a simplified memory usage estimation
+    // In practice, you'd use a proper memory profiling tool
+    use std::process::Command;
+
+    let output = Command::new("ps")
+        .args(["-o", "rss=", "-p", &std::process::id().to_string()])
+        .output();
+
+    if let Ok(output) = output {
+        let rss_str = String::from_utf8_lossy(&output.stdout);
+        rss_str.trim().parse::<usize>().unwrap_or(0) * 1024 // Convert KB to bytes
+    } else {
+        0
+    }
+}
+
+#[derive(Serialize)]
+pub struct BenchmarkResult {
+    pub method: String,
+    pub chunk_size: usize,
+    pub num_tests: usize,
+    pub avg_time_ms: f64,
+    pub avg_time_us: f64,  // microseconds
+    pub avg_time_ns: f64,  // nanoseconds
+    pub min_time_ms: u128,
+    pub min_time_us: u128,
+    pub min_time_ns: u128,
+    pub max_time_ms: u128,
+    pub max_time_us: u128,
+    pub max_time_ns: u128,
+    pub total_time_ms: u128,
+    pub total_time_us: u128,
+    pub total_time_ns: u128,
+    pub memory_usage_bytes: usize,             // growth of the process RSS over the run
+    pub allocations_per_comparison: f64,
+    pub allocated_bytes_per_comparison: f64,
+    pub peak_heap_bytes: usize,                // above the heap size when the run started
+    pub throughput_comparisons_per_sec: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_cache: Option<CacheStats>,    // lookups during this run, when the cache is enabled
+}
+
+// What a benchmark run measures: normalizing both versions into chunk
+// strings and comparing them with a method, or hashing token spans into
+// digests (the pipeline `DomDiffer` uses for plain `merkle_lite` comparisons)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pipeline {
+    Chunks(DiffMethod),
+    Spans,
+}
+
+impl Pipeline {
+    fn name(&self) -> &'static str {
+        match self {
+            Pipeline::Chunks(method) => method.as_str(),
+            Pipeline::Spans => "merkle_lite_spans",
+        }
+    }
+
+    // One full comparison; returns the difference so the work is not optimized away
+    fn run(&self, html_a: &str, html_b: &str, chunk_size: usize) -> f64 {
+        match *self {
+            Pipeline::Chunks(method) => {
+                let (chunks_a, _) = normalize_html_with_lines(html_a, chunk_size);
+                let (chunks_b, _) = normalize_html_with_lines(html_b, chunk_size);
+                compare_with_method(&chunks_a, &chunks_b, method).0
+            }
+            Pipeline::Spans => {
+                let algorithm = get_perf_config().hash_algorithm;
+                let rules = IgnoreRules::default();
+                let hashed_a = hash_spans(html_a, chunk_size, &rules, algorithm);
+                let hashed_b = hash_spans(html_b, chunk_size, &rules, algorithm);
+                detailed_diff(&hashed_a.chunk_hashes, &hashed_b.chunk_hashes).0
+            }
+        }
+    }
+}
+
+pub fn run_benchmark(num_tests: usize) {
+    println!("Running benchmark with {} tests...", num_tests);
+
+    let base_html = r##"<!DOCTYPE html>
+<html>
+<head>
+    <title>Benchmark HTML</title>
+    <meta charset="utf-8">
+    <style>
+        body { font-family: Arial, sans-serif; }
+        .container { max-width: 1200px; margin: 0 auto; }
+        .header { background: #333; color: white; padding: 20px; }
+        .content { padding: 20px; }
+        .footer { background: #666; color: white; padding: 10px; }
+    </style>
+</head>
+<body>
+    <div class="container">
+        <header class="header">
+            <h1>Performance Test Page</h1>
+            <nav>
+                <ul>
+                    <li><a href="#home">Home</a></li>
+                    <li><a href="#about">About</a></li>
+                    <li><a href="#contact">Contact</a></li>
+                </ul>
+            </nav>
+        </header>
+        <main class="content">
+            <section>
+                <h2>Content Section</h2>
+                <p>This is a test paragraph with <strong>bold</strong> and <em>italic</em> text.</p>
+                <div class="card">
+                    <h3>Card Title</h3>
+                    <p>Card content goes here with more text to analyze.</p>
+                    <button onclick="alert('clicked')">Click Me</button>
+                </div>
+            </section>
+            <section>
+                <h2>List Section</h2>
+                <ul>
+                    <li>Item 1</li>
+                    <li>Item 2</li>
+                    <li>Item 3</li>
+                </ul>
+                <table>
+                    <tr><th>Column 1</th><th>Column 2</th></tr>
+                    <tr><td>Data 1</td><td>Data 2</td></tr>
+                </table>
+            </section>
+        </main>
+        <footer class="footer">
+            <p>&copy; 2025 Benchmark Test</p>
+        </footer>
+    </div>
+    <script>
+        console.log('Page loaded');
+        function testFunction() {
+            return 'test';
+        }
+    </script>
+</body>
+</html>"##;
+
+    let chunk_sizes = [1, 2, 3, 5];
+    let pipelines = [
+        Pipeline::Chunks(DiffMethod::MerkleLite),
+        Pipeline::Chunks(DiffMethod::MerkleTree),
+        Pipeline::Spans,
+    ];
+
+    let mut benchmark_results = Vec::new();
+
+    for chunk_size in chunk_sizes {
+        for pipeline in pipelines {
+            let method_name = pipeline.name();
+            println!("Testing {} with chunk size {}...", method_name, chunk_size);
+
+            // Versions are generated up front so that neither their time nor their
+            // allocations count towards the comparisons
+            let modified_versions: Vec<String> = (0..num_tests)
+                .map(|test_idx| format!("{}<div id='test-{}'></div>", base_html, test_idx))
+                .collect();
+
+            // Per-comparison times in nanoseconds, covering normalization, hashing and diffing
+            let mut times: Vec<u128> = Vec::with_capacity(num_tests);
+            let memory_before = get_memory_usage();
+            let cache_before = hash_cache_stats();
+            let heap_before = alloc_stats();
+            reset_peak_heap();
+
+            let total_start = Instant::now();
+
+            for modified_html in &modified_versions {
+                let start = Instant::now();
+                // Use black_box to prevent compiler optimizations
+                black_box(pipeline.run(black_box(base_html), black_box(modified_html), chunk_size));
+                times.push(start.elapsed().as_nanos());
+            }
+
+            let total_elapsed = total_start.elapsed();
+            let heap_after = alloc_stats();
+            let comparisons = num_tests.max(1) as f64;
+            let total_duration_ms = total_elapsed.as_millis();
+            let total_duration_us = total_elapsed.as_micros();
+            let total_duration_ns = total_elapsed.as_nanos();
+            let memory_after = get_memory_usage();
+            let hash_cache = hash_cache_stats().zip(cache_before).map(|(after, before)| CacheStats {
+                hits: after.hits - before.hits,
+                misses: after.misses - before.misses,
+                evictions: after.evictions - before.evictions,
+                ..after
+            });
+
+            let avg_time_ns = times.iter().sum::<u128>() as f64 / comparisons;
+            let min_time_ns = times.iter().min().copied().unwrap_or(0);
+            let max_time_ns = times.iter().max().copied().unwrap_or(0);
+
+            // Calculate averages for all time units
+            let avg_time_us = avg_time_ns / 1000.0;
+            let avg_time_ms = avg_time_us / 1000.0;
+            let min_time_us = min_time_ns / 1000;
+            let min_time_ms = min_time_us / 1000;
+            let max_time_us = max_time_ns / 1000;
+            let max_time_ms = max_time_us / 1000;
+
+            let throughput = if total_duration_ns > 0 {
+                (num_tests as f64 * 1e9) / total_duration_ns as f64
+            } else {
+                0.0
+            };
+
+            benchmark_results.push(BenchmarkResult {
+                method: method_name.to_string(),
+                chunk_size,
+                num_tests,
+                avg_time_ms,
+                avg_time_us,
+                avg_time_ns,
+                min_time_ms,
+                min_time_us,
+                min_time_ns,
+                max_time_ms,
+                max_time_us,
+                max_time_ns,
+                total_time_ms: total_duration_ms,
+                total_time_us: total_duration_us,
+                total_time_ns: total_duration_ns,
+                memory_usage_bytes: memory_after.saturating_sub(memory_before),
+                allocations_per_comparison: (heap_after.allocations - heap_before.allocations) as f64 / comparisons,
+                allocated_bytes_per_comparison: (heap_after.allocated_bytes - heap_before.allocated_bytes) as f64
+                    / comparisons,
+                peak_heap_bytes: heap_after.peak_bytes.saturating_sub(heap_before.live_bytes),
+                throughput_comparisons_per_sec: throughput,
+                hash_cache,
+            });
+        }
+    }
+
+    // Create result directory if it doesn't exist
+    fs::create_dir_all("result").unwrap_or_else(|_| {
+        eprintln!("Error: Could not create result directory");
+        std::process::exit(1);
+    });
+
+    // Generate timestamp
+    let now: DateTime<Utc> = Utc::now();
+    let timestamp = now.format("%Y%m%d_%H%M%S").to_string();
+    let filename = format!("result/benchmark-{}.json", timestamp);
+
+    // Save benchmark results
+    let json_output = serde_json::to_string_pretty(&benchmark_results).expect("Failed to serialize benchmark results");
+    fs::write(&filename, &json_output).unwrap_or_else(|_| {
+        eprintln!("Error: Could not write benchmark file {}", filename);
+        std::process::exit(1);
+    });
+
+    // Print summary
+    println!("\n=== BENCHMARK RESULTS ===");
+    println!("Benchmark results saved to: {}", filename);
+    println!();
+
+    for result in &benchmark_results {
+        println!("Method: {} (chunk size: {})", result.method, result.chunk_size);
+
+        // Choose best unit for display based on timing
+        if result.avg_time_ms >= 1.0 {
+            println!("  Average time: {:.3} ms ({:.1} μs, {:.0} ns)", result.avg_time_ms, result.avg_time_us, result.avg_time_ns);
+            println!("  Min/Max time: {} ms / {} ms", result.min_time_ms, result.max_time_ms);
+        } else if result.avg_time_us >= 1.0 {
+            println!("  Average time: {:.3} μs ({:.3} ms, {:.0} ns)", result.avg_time_us, result.avg_time_ms, result.avg_time_ns);
+            println!("  Min/Max time: {} μs / {} μs", result.min_time_us, result.max_time_us);
+        } else {
+            println!("  Average time: {:.0} ns ({:.3} μs, {:.3} ms)", result.avg_time_ns, result.avg_time_us, result.avg_time_ms);
+            println!("  Min/Max time: {} ns / {} ns", result.min_time_ns, result.max_time_ns);
+        }
+
+        println!("  Total time: {} ms ({} μs, {} ns)", result.total_time_ms, result.total_time_us, result.total_time_ns);
+        println!("  Memory usage: {} bytes ({:.2} KB)", result.memory_usage_bytes, result.memory_usage_bytes as f64 / 1024.0);
+        println!(
+            "  Heap: {:.0} allocations ({:.2} KB) per comparison, peak {:.2} KB",
+            result.allocations_per_comparison,
+            result.allocated_bytes_per_comparison / 1024.0,
+            result.peak_heap_bytes as f64 / 1024.0
+        );
+        println!("  Throughput: {:.2} comparisons/sec", result.throughput_comparisons_per_sec);
+        if let Some(cache) = &result.hash_cache {
+            println!("  Hash cache: {} hits, {} misses ({:.1}% hit rate)", cache.hits, cache.misses, cache.hit_rate() * 100.0);
+        }
+        println!();
+    }
+
+    // Performance comparison
+    println!("=== PERFORMANCE COMPARISON ===");
+    for chunk_size in chunk_sizes {
+        let lite_result = benchmark_results.iter().find(|r| r.method == "merkle_lite" && r.chunk_size == chunk_size);
+        let tree_result = benchmark_results.iter().find(|r| r.method == "merkle_tree" && r.chunk_size == chunk_size);
+
+        if let (Some(lite), Some(tree)) = (lite_result, tree_result) {
+            let speed_ratio = if tree.avg_time_ms > 0.0 {
+                lite.avg_time_ms / tree.avg_time_ms
+            } else {
+                1.0
+            };
+            let winner = if lite.avg_time_ms < tree.avg_time_ms { "Merkle Lite" } else { "Merkle Tree" };
+
+            println!("Chunk Size {}: {} is {:.2}x faster", chunk_size, winner, speed_ratio.max(1.0 / speed_ratio));
+
+            // Show timing in most appropriate unit
+            if lite.avg_time_ms >= 1.0 {
+                println!("  Merkle Lite: {:.3} ms avg, {:.2} comparisons/sec", lite.avg_time_ms, lite.throughput_comparisons_per_sec);
+                println!("  Merkle Tree: {:.3} ms avg, {:.2} comparisons/sec", tree.avg_time_ms, tree.throughput_comparisons_per_sec);
+            } else {
+                println!("  Merkle Lite: {:.1} μs avg, {:.2} comparisons/sec", lite.avg_time_us, lite.throughput_comparisons_per_sec);
+                println!("  Merkle Tree: {:.1} μs avg, {:.2} comparisons/sec", tree.avg_time_us, tree.throughput_comparisons_per_sec);
+            }
+            if let Some(spans) = benchmark_results.iter().find(|r| r.method == "merkle_lite_spans" && r.chunk_size == chunk_size) {
+                let speedup = if spans.avg_time_ns > 0.0 { lite.avg_time_ns / spans.avg_time_ns } else { 1.0 };
+                println!(
+                    "  Token spans: {:.2}x the speed of Merkle Lite, {:.0} vs {:.0} allocations per comparison",
+                    speedup, spans.allocations_per_comparison, lite.allocations_per_comparison
+                );
+            }
+            println!();
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::Serialize;
+use xxhash_rust::xxh3::xxh3_128;
+
+use crate::hashing::HashAlgorithm;
+
+// Bounded LRU cache of chunk hashes, split into independently locked shards
+// so rayon workers rarely wait on each other. Entries are found by the
+// algorithm and a 128-bit xxh3 digest of the chunk, and a hit also compares
+// the stored chunk: xxh3 collisions can be crafted, and must never hand out
+// another chunk's SHA-256 or BLAKE3 digest.
+
+const SHARDS: usize = 16;
+const NIL: usize = usize::MAX;
+
+type CacheKey = (HashAlgorithm, u128);
+
+// Counters since the cache was created
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 { 0.0 } else { self.hits as f64 / lookups as f64 }
+    }
+}
+
+struct Entry {
+    key: CacheKey,
+    chunk: Box<str>,
+    value: String,
+    prev: usize,                   // towards the most recently used
+    next: usize,                   // towards the least recently used
+}
+
+// One LRU list, threaded through a slab of entries by index
+struct Shard {
+    index: HashMap<CacheKey, usize>,
+    entries: Vec<Entry>,
+    head: usize,
+    tail: usize,
+    capacity: usize,
+}
+
+impl Shard {
+    fn new(capacity: usize) -> Self {
+        Shard {
+            index: HashMap::with_capacity(capacity),
+            entries: Vec::with_capacity(capacity),
+            head: NIL,
+            tail: NIL,
+            capacity,
+        }
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.entries[i].prev, self.entries[i].next);
+        match prev {
+            NIL => self.head = next,
+            p => self.entries[p].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            n => self.entries[n].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.entries[i].prev = NIL;
+        self.entries[i].next = self.head;
+        match self.head {
+            NIL => self.tail = i,
+            h => self.entries[h].prev = i,
+        }
+        self.head = i;
+    }
+
+    // Value cached for `chunk`; an entry under the same key for another chunk is a miss
+    fn get(&mut self, key: &CacheKey, chunk: &str) -> Option<String> {
+        let i = *self.index.get(key)?;
+        if *self.entries[i].chunk != *chunk {
+            return None;
+        }
+        self.unlink(i);
+        self.push_front(i);
+        Some(self.entries[i].value.clone())
+    }
+
+    // Insert or refresh `key`; true if another entry was evicted for it
+    fn insert(&mut self, key: CacheKey, chunk: &str, value: String) -> bool {
+        if let Some(&i) = self.index.get(&key) {
+            if *self.entries[i].chunk != *chunk {
+                self.entries[i].chunk = chunk.into();
+            }
+            self.entries[i].value = value;
+            self.unlink(i);
+            self.push_front(i);
+            return false;
+        }
+        if self.entries.len() < self.capacity {
+            self.entries.push(Entry { key, chunk: chunk.into(), value, prev: NIL, next: NIL });
+            let i = self.entries.len() - 1;
+            self.index.insert(key, i);
+            self.push_front(i);
+            return false;
+        }
+        // Reuse the least recently used slot
+        let i = self.tail;
+        self.unlink(i);
+        self.index.remove(&self.entries[i].key);
+        self.entries[i].key = key;
+        self.entries[i].chunk = chunk.into();
+        self.entries[i].value = value;
+        self.index.insert(key, i);
+        self.push_front(i);
+        true
+    }
+}
+
+pub struct HashCache {
+    shards: Vec<Mutex<Shard>>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl HashCache {
+    // A cache holding about `capacity` hashes (at least one per shard)
+    pub fn new(capacity: usize) -> Self {
+        let per_shard = capacity.div_ceil(SHARDS).max(1);
+        HashCache {
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard::new(per_shard))).collect(),
+            capacity: per_shard * SHARDS,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    // Cached hash of `chunk`, computing and storing it on a miss
+    pub fn get_or_insert_with<F>(&self, algorithm: HashAlgorithm, chunk: &str, compute: F) -> String
+    where
+        F: FnOnce() -> String,
+    {
+        let key = (algorithm, xxh3_128(chunk.as_bytes()));
+        let shard = &self.shards[key.1 as usize % SHARDS];
+
+        if let Some(hash) = shard.lock().unwrap().get(&key, chunk) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return hash;
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // Hash outside the lock; a concurrent miss on the same chunk just stores it twice
+        let hash = compute();
+        if shard.lock().unwrap().insert(key, chunk, hash.clone()) {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        hash
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: self.shards.iter().map(|s| s.lock().unwrap().entries.len()).sum(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u128) -> CacheKey {
+        (HashAlgorithm::Xxh3_64, n)
+    }
+
+    // Keys from most to least recently used
+    fn order(shard: &Shard) -> Vec<u128> {
+        let mut keys = Vec::new();
+        let mut i = shard.head;
+        while i != NIL {
+            keys.push(shard.entries[i].key.1);
+            i = shard.entries[i].next;
+        }
+        keys
+    }
+
+    #[test]
+    fn shard_evicts_the_least_recently_used_entry() {
+        let mut shard = Shard::new(3);
+        for n in 1..=3 {
+            assert!(!shard.insert(key(n), &n.to_string(), n.to_string()));
+        }
+        assert_eq!(order(&shard), [3, 2, 1]);
+
+        assert_eq!(shard.get(&key(1), "1").as_deref(), Some("1"));
+        assert_eq!(order(&shard), [1, 3, 2]);
+
+        assert!(shard.insert(key(4), "4", "4".to_string()));
+        assert_eq!(order(&shard), [4, 1, 3]);
+        assert_eq!(shard.get(&key(2), "2"), None);
+
+        // Refreshing an entry moves it to the front without evicting
+        assert!(!shard.insert(key(3), "3", "three".to_string()));
+        assert_eq!(order(&shard), [3, 4, 1]);
+        assert!(shard.insert(key(5), "5", "5".to_string()));
+        assert_eq!(order(&shard), [5, 3, 4]);
+        assert_eq!(shard.get(&key(3), "3").as_deref(), Some("three"));
+        assert_eq!(shard.index.len(), 3);
+    }
+
+    #[test]
+    fn cache_counts_hits_misses_and_evictions() {
+        // Two entries per shard; pick three chunks that share one
+        let cache = HashCache::new(2 * SHARDS);
+        let shard_of = |chunk: &str| xxh3_128(chunk.as_bytes()) as usize % SHARDS;
+        let target = shard_of("chunk 0");
+        let chunks: Vec<String> = (0..).map(|i| format!("chunk {}", i)).filter(|c| shard_of(c) == target).take(3).collect();
+        let mut computed = 0;
+        let mut lookup = |chunk: &str| {
+            cache.get_or_insert_with(HashAlgorithm::Xxh3_64, chunk, || {
+                computed += 1;
+                format!("hash of {}", chunk)
+            })
+        };
+
+        assert_eq!(lookup(&chunks[0]), "hash of chunk 0");
+        lookup(&chunks[1]);
+        lookup(&chunks[0]);            // hit, chunks[1] is now least recently used
+        lookup(&chunks[2]);            // evicts chunks[1]
+        lookup(&chunks[0]);            // hit
+        lookup(&chunks[1]);            // miss again, evicts chunks[2]
+        assert_eq!(computed, 4);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 4, 2));
+        assert_eq!((stats.entries, stats.capacity), (2, 2 * SHARDS));
+        assert!((stats.hit_rate() - 2.0 / 6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn algorithms_are_cached_apart() {
+        let cache = HashCache::new(64);
+        cache.get_or_insert_with(HashAlgorithm::Xxh3_64, "same", || "a".to_string());
+        let other = cache.get_or_insert_with(HashAlgorithm::Sha256, "same", || "b".to_string());
+        assert_eq!(other, "b");
+        assert_eq!(cache.stats().misses, 2);
+    }
+
+    #[test]
+    fn colliding_digests_do_not_share_a_hash() {
+        // Two chunks under one key, as with a crafted xxh3_128 collision
+        let mut shard = Shard::new(2);
+        shard.insert(key(7), "<p>genuine</p>", "genuine digest".to_string());
+        assert_eq!(shard.get(&key(7), "<p>forged</p>"), None);
+        assert_eq!(shard.get(&key(7), "<p>genuine</p>").as_deref(), Some("genuine digest"));
+
+        // The newer chunk takes over the slot, and the old one is a miss from then on
+        assert!(!shard.insert(key(7), "<p>forged</p>", "forged digest".to_string()));
+        assert_eq!(shard.get(&key(7), "<p>forged</p>").as_deref(), Some("forged digest"));
+        assert_eq!(shard.get(&key(7), "<p>genuine</p>"), None);
+        assert_eq!(shard.entries.len(), 1);
+    }
+}
@@ -0,0 +1,534 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::ignore::MASK;
+use crate::normalize::TokenWithLine;
+use crate::tokenizer::{closes_implicitly, is_void_element};
+
+// How normalized tokens are grouped into chunks.
+//
+// Fixed-size chunks cut after every `n` tokens, so one inserted token shifts
+// every later boundary and all later chunks hash differently. Content-defined
+// chunks cut where a rolling hash over the last `ROLLING_WINDOW` tokens hits
+// a pattern, so boundaries depend only on nearby content: after an insertion
+// the boundaries fall back into step within a window (plus the minimum size),
+// and only the chunks around it change. The pattern is hit once every
+// `avg - min + 1` tokens on average, after the first `min` tokens of a chunk,
+// and a chunk is cut at `max` tokens regardless.
+//
+// The rolling hash runs over xxh3 fingerprints of the normalized tokens, so
+// boundaries do not depend on the chunk hash algorithm.
+//
+// Element chunks follow the document structure instead: each chunk is one
+// complete element subtree (`<header>`, `<nav>`, a card), so no chunk ends
+// halfway through an element. The elements chosen are the outermost ones at
+// least `depth` levels deep (top-level elements are at depth 0) with at most
+// `max_tokens` tokens. Tokens outside such an element, i.e. the tags of
+// shallower or larger elements and text directly inside them, are chunks of
+// their own. Open elements are tracked like the tokenizer's tree builder does
+// (implied end tags, stray end tags ignored).
+
+pub const ROLLING_WINDOW: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ContentDefined {
+    pub min: usize,
+    pub avg: usize,
+    pub max: usize,
+}
+
+impl ContentDefined {
+    pub fn new(min: usize, avg: usize, max: usize) -> Result<Self, String> {
+        if min == 0 || min > avg || avg > max {
+            return Err(format!(
+                "content-defined chunk sizes must satisfy 1 <= min <= avg <= max (got {},{},{})",
+                min, avg, max
+            ));
+        }
+        Ok(ContentDefined { min, avg, max })
+    }
+
+    // Sizes from a quarter to four times `avg`
+    pub fn around(avg: usize) -> Self {
+        let avg = avg.max(1);
+        ContentDefined { min: avg.div_ceil(4), avg, max: avg * 4 }
+    }
+}
+
+// `avg` alone, or `min,avg,max`
+impl FromStr for ContentDefined {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sizes: Vec<usize> = s
+            .split(',')
+            .map(|n| n.trim().parse().map_err(|_| format!("invalid chunk size '{}'", n.trim())))
+            .collect::<Result<_, _>>()?;
+        match sizes[..] {
+            [avg] if avg > 0 => Ok(ContentDefined::around(avg)),
+            [min, avg, max] => ContentDefined::new(min, avg, max),
+            _ => Err(format!("expected <avg> or <min,avg,max> chunk sizes, got '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ElementChunks {
+    pub depth: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>, // token budget of one subtree chunk
+}
+
+// Serialized as the plain chunk size, `{"min", "avg", "max"}` or `{"depth", "max_tokens"}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Chunking {
+    Fixed(usize),                  // tokens per chunk
+    ContentDefined(ContentDefined),
+    Elements(ElementChunks),
+}
+
+impl Default for Chunking {
+    fn default() -> Self {
+        Chunking::Fixed(1)
+    }
+}
+
+impl From<usize> for Chunking {
+    fn from(chunk_size: usize) -> Self {
+        Chunking::Fixed(chunk_size.max(1))
+    }
+}
+
+impl From<ContentDefined> for Chunking {
+    fn from(sizes: ContentDefined) -> Self {
+        Chunking::ContentDefined(sizes)
+    }
+}
+
+impl From<ElementChunks> for Chunking {
+    fn from(elements: ElementChunks) -> Self {
+        Chunking::Elements(elements)
+    }
+}
+
+impl fmt::Display for Chunking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chunking::Fixed(size) => write!(f, "chunk size: {}", size),
+            Chunking::ContentDefined(c) => write!(f, "content-defined chunks: {}-{}, avg {}", c.min, c.max, c.avg),
+            Chunking::Elements(e) => match e.max_tokens {
+                Some(max) => write!(f, "element chunks: depth {}, up to {} tokens", e.depth, max),
+                None => write!(f, "element chunks: depth {}", e.depth),
+            },
+        }
+    }
+}
+
+impl Chunking {
+    pub fn chunker(&self) -> Chunker {
+        Chunker {
+            chunking: match *self {
+                Chunking::Fixed(size) => Chunking::Fixed(size.max(1)),
+                Chunking::Elements(e) => Chunking::Elements(ElementChunks {
+                    max_tokens: e.max_tokens.map(|max| max.max(1)),
+                    ..e
+                }),
+                content_defined => content_defined,
+            },
+            pending: 0,
+            window: [0; ROLLING_WINDOW],
+            next: 0,
+            rolling: 0,
+            seen: 0,
+            open: Vec::new(),
+            completed: Vec::new(),
+        }
+    }
+
+    // Token count of a fixed chunk, if chunks are fixed-size
+    pub fn fixed_size(&self) -> Option<usize> {
+        match *self {
+            Chunking::Fixed(size) => Some(size.max(1)),
+            _ => None,
+        }
+    }
+}
+
+// An element open at the current token. Subtrees still in the running to
+// become a chunk are pending: their tokens are not assigned yet.
+#[derive(Debug, Clone)]
+struct OpenElement {
+    name: String,
+    pending: Option<PendingElement>,
+}
+
+#[derive(Debug, Clone)]
+struct PendingElement {
+    start: usize,                  // index of the start tag
+    items: Vec<usize>,             // token counts of the finished children, in order
+}
+
+// How a normalized token affects the open elements
+enum TagEffect<'t> {
+    Open(&'t str),
+    Close(&'t str),
+    Leaf(Option<&'t str>),         // start tags that cannot have content carry their name
+}
+
+fn tag_effect(token: &str) -> TagEffect<'_> {
+    let Some(tag) = token.strip_prefix("TAG:<") else {
+        return TagEffect::Leaf(None);
+    };
+    if let Some(end) = tag.strip_prefix('/') {
+        return TagEffect::Close(end.trim_end_matches('>'));
+    }
+    if tag.starts_with(['!', '?']) {
+        return TagEffect::Leaf(None);
+    }
+    let name = &tag[..tag.find([' ', '>']).unwrap_or(tag.len())];
+    // A masked subtree is one `TAG:<name>*</name>` token
+    let masked = tag == format!("{}>{}</{}>", name, MASK, name);
+    if masked || tag.ends_with(" />") || is_void_element(name) {
+        TagEffect::Leaf(Some(name))
+    } else {
+        TagEffect::Open(name)
+    }
+}
+
+// Decides chunk boundaries one token at a time
+#[derive(Debug, Clone)]
+pub struct Chunker {
+    chunking: Chunking,
+    pending: usize,                // tokens not assigned to a chunk yet
+    window: [u64; ROLLING_WINDOW], // fingerprints of the last tokens, oldest at `next`
+    next: usize,
+    rolling: u64,                  // buzhash of `window`
+    seen: usize,                   // tokens pushed so far
+    open: Vec<OpenElement>,        // pending elements are a suffix of the stack
+    completed: Vec<usize>,
+}
+
+impl Chunker {
+    // Add the next token. Returns the token counts of the chunks completed by
+    // it, in order, starting with the oldest token not in a chunk yet.
+    pub fn push(&mut self, token: &str) -> &[usize] {
+        self.completed.clear();
+        match self.chunking {
+            Chunking::Fixed(size) => {
+                self.pending += 1;
+                if self.pending == size {
+                    self.complete(size);
+                }
+            }
+            Chunking::ContentDefined(sizes) => {
+                self.pending += 1;
+                let fingerprint = xxh3_64(token.as_bytes());
+                let oldest = std::mem::replace(&mut self.window[self.next], fingerprint);
+                self.next = (self.next + 1) % ROLLING_WINDOW;
+                self.rolling =
+                    self.rolling.rotate_left(1) ^ oldest.rotate_left(ROLLING_WINDOW as u32) ^ fingerprint;
+                if self.pending >= sizes.max
+                    || (self.pending >= sizes.min && self.rolling.is_multiple_of((sizes.avg - sizes.min + 1) as u64))
+                {
+                    self.complete(self.pending);
+                }
+            }
+            Chunking::Elements(elements) => self.push_element_token(token, elements),
+        }
+        &self.completed
+    }
+
+    // Complete the chunks of all remaining tokens at the end of the document
+    pub fn finish(&mut self) -> &[usize] {
+        self.completed.clear();
+        if matches!(self.chunking, Chunking::Elements(_)) {
+            while !self.open.is_empty() {
+                self.close_top(false);
+            }
+        } else if self.pending > 0 {
+            self.complete(self.pending);
+        }
+        &self.completed
+    }
+
+    fn complete(&mut self, tokens: usize) {
+        self.completed.push(tokens);
+        self.pending -= tokens;
+    }
+
+    fn push_element_token(&mut self, token: &str, elements: ElementChunks) {
+        // Elements a token closes implicitly end before it
+        match tag_effect(token) {
+            TagEffect::Open(name) | TagEffect::Leaf(Some(name)) => {
+                while self.open.last().is_some_and(|top| closes_implicitly(&top.name, name)) {
+                    self.close_top(false);
+                }
+            }
+            TagEffect::Close(name) => {
+                if let Some(idx) = self.open.iter().rposition(|e| e.name == name) {
+                    while self.open.len() > idx + 1 {
+                        self.close_top(false);
+                    }
+                }
+            }
+            TagEffect::Leaf(None) => {}
+        }
+
+        self.seen += 1;
+        self.pending += 1;
+        self.split_over_budget(elements.max_tokens);
+
+        match tag_effect(token) {
+            TagEffect::Open(name) => {
+                let pending = (self.open.last().is_some_and(|top| top.pending.is_some())
+                    || self.open.len() >= elements.depth)
+                    .then(|| PendingElement { start: self.seen - 1, items: Vec::new() });
+                if pending.is_none() {
+                    self.complete(1);
+                }
+                self.open.push(OpenElement { name: name.to_string(), pending });
+            }
+            TagEffect::Close(name) if self.open.last().is_some_and(|top| top.name == name) => self.close_top(true),
+            _ => self.add_item(1),
+        }
+    }
+
+    // A finished child of the innermost open element
+    fn add_item(&mut self, tokens: usize) {
+        match self.open.last_mut().and_then(|top| top.pending.as_mut()) {
+            Some(parent) => parent.items.push(tokens),
+            None => self.complete(tokens),
+        }
+    }
+
+    fn close_top(&mut self, with_end_tag: bool) {
+        let Some(element) = self.open.pop() else { return };
+        match element.pending {
+            // Still within the budget, so the whole subtree is one chunk or item
+            Some(pending) => self.add_item(self.seen - pending.start),
+            None if with_end_tag => self.add_item(1),
+            None => {}
+        }
+    }
+
+    // Give up on the outermost pending subtrees that outgrew the budget: their
+    // start tags and finished children become chunks of their own
+    fn split_over_budget(&mut self, max_tokens: Option<usize>) {
+        let Some(max) = max_tokens else { return };
+        while let Some(bottom) = self.open.iter().position(|e| e.pending.is_some()) {
+            let pending = self.open[bottom].pending.as_ref().expect("pending element");
+            if self.seen - pending.start <= max {
+                break;
+            }
+            let pending = self.open[bottom].pending.take().expect("pending element");
+            self.complete(1);
+            for tokens in pending.items {
+                self.complete(tokens);
+            }
+        }
+    }
+}
+
+// Pending tokens joined with `|`, cut into chunk texts as the chunker decides
+#[derive(Debug, Clone)]
+pub struct ChunkJoiner {
+    chunker: Chunker,
+    text: String,
+    ends: Vec<usize>,              // end of each pending token in `text`
+}
+
+impl ChunkJoiner {
+    pub fn new(chunking: Chunking) -> Self {
+        ChunkJoiner { chunker: chunking.chunker(), text: String::new(), ends: Vec::new() }
+    }
+
+    // Add the next token, calling `emit` with the text of every chunk it completes
+    pub fn push<F: FnMut(&str)>(&mut self, token: &str, emit: F) {
+        if !self.ends.is_empty() {
+            self.text.push('|');
+        }
+        self.text.push_str(token);
+        self.ends.push(self.text.len());
+        let completed = self.chunker.push(token);
+        cut_chunks(&mut self.text, &mut self.ends, completed, emit);
+    }
+
+    pub fn finish<F: FnMut(&str)>(mut self, emit: F) {
+        let completed = self.chunker.finish();
+        cut_chunks(&mut self.text, &mut self.ends, completed, emit);
+    }
+}
+
+fn cut_chunks<F: FnMut(&str)>(text: &mut String, ends: &mut Vec<usize>, completed: &[usize], mut emit: F) {
+    let (mut tokens, mut offset) = (0, 0);
+    for &count in completed {
+        tokens += count;
+        let end = ends[tokens - 1];
+        emit(&text[offset..end]);
+        offset = end + 1;
+    }
+    if tokens == ends.len() {
+        text.clear();
+        ends.clear();
+    } else if tokens > 0 {
+        text.drain(..offset);
+        ends.drain(..tokens);
+        for end in ends.iter_mut() {
+            *end -= offset;
+        }
+    }
+}
+
+// Token index range of every chunk of `tokens`
+pub fn chunk_ranges(tokens: &[TokenWithLine], chunking: Chunking) -> Vec<Range<usize>> {
+    if let Some(size) = chunking.fixed_size() {
+        return (0..tokens.len()).step_by(size).map(|start| start..(start + size).min(tokens.len())).collect();
+    }
+    let mut chunker = chunking.chunker();
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut cut = |completed: &[usize]| {
+        for &count in completed {
+            ranges.push(start..start + count);
+            start += count;
+        }
+    };
+    for token in tokens {
+        cut(chunker.push(&token.content));
+    }
+    cut(chunker.finish());
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::normalize_html_with_lines;
+
+    fn tokens(contents: &[String]) -> Vec<TokenWithLine> {
+        contents.iter().map(|c| TokenWithLine { content: c.clone(), line_number: 1 }).collect()
+    }
+
+    // Token index at which each chunk ends
+    fn boundaries(contents: &[String], chunking: Chunking) -> Vec<usize> {
+        chunk_ranges(&tokens(contents), chunking).into_iter().map(|r| r.end).collect()
+    }
+
+    fn words(count: usize) -> Vec<String> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..count)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                format!("TEXT:w{}", state % 1000)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn content_defined_chunks_respect_their_sizes() {
+        let sizes = ContentDefined::new(4, 16, 40).unwrap();
+        let ranges = chunk_ranges(&tokens(&words(5000)), sizes.into());
+        let (last, full) = ranges.split_last().unwrap();
+        assert!(full.iter().all(|r| (sizes.min..=sizes.max).contains(&r.len())));
+        assert!(last.len() <= sizes.max);
+        assert_eq!(last.end, 5000);
+        let average = 5000 / ranges.len();
+        assert!((8..=32).contains(&average), "average chunk of {average} tokens");
+    }
+
+    #[test]
+    fn content_defined_boundaries_survive_a_local_insert() {
+        let sizes = ContentDefined::new(4, 16, 64).unwrap();
+        let original = words(4000);
+        let mut edited = original.clone();
+        let at = 2000;
+        edited.insert(at, "TEXT:inserted".to_string());
+
+        let before = boundaries(&original, sizes.into());
+        let after = boundaries(&edited, sizes.into());
+        let resync = at + ROLLING_WINDOW + sizes.max;
+        let unchanged_prefix: Vec<_> = before.iter().filter(|&&b| b < at).collect();
+        assert_eq!(unchanged_prefix, after.iter().filter(|&&b| b < at).collect::<Vec<_>>());
+        let shifted_suffix: Vec<_> = before.iter().filter(|&&b| b > resync).map(|b| b + 1).collect();
+        assert_eq!(shifted_suffix, after.iter().filter(|&&b| b > resync + 1).copied().collect::<Vec<_>>());
+
+        // Fixed-size chunks, by contrast, all move
+        let fixed_before = boundaries(&original, Chunking::Fixed(16));
+        let fixed_after = boundaries(&edited, Chunking::Fixed(16));
+        assert!(fixed_before.iter().filter(|&&b| b > at && b < original.len()).all(|b| !fixed_after.contains(&(b + 1))));
+    }
+
+    // Token range of every element closed by an explicit end tag
+    fn element_ranges(contents: &[String]) -> Vec<Range<usize>> {
+        let mut open: Vec<(&str, usize)> = Vec::new();
+        let mut ranges = Vec::new();
+        for (i, token) in contents.iter().enumerate() {
+            match tag_effect(token) {
+                TagEffect::Open(name) => open.push((name, i)),
+                TagEffect::Close(name) => {
+                    if let Some(idx) = open.iter().rposition(|(n, _)| *n == name) {
+                        ranges.push(open[idx].1..i + 1);
+                        open.truncate(idx);
+                    }
+                }
+                TagEffect::Leaf(_) => {}
+            }
+        }
+        ranges
+    }
+
+    fn assert_elements_whole(html: &str, elements: ElementChunks) {
+        let (contents, _) = normalize_html_with_lines(html, 1);
+        let chunks = chunk_ranges(&tokens(&contents), elements.into());
+        assert_eq!(chunks.iter().map(|c| c.len()).sum::<usize>(), contents.len());
+        for element in element_ranges(&contents) {
+            for chunk in &chunks {
+                let disjoint = chunk.end <= element.start || element.end <= chunk.start;
+                let inside = element.start <= chunk.start && chunk.end <= element.end;
+                let contains = chunk.start <= element.start && element.end <= chunk.end;
+                assert!(
+                    disjoint || inside || contains,
+                    "chunk {chunk:?} splits element {element:?} ({elements:?})"
+                );
+            }
+        }
+        for chunk in chunks.iter().filter(|c| c.len() > 1) {
+            assert!(matches!(tag_effect(&contents[chunk.start]), TagEffect::Open(_)), "{chunk:?} does not start an element");
+            if let Some(max) = elements.max_tokens {
+                assert!(chunk.len() <= max, "{chunk:?} is over the {max}-token budget");
+            }
+        }
+    }
+
+    #[test]
+    fn element_chunks_never_split_an_element() {
+        let html = "<html><body><header><h1>Title</h1><nav><a href=\"/\">Home</a> <a href=\"/b\">B</a></nav></header>\
+                    <main><article><h2>One</h2><p>text <b>bold</b> more</p></article>\
+                    <article><h2>Two</h2><ul><li>a</li><li>b</li><li>c</li></ul><br></article></main>\
+                    <footer>end</footer></body></html>";
+        for depth in 0..6 {
+            for max_tokens in [None, Some(1), Some(3), Some(8), Some(20)] {
+                assert_elements_whole(html, ElementChunks { depth, max_tokens });
+            }
+        }
+    }
+
+    #[test]
+    fn element_chunks_of_the_snapshots_are_whole_elements() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots");
+        for version in ["v1", "v2", "v3", "v4", "v5", "v10"] {
+            let html = std::fs::read_to_string(dir.join(format!("{version}.html"))).unwrap();
+            for depth in [0, 2, 4] {
+                for max_tokens in [None, Some(16)] {
+                    assert_elements_whole(&html, ElementChunks { depth, max_tokens });
+                }
+            }
+        }
+    }
+}
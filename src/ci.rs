@@ -0,0 +1,347 @@
+use std::str::FromStr;
+use serde_json::{Value, json};
+
+use crate::diff::{ComparisonResult, LineDiff, parse_line_range};
+use crate::report::escape_html;
+
+// Machine-readable results for CI pipelines. Every `LineDiff` left after
+// `--ignore` masking counts as an unexpected change:
+//
+//   - JUnit XML: one test suite per compared file, one failed test case per
+//     line change (a file without changes gets a single passing case), with
+//     the `file` and `line` attributes GitLab and Jenkins use for annotations
+//   - SARIF 2.1.0: one result per line change, located at its file and lines,
+//     which GitHub code scanning shows inline
+//
+// Added and modified lines are located in the new version of a file, removed
+// lines in the old one.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Junit,
+    Sarif,
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Junit => "junit",
+            OutputFormat::Sarif => "sarif",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "junit" => Ok(OutputFormat::Junit),
+            "sarif" => Ok(OutputFormat::Sarif),
+            _ => Err(format!("unknown format '{}' (expected junit or sarif)", s)),
+        }
+    }
+}
+
+// One compared file, by the paths annotations should point at
+#[derive(Debug, Clone, Copy)]
+pub struct FileComparison<'a> {
+    pub old_path: &'a str,
+    pub new_path: &'a str,
+    pub result: &'a ComparisonResult,
+}
+
+// Where a line change is: file and first and last line
+fn location<'a>(file: &FileComparison<'a>, diff: &LineDiff) -> (&'a str, Option<(usize, usize)>) {
+    match diff.change_type.as_str() {
+        "removed" => (file.old_path, parse_line_range(&diff.line_range)),
+        "added" => (file.new_path, parse_line_range(&diff.line_range)),
+        _ => (file.new_path, parse_line_range(diff.new_line_range.as_deref().unwrap_or(&diff.line_range))),
+    }
+}
+
+fn describe(diff: &LineDiff) -> String {
+    match (&diff.old_line_range, &diff.new_line_range) {
+        (Some(old), Some(new)) => format!("DOM content modified ({} -> {}): {}", old, new, diff.content_preview),
+        _ => format!("DOM content {} ({}): {}", diff.change_type, diff.line_range, diff.content_preview),
+    }
+}
+
+// Escaped for XML text and attributes, without the control characters XML 1.0 forbids
+fn xml(text: &str) -> String {
+    let allowed: String = text.chars().filter(|&c| !c.is_control() || matches!(c, '\t' | '\n' | '\r')).collect();
+    escape_html(&allowed)
+}
+
+pub fn junit_xml(files: &[FileComparison]) -> String {
+    let failures: usize = files.iter().map(|f| f.result.line_diffs.len()).sum();
+    let tests: usize = files.iter().map(|f| f.result.line_diffs.len().max(1)).sum();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!("<testsuites name=\"merkle-domdiff\" tests=\"{}\" failures=\"{}\">\n", tests, failures));
+
+    for file in files {
+        let result = file.result;
+        out.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+            xml(&format!("{} vs {}", file.old_path, file.new_path)),
+            result.line_diffs.len().max(1),
+            result.line_diffs.len(),
+            result.processing_time_us as f64 / 1_000_000.0
+        ));
+        out.push_str("    <properties>\n");
+        for (name, value) in [
+            ("difference_percent", format!("{:.2}", result.difference_percent)),
+            ("method", result.method.clone()),
+            ("hash_algorithm", result.hash_algorithm.clone()),
+            ("common_chunks", result.common_chunks.to_string()),
+            ("different_chunks", result.different_chunks.to_string()),
+        ] {
+            out.push_str(&format!("      <property name=\"{}\" value=\"{}\"/>\n", name, xml(&value)));
+        }
+        out.push_str("    </properties>\n");
+
+        if result.line_diffs.is_empty() {
+            out.push_str(&format!(
+                "    <testcase name=\"DOM unchanged\" classname=\"{}\" file=\"{}\"/>\n",
+                xml(file.new_path), xml(file.new_path)
+            ));
+        }
+        for diff in &result.line_diffs {
+            let (path, lines) = location(file, diff);
+            let line = lines.map(|(start, _)| format!(" line=\"{}\"", start)).unwrap_or_default();
+            out.push_str(&format!(
+                "    <testcase name=\"{} {}\" classname=\"{}\" file=\"{}\"{}>\n",
+                xml(&diff.change_type), xml(&diff.line_range), xml(path), xml(path), line
+            ));
+            out.push_str(&format!(
+                "      <failure type=\"{}\" message=\"{}\">{}</failure>\n",
+                xml(&diff.change_type), xml(&diff.content_preview), xml(&describe(diff))
+            ));
+            out.push_str("    </testcase>\n");
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    out
+}
+
+// A relative URI reference for `path`: everything but `/` and the unreserved
+// characters is percent-encoded, byte by byte in UTF-8
+fn uri(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => out.push(byte as char),
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+fn sarif_location(path: &str, lines: Option<(usize, usize)>) -> Value {
+    let mut location = json!({ "artifactLocation": { "uri": uri(path) } });
+    if let Some((start, end)) = lines {
+        location["region"] = json!({ "startLine": start.max(1), "endLine": end.max(start).max(1) });
+    }
+    json!({ "physicalLocation": location })
+}
+
+pub fn sarif_json(files: &[FileComparison]) -> String {
+    let rules = [
+        ("dom-added", "DOM content added"),
+        ("dom-removed", "DOM content removed"),
+        ("dom-modified", "DOM content modified"),
+    ];
+    let mut results = Vec::new();
+    for file in files {
+        for diff in &file.result.line_diffs {
+            let (path, lines) = location(file, diff);
+            let mut result = json!({
+                "ruleId": format!("dom-{}", diff.change_type),
+                "level": "error",
+                "message": { "text": describe(diff) },
+                "locations": [sarif_location(path, lines)],
+                "properties": { "difference_percent": file.result.difference_percent },
+            });
+            // The old lines of a modification
+            if let Some(old) = diff.old_line_range.as_deref() {
+                let mut related = sarif_location(file.old_path, parse_line_range(old));
+                related["message"] = json!({ "text": "before the change" });
+                result["relatedLocations"] = json!([related]);
+            }
+            results.push(result);
+        }
+    }
+
+    let sarif = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "merkle-domdiff",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules
+                        .iter()
+                        .map(|(id, text)| json!({ "id": id, "shortDescription": { "text": text } }))
+                        .collect::<Vec<_>>(),
+                }
+            },
+            "results": results,
+        }]
+    });
+    serde_json::to_string_pretty(&sarif).expect("Failed to serialize to JSON")
+}
+
+pub fn render(format: OutputFormat, files: &[FileComparison]) -> String {
+    match format {
+        OutputFormat::Junit => junit_xml(files),
+        OutputFormat::Sarif => sarif_json(files) + "\n",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line_diff(change_type: &str, line_range: &str, preview: &str) -> LineDiff {
+        LineDiff {
+            line_range: line_range.to_string(),
+            change_type: change_type.to_string(),
+            content_preview: preview.to_string(),
+            old_line_range: None,
+            new_line_range: None,
+            old_content: None,
+            new_content: None,
+        }
+    }
+
+    fn modified(old: &str, new: &str, preview: &str) -> LineDiff {
+        LineDiff {
+            old_line_range: Some(old.to_string()),
+            new_line_range: Some(new.to_string()),
+            ..line_diff("modified", new, preview)
+        }
+    }
+
+    fn result(line_diffs: Vec<LineDiff>) -> ComparisonResult {
+        ComparisonResult {
+            version_a: "a".to_string(),
+            version_b: "b".to_string(),
+            difference_percent: if line_diffs.is_empty() { 0.0 } else { 50.0 },
+            total_chunks_a: 4,
+            total_chunks_b: 4,
+            common_chunks: 2,
+            different_chunks: 2,
+            method: "merkle_lite".to_string(),
+            hash_algorithm: "xxh3_64".to_string(),
+            processing_time_ms: 1,
+            processing_time_us: 1500,
+            processing_time_ns: 1_500_000,
+            line_diffs,
+            subtree_changes: Vec::new(),
+            sequence_edits: Vec::new(),
+            edit_script: Vec::new(),
+            sketch: None,
+        }
+    }
+
+    #[test]
+    fn junit_counts_and_locates_every_line_change() {
+        let changed = result(vec![
+            line_diff("added", "L8", "+ TEXT:c"),
+            line_diff("removed", "L3-L4", "- TEXT:b"),
+            modified("L5", "L6", "~ TEXT:x -> TEXT:y"),
+        ]);
+        let unchanged = result(Vec::new());
+        let xml = junit_xml(&[
+            FileComparison { old_path: "old/a.html", new_path: "new/a.html", result: &changed },
+            FileComparison { old_path: "old/b.html", new_path: "new/b.html", result: &unchanged },
+        ]);
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(xml.contains("<testsuites name=\"merkle-domdiff\" tests=\"4\" failures=\"3\">"));
+        assert!(xml.contains("<testsuite name=\"old/a.html vs new/a.html\" tests=\"3\" failures=\"3\" time=\"0.002\">"));
+        assert!(xml.contains("<testcase name=\"added L8\" classname=\"new/a.html\" file=\"new/a.html\" line=\"8\">"));
+        assert!(xml.contains("<testcase name=\"removed L3-L4\" classname=\"old/a.html\" file=\"old/a.html\" line=\"3\">"));
+        assert!(xml.contains("<testcase name=\"modified L6\" classname=\"new/a.html\" file=\"new/a.html\" line=\"6\">"));
+        assert!(xml.contains("DOM content modified (L5 -&gt; L6)"));
+        assert_eq!(xml.matches("<failure ").count(), 3);
+
+        // The unchanged file is a single passing case
+        assert!(xml.contains("<testsuite name=\"old/b.html vs new/b.html\" tests=\"1\" failures=\"0\""));
+        assert!(xml.contains("<testcase name=\"DOM unchanged\" classname=\"new/b.html\" file=\"new/b.html\"/>"));
+        assert!(xml.contains("<property name=\"difference_percent\" value=\"0.00\"/>"));
+        assert!(xml.ends_with("</testsuites>\n"));
+    }
+
+    #[test]
+    fn junit_escapes_markup_and_drops_control_characters() {
+        let changed = result(vec![line_diff("added", "L1", "+ TEXT:a < b & \"c\"\u{1}\u{1b}[0m\tend")]);
+        let xml = junit_xml(&[FileComparison { old_path: "a&b.html", new_path: "<x>.html", result: &changed }]);
+
+        assert!(xml.contains("message=\"+ TEXT:a &lt; b &amp; &quot;c&quot;[0m\tend\""));
+        assert!(xml.contains("file=\"&lt;x&gt;.html\""));
+        assert!(xml.contains("name=\"a&amp;b.html vs &lt;x&gt;.html\""));
+        assert!(!xml.contains('\u{1}') && !xml.contains('\u{1b}'));
+        assert!(!xml.contains("<x>") && !xml.contains("a&b"));
+    }
+
+    #[test]
+    fn sarif_locates_removed_lines_in_the_old_file() {
+        let changed = result(vec![
+            line_diff("added", "L8", "+ TEXT:c"),
+            line_diff("removed", "L3-L4", "- TEXT:b"),
+            modified("L5", "L6-L7", "~ TEXT:x -> TEXT:y"),
+        ]);
+        let sarif = sarif_json(&[FileComparison { old_path: "old/a.html", new_path: "new/a.html", result: &changed }]);
+        let sarif: Value = serde_json::from_str(&sarif).unwrap();
+
+        assert_eq!(sarif["version"], "2.1.0");
+        let run = &sarif["runs"][0];
+        let rules: Vec<&str> = run["tool"]["driver"]["rules"].as_array().unwrap().iter().map(|r| r["id"].as_str().unwrap()).collect();
+        assert_eq!(rules, ["dom-added", "dom-removed", "dom-modified"]);
+
+        let results = run["results"].as_array().unwrap();
+        let located: Vec<(&str, &str, u64, u64)> = results
+            .iter()
+            .map(|r| {
+                let location = &r["locations"][0]["physicalLocation"];
+                (
+                    r["ruleId"].as_str().unwrap(),
+                    location["artifactLocation"]["uri"].as_str().unwrap(),
+                    location["region"]["startLine"].as_u64().unwrap(),
+                    location["region"]["endLine"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            located,
+            [
+                ("dom-added", "new/a.html", 8, 8),
+                ("dom-removed", "old/a.html", 3, 4),
+                ("dom-modified", "new/a.html", 6, 7),
+            ]
+        );
+
+        // Only the modification points back at its old lines
+        assert!(results[0].get("relatedLocations").is_none() && results[1].get("relatedLocations").is_none());
+        let related = &results[2]["relatedLocations"][0]["physicalLocation"];
+        assert_eq!(related["artifactLocation"]["uri"], "old/a.html");
+        assert_eq!((related["region"]["startLine"].as_u64(), related["region"]["endLine"].as_u64()), (Some(5), Some(5)));
+    }
+
+    #[test]
+    fn sarif_uris_are_percent_encoded() {
+        assert_eq!(uri("site/about us/index.html"), "site/about%20us/index.html");
+        assert_eq!(uri("a#b?c%d.html"), "a%23b%3Fc%25d.html");
+        assert_eq!(uri("caf\u{e9}/~x-y_z.html"), "caf%C3%A9/~x-y_z.html");
+
+        let changed = result(vec![line_diff("removed", "L2", "- TEXT:b")]);
+        let sarif = sarif_json(&[FileComparison { old_path: "old dir/#1.html", new_path: "new.html", result: &changed }]);
+        let sarif: Value = serde_json::from_str(&sarif).unwrap();
+        let location = &sarif["runs"][0]["results"][0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "old%20dir/%231.html");
+        assert_eq!(render(OutputFormat::Sarif, &[]), sarif_json(&[]) + "\n");
+    }
+}
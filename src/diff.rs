@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, HashSet};
+use std::hash::Hash;
+use std::str::FromStr;
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+
+use crate::dom::SubtreeChange;
+use crate::edit::EditOperation;
+use crate::merkle::{merkle_lite_hash, merkle_tree_hash};
+use crate::normalize::TokenWithLine;
+use crate::sequence::{DiffOp, SequenceEdit, diff_sequences};
+use crate::stream::SketchSummary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffMethod {
+    MerkleLite,
+    MerkleTree,
+    // Hierarchical Merkle tree mirroring element nesting; chunk size does not apply
+    DomTree,
+    // Node-level edit script over the DOM Merkle tree
+    TreeEdit,
+}
+
+impl DiffMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiffMethod::MerkleLite => "merkle_lite",
+            DiffMethod::MerkleTree => "merkle_tree",
+            DiffMethod::DomTree => "dom_tree",
+            DiffMethod::TreeEdit => "tree_edit",
+        }
+    }
+
+    // Whether the method compares DOM trees rather than flat chunks
+    pub fn uses_dom_tree(&self) -> bool {
+        matches!(self, DiffMethod::DomTree | DiffMethod::TreeEdit)
+    }
+
+    // Hash a chunk list the way this method does: directly, or via the leaves of a full tree
+    pub fn hash_chunks(&self, chunks: &[String]) -> Vec<String> {
+        match self {
+            // Flat chunks carry no element structure, so the DOM tree hashes them directly
+            DiffMethod::MerkleLite | DiffMethod::DomTree | DiffMethod::TreeEdit => merkle_lite_hash(chunks),
+            DiffMethod::MerkleTree => merkle_tree_hash(chunks),
+        }
+    }
+}
+
+impl FromStr for DiffMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merkle_lite" => Ok(DiffMethod::MerkleLite),
+            "merkle_tree" => Ok(DiffMethod::MerkleTree),
+            "dom_tree" => Ok(DiffMethod::DomTree),
+            "tree_edit" => Ok(DiffMethod::TreeEdit),
+            _ => Err(format!("unknown method '{}' (expected merkle_lite, merkle_tree, dom_tree or tree_edit)", s)),
+        }
+    }
+}
+
+// How chunk hashes are compared: as sets (order and repetition ignored) or as
+// ordered sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffMode {
+    #[default]
+    Set,
+    Ordered,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LineDiff {
+    pub line_range: String,    // e.g., "L100-L120" (lines in the new version for "modified")
+    pub change_type: String,   // "added", "removed", "modified"
+    pub content_preview: String,
+    // Both sides of a "modified" entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_line_range: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_line_range: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_content: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComparisonResult {
+    pub version_a: String,
+    pub version_b: String,
+    pub difference_percent: f64,
+    pub total_chunks_a: usize,
+    pub total_chunks_b: usize,
+    pub common_chunks: usize,
+    pub different_chunks: usize,
+    pub method: String,
+    #[serde(default)]
+    pub hash_algorithm: String,
+    pub processing_time_ms: u128,
+    pub processing_time_us: u128,  // microseconds
+    pub processing_time_ns: u128,  // nanoseconds
+    pub line_diffs: Vec<LineDiff>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtree_changes: Vec<SubtreeChange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sequence_edits: Vec<SequenceEdit>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edit_script: Vec<EditOperation>,
+    // Set when the documents were streamed and compared through chunk sketches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sketch: Option<SketchSummary>,
+}
+
+pub fn detailed_diff<T: Eq + Hash>(hashes_a: &[T], hashes_b: &[T]) -> (f64, usize, usize, usize, usize) {
+    let set_a: HashSet<_> = hashes_a.iter().collect();
+    let set_b: HashSet<_> = hashes_b.iter().collect();
+
+    let total = set_a.union(&set_b).count();
+    let common = set_a.intersection(&set_b).count();
+    let diff = set_a.symmetric_difference(&set_b).count();
+
+    let percent = if total == 0 {
+        0.0
+    } else {
+        (diff as f64 / total as f64) * 100.0
+    };
+
+    (percent, hashes_a.len(), hashes_b.len(), common, diff)
+}
+
+pub fn generate_line_diffs(tokens_a: &[TokenWithLine], tokens_b: &[TokenWithLine]) -> Vec<LineDiff> {
+    // Create hash sets for comparison
+    let set_a: HashSet<_> = tokens_a.iter().map(|t| &t.content).collect();
+    let set_b: HashSet<_> = tokens_b.iter().map(|t| &t.content).collect();
+
+    // Removed tokens (in a but not in b) and added tokens (in b but not in a),
+    // positioned by how many common tokens precede them on their side
+    let mut hunks: BTreeMap<usize, Hunk> = BTreeMap::new();
+    let mut common = 0;
+    for token in tokens_a {
+        if set_b.contains(&token.content) {
+            common += 1;
+        } else {
+            hunks.entry(common).or_default().removed.push((token.line_number, token.content.clone()));
+        }
+    }
+    common = 0;
+    for token in tokens_b {
+        if set_a.contains(&token.content) {
+            common += 1;
+        } else {
+            hunks.entry(common).or_default().added.push((token.line_number, token.content.clone()));
+        }
+    }
+
+    line_diffs_from_hunks(hunks.into_values())
+}
+
+// Order-aware variant of `generate_line_diffs`: tokens are aligned with a
+// sequence diff, so repeated tokens (many `TAG:</div>`) and reordered content
+// show up as changes instead of vanishing into a set.
+pub fn generate_ordered_line_diffs(tokens_a: &[TokenWithLine], tokens_b: &[TokenWithLine]) -> Vec<LineDiff> {
+    let contents_a: Vec<&str> = tokens_a.iter().map(|t| t.content.as_str()).collect();
+    let contents_b: Vec<&str> = tokens_b.iter().map(|t| t.content.as_str()).collect();
+
+    let mut hunks = vec![Hunk::default()];
+    for op in diff_sequences(&contents_a, &contents_b) {
+        let hunk = hunks.last_mut().expect("current hunk");
+        match op {
+            DiffOp::Equal(_, _) => {
+                if !hunk.removed.is_empty() || !hunk.added.is_empty() {
+                    hunks.push(Hunk::default());
+                }
+            }
+            DiffOp::Delete(i) => hunk.removed.push((tokens_a[i].line_number, tokens_a[i].content.clone())),
+            DiffOp::Insert(j) => hunk.added.push((tokens_b[j].line_number, tokens_b[j].content.clone())),
+        }
+    }
+
+    line_diffs_from_hunks(hunks)
+}
+
+// Tokens removed from A and added to B at the same position, i.e. between the
+// same pair of common tokens
+#[derive(Default)]
+struct Hunk {
+    removed: Vec<(usize, String)>,
+    added: Vec<(usize, String)>,
+}
+
+// What has to match for a removed and an added token to be the same node with
+// changed content: the tag name (`TAG:<div`, `TAG:</div`), or the token type
+// for text, comments and doctypes
+fn token_match_key(content: &str) -> &str {
+    match content.strip_prefix("TAG:") {
+        Some(tag) if tag.starts_with("<!--") => "TAG:<!--",
+        Some(tag) if tag.starts_with("<!") => "TAG:<!",
+        Some(tag) => {
+            let name_start = if tag.starts_with("</") { 2 } else { 1 };
+            let name_len = tag[name_start..].find([' ', '>', '/']).unwrap_or(tag.len() - name_start);
+            &content[..4 + name_start + name_len]
+        }
+        None => content.split(':').next().unwrap_or(content),
+    }
+}
+
+fn line_range(start: usize, end: usize) -> String {
+    if start == end {
+        format!("L{}", start)
+    } else {
+        format!("L{}-L{}", start, end)
+    }
+}
+
+// Inverse of `line_range`: "L12" or "L12-L15" as (12, 15)
+pub fn parse_line_range(range: &str) -> Option<(usize, usize)> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    Some((start.strip_prefix('L')?.parse().ok()?, end.strip_prefix('L')?.parse().ok()?))
+}
+
+fn line_diffs_from_hunks<I: IntoIterator<Item = Hunk>>(hunks: I) -> Vec<LineDiff> {
+    let mut added_items = Vec::new();
+    let mut removed_items = Vec::new();
+    let mut modified_items = Vec::new();
+
+    // Within a hunk, pair removed and added tokens of the same kind in order
+    for Hunk { removed, added } in hunks {
+        let mut next = 0;
+        for (line_a, content_a) in removed {
+            let key = token_match_key(&content_a);
+            match (next..added.len()).find(|&j| token_match_key(&added[j].1) == key) {
+                Some(j) => {
+                    added_items.extend_from_slice(&added[next..j]);
+                    let (line_b, content_b) = added[j].clone();
+                    modified_items.push((line_a, content_a, line_b, content_b));
+                    next = j + 1;
+                }
+                None => removed_items.push((line_a, content_a)),
+            }
+        }
+        added_items.extend_from_slice(&added[next..]);
+    }
+
+    let mut line_diffs = Vec::new();
+
+    // Group consecutive line numbers into ranges with content
+    fn group_consecutive_lines_with_content(mut items: Vec<(usize, String)>) -> Vec<(String, String)> {
+        if items.is_empty() {
+            return Vec::new();
+        }
+
+        items.sort_by_key(|&(line, _)| line);
+
+        let mut ranges = Vec::new();
+        let mut start = items[0].0;
+        let mut end = items[0].0;
+        let mut content_samples = vec![items[0].1.clone()];
+
+        for &(line, ref content) in &items[1..] {
+            if line == end + 1 {
+                end = line;
+                if content_samples.len() < 3 {
+                    content_samples.push(content.clone());
+                }
+            } else {
+                let preview = if content_samples.len() <= 2 {
+                    content_samples.join(", ")
+                } else {
+                    format!("{}, {} ... ({} more)",
+                        content_samples[0], content_samples[1], content_samples.len() - 2)
+                };
+
+                ranges.push((line_range(start, end), preview));
+                start = line;
+                end = line;
+                content_samples = vec![content.clone()];
+            }
+        }
+
+        // Add the last range
+        let preview = if content_samples.len() <= 2 {
+            content_samples.join(", ")
+        } else {
+            format!("{}, {} ... ({} more)",
+                content_samples[0], content_samples[1], content_samples.len() - 2)
+        };
+
+        ranges.push((line_range(start, end), preview));
+        ranges
+    }
+
+    // Generate line diffs for modified content: consecutive pairs whose lines
+    // advance together on both sides form one entry
+    let mut pairs = modified_items.into_iter();
+    if let Some((line_a, content_a, line_b, content_b)) = pairs.next() {
+        let mut group = ModifiedGroup::new(line_a, content_a, line_b, content_b);
+        for (line_a, content_a, line_b, content_b) in pairs {
+            if (group.end_a..=group.end_a + 1).contains(&line_a) && (group.end_b..=group.end_b + 1).contains(&line_b) {
+                group.push(line_a, content_a, line_b, content_b);
+            } else {
+                line_diffs.push(group.into_line_diff());
+                group = ModifiedGroup::new(line_a, content_a, line_b, content_b);
+            }
+        }
+        line_diffs.push(group.into_line_diff());
+    }
+
+    // Generate line diffs for added content
+    for (range, content) in group_consecutive_lines_with_content(added_items) {
+        line_diffs.push(LineDiff {
+            line_range: range,
+            change_type: "added".to_string(),
+            content_preview: format!("+ {}", content.chars().take(100).collect::<String>()),
+            ..LineDiff::default()
+        });
+    }
+
+    // Generate line diffs for removed content
+    for (range, content) in group_consecutive_lines_with_content(removed_items) {
+        line_diffs.push(LineDiff {
+            line_range: range,
+            change_type: "removed".to_string(),
+            content_preview: format!("- {}", content.chars().take(100).collect::<String>()),
+            ..LineDiff::default()
+        });
+    }
+
+    line_diffs
+}
+
+// Run of paired tokens reported as a single "modified" line diff
+struct ModifiedGroup {
+    start_a: usize,
+    end_a: usize,
+    start_b: usize,
+    end_b: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+impl ModifiedGroup {
+    fn new(line_a: usize, content_a: String, line_b: usize, content_b: String) -> Self {
+        ModifiedGroup {
+            start_a: line_a,
+            end_a: line_a,
+            start_b: line_b,
+            end_b: line_b,
+            old: vec![content_a],
+            new: vec![content_b],
+        }
+    }
+
+    fn push(&mut self, line_a: usize, content_a: String, line_b: usize, content_b: String) {
+        self.end_a = line_a;
+        self.end_b = line_b;
+        self.old.push(content_a);
+        self.new.push(content_b);
+    }
+
+    fn into_line_diff(self) -> LineDiff {
+        let mut preview = format!(
+            "~ {} -> {}",
+            self.old[0].chars().take(100).collect::<String>(),
+            self.new[0].chars().take(100).collect::<String>()
+        );
+        if self.old.len() > 1 {
+            preview.push_str(&format!(" ... ({} more)", self.old.len() - 1));
+        }
+        LineDiff {
+            line_range: line_range(self.start_b, self.end_b),
+            change_type: "modified".to_string(),
+            content_preview: preview,
+            old_line_range: Some(line_range(self.start_a, self.end_a)),
+            new_line_range: Some(line_range(self.start_b, self.end_b)),
+            old_content: Some(self.old.join("\n")),
+            new_content: Some(self.new.join("\n")),
+        }
+    }
+}
+
+pub fn compare_with_method(chunks_a: &[String], chunks_b: &[String], method: DiffMethod) -> (f64, usize, usize, usize, usize, u128, u128, u128) {
+    let start = Instant::now();
+
+    let hashes_a = method.hash_chunks(chunks_a);
+    let hashes_b = method.hash_chunks(chunks_b);
+
+    let (percent, total_a, total_b, common, different) = detailed_diff(&hashes_a, &hashes_b);
+    let elapsed = start.elapsed();
+    let duration_ms = elapsed.as_millis();
+    let duration_us = elapsed.as_micros();
+    let duration_ns = elapsed.as_nanos();
+
+    (percent, total_a, total_b, common, different, duration_ms, duration_us, duration_ns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(items: &[(usize, &str)]) -> Vec<TokenWithLine> {
+        items
+            .iter()
+            .map(|&(line_number, content)| TokenWithLine { content: content.to_string(), line_number })
+            .collect()
+    }
+
+    fn summary(diffs: &[LineDiff]) -> Vec<(&str, &str)> {
+        diffs.iter().map(|d| (d.change_type.as_str(), d.line_range.as_str())).collect()
+    }
+
+    #[test]
+    fn match_keys_compare_tag_names_or_token_types() {
+        assert_eq!(token_match_key(r#"TAG:<div class="x">"#), "TAG:<div");
+        assert_eq!(token_match_key("TAG:<div>"), "TAG:<div");
+        assert_eq!(token_match_key("TAG:</div>"), "TAG:</div");
+        assert_eq!(token_match_key("TAG:<br/>"), "TAG:<br");
+        assert_eq!(token_match_key("TAG:<!-- a -->"), "TAG:<!--");
+        assert_eq!(token_match_key("TAG:<!DOCTYPE html>"), "TAG:<!");
+        assert_eq!(token_match_key("TEXT:a: b"), "TEXT");
+        assert_ne!(token_match_key("TAG:<div>"), token_match_key("TAG:<divx>"));
+    }
+
+    #[test]
+    fn pairs_removed_and_added_tokens_into_modified_entries() {
+        let a = tokens(&[(1, "TAG:<main>"), (2, r#"TAG:<p class="a">"#), (2, "TEXT:old"), (3, "TAG:</p>"), (4, "TAG:</main>")]);
+        let b = tokens(&[(1, "TAG:<main>"), (2, r#"TAG:<p class="b">"#), (3, "TEXT:new"), (4, "TAG:</p>"), (5, "TAG:</main>")]);
+
+        for diffs in [generate_line_diffs(&a, &b), generate_ordered_line_diffs(&a, &b)] {
+            assert_eq!(summary(&diffs), [("modified", "L2-L3")]);
+            let modified = &diffs[0];
+            assert_eq!(modified.old_line_range.as_deref(), Some("L2"));
+            assert_eq!(modified.new_line_range.as_deref(), Some("L2-L3"));
+            assert_eq!(modified.old_content.as_deref(), Some("TAG:<p class=\"a\">\nTEXT:old"));
+            assert_eq!(modified.new_content.as_deref(), Some("TAG:<p class=\"b\">\nTEXT:new"));
+            assert_eq!(modified.content_preview, r#"~ TAG:<p class="a"> -> TAG:<p class="b"> ... (1 more)"#);
+        }
+    }
+
+    #[test]
+    fn leaves_tokens_of_different_kinds_unpaired() {
+        let a = tokens(&[(1, "TAG:<ul>"), (2, "TAG:<li>"), (2, "TEXT:one"), (3, "TAG:</ul>")]);
+        let b = tokens(&[(1, "TAG:<ul>"), (2, "TAG:<img>"), (3, "TEXT:uno"), (4, "TAG:</ul>")]);
+        let diffs = generate_ordered_line_diffs(&a, &b);
+        assert_eq!(summary(&diffs), [("modified", "L3"), ("added", "L2"), ("removed", "L2")]);
+        assert_eq!(diffs[0].old_line_range.as_deref(), Some("L2"));
+        assert_eq!(diffs[0].content_preview, "~ TEXT:one -> TEXT:uno");
+        assert_eq!(diffs[1].content_preview, "+ TAG:<img>");
+        assert_eq!(diffs[2].content_preview, "- TAG:<li>");
+        assert!(diffs[1].old_line_range.is_none() && diffs[2].new_content.is_none());
+    }
+
+    #[test]
+    fn pairs_only_within_the_same_hunk() {
+        // The removal comes before the common <hr>, the addition after it
+        let a = tokens(&[(1, "TEXT:before"), (2, "TAG:<hr>"), (3, "TAG:<br>")]);
+        let b = tokens(&[(1, "TAG:<hr>"), (2, "TAG:<br>"), (3, "TEXT:after")]);
+        for diffs in [generate_line_diffs(&a, &b), generate_ordered_line_diffs(&a, &b)] {
+            assert_eq!(summary(&diffs), [("added", "L3"), ("removed", "L1")]);
+        }
+    }
+
+    #[test]
+    fn splits_modified_runs_when_either_side_jumps() {
+        let a = tokens(&[(1, "TEXT:a"), (2, "TEXT:b"), (3, "TEXT:c")]);
+        let b = tokens(&[(1, "TEXT:x"), (2, "TEXT:y"), (3, "TEXT:z")]);
+        let diffs = generate_ordered_line_diffs(&a, &b);
+        assert_eq!(summary(&diffs), [("modified", "L1-L3")]);
+        assert_eq!(diffs[0].content_preview, "~ TEXT:a -> TEXT:x ... (2 more)");
+
+        // c jumps from L3 to L9 in A while z stays on L3 in B
+        let a = tokens(&[(1, "TEXT:a"), (2, "TEXT:b"), (9, "TEXT:c")]);
+        let diffs = generate_ordered_line_diffs(&a, &b);
+        assert_eq!(summary(&diffs), [("modified", "L1-L2"), ("modified", "L3")]);
+        assert_eq!(diffs[1].old_line_range.as_deref(), Some("L9"));
+    }
+
+    #[test]
+    fn groups_added_and_removed_tokens_on_consecutive_lines() {
+        let a = tokens(&[(1, "TAG:<body>")]);
+        let b = tokens(&[(1, "TAG:<body>"), (2, "TEXT:a"), (3, "TEXT:b"), (4, "TEXT:c"), (6, "TEXT:d")]);
+        let diffs = generate_line_diffs(&a, &b);
+        assert_eq!(summary(&diffs), [("added", "L2-L4"), ("added", "L6")]);
+        assert_eq!(diffs[0].content_preview, "+ TEXT:a, TEXT:b ... (1 more)");
+
+        let diffs = generate_line_diffs(&b, &a);
+        assert_eq!(summary(&diffs), [("removed", "L2-L4"), ("removed", "L6")]);
+        assert_eq!(diffs[1].content_preview, "- TEXT:d");
+        assert!(generate_line_diffs(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn parses_line_ranges() {
+        assert_eq!(parse_line_range("L12"), Some((12, 12)));
+        assert_eq!(parse_line_range("L12-L15"), Some((12, 15)));
+        assert_eq!(parse_line_range(&line_range(3, 7)), Some((3, 7)));
+        assert_eq!(parse_line_range("12"), None);
+        assert_eq!(parse_line_range("L1-15"), None);
+    }
+}
@@ -0,0 +1,396 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::chunking::{Chunking, chunk_ranges};
+use crate::diff::{
+    ComparisonResult, DiffMethod, DiffMode, detailed_diff, generate_line_diffs, generate_ordered_line_diffs,
+};
+use crate::dom::{build_dom_tree, diff_dom_trees};
+use crate::edit::edit_script;
+use crate::hashing::get_perf_config;
+use crate::ignore::IgnoreRules;
+use crate::normalize::{TokenWithLine, hash_spans, normalize_html_with_rules};
+use crate::sequence::ordered_diff;
+use crate::stream::{DEFAULT_SKETCH_CAPACITY, SketchSummary, compare_sketches, sketch_reader};
+
+// Builder for DOM comparisons; the entry point for library users.
+//
+//     let result = DomDiffer::new()
+//         .chunk_size(3)
+//         .method(DiffMethod::MerkleTree)
+//         .line_diffs(true)
+//         .compare(&old_html, &new_html);
+#[derive(Debug, Clone)]
+pub struct DomDiffer {
+    pub(crate) chunking: Chunking,
+    pub(crate) method: DiffMethod,
+    mode: DiffMode,
+    include_line_diffs: bool,
+    ignore_rules: IgnoreRules,
+    sketch_capacity: usize,
+}
+
+impl Default for DomDiffer {
+    fn default() -> Self {
+        DomDiffer {
+            chunking: Chunking::default(),
+            method: DiffMethod::MerkleLite,
+            mode: DiffMode::Set,
+            include_line_diffs: false,
+            ignore_rules: IgnoreRules::default(),
+            sketch_capacity: DEFAULT_SKETCH_CAPACITY,
+        }
+    }
+}
+
+impl DomDiffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Number of tokens grouped into one chunk (values below 1 are treated as 1)
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunking = Chunking::from(chunk_size);
+        self
+    }
+
+    // Fixed-size or content-defined chunk boundaries
+    pub fn chunking(mut self, chunking: impl Into<Chunking>) -> Self {
+        self.chunking = chunking.into();
+        self
+    }
+
+    pub fn method(mut self, method: DiffMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    // Set-based (default) or order-aware comparison of the chunk hashes
+    pub fn mode(mut self, mode: DiffMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // Whether to populate `ComparisonResult::line_diffs` (slower)
+    pub fn line_diffs(mut self, enabled: bool) -> Self {
+        self.include_line_diffs = enabled;
+        self
+    }
+
+    // Mask volatile attributes, values and subtrees before hashing
+    pub fn ignore(mut self, rules: IgnoreRules) -> Self {
+        self.ignore_rules = rules;
+        self
+    }
+
+    // Distinct chunk hashes kept per document by the streaming comparison;
+    // documents with more distinct chunks get an estimated percentage
+    pub fn sketch_capacity(mut self, capacity: usize) -> Self {
+        self.sketch_capacity = capacity.max(1);
+        self
+    }
+
+    pub fn compare(&self, html_a: &str, html_b: &str) -> ComparisonResult {
+        self.compare_named("a", html_a, "b", html_b)
+    }
+
+    pub fn compare_named(&self, version_a: &str, html_a: &str, version_b: &str, html_b: &str) -> ComparisonResult {
+        let start = Instant::now();
+        let config = get_perf_config();
+        let algorithm = config.hash_algorithm;
+
+        // A plain set comparison only needs chunk digests, which the span
+        // pipeline produces without materializing any chunk text. The hash
+        // cache works on chunk text, so enabling it keeps the string pipeline.
+        if self.method == DiffMethod::MerkleLite
+            && self.mode == DiffMode::Set
+            && !self.include_line_diffs
+            && !config.use_hash_cache
+        {
+            let hashed_a = hash_spans(html_a, self.chunking, &self.ignore_rules, algorithm);
+            let hashed_b = hash_spans(html_b, self.chunking, &self.ignore_rules, algorithm);
+            let (percent, total_a, total_b, common, different) =
+                detailed_diff(&hashed_a.chunk_hashes, &hashed_b.chunk_hashes);
+            let elapsed = start.elapsed();
+            return ComparisonResult {
+                version_a: version_a.to_string(),
+                version_b: version_b.to_string(),
+                difference_percent: percent,
+                total_chunks_a: total_a,
+                total_chunks_b: total_b,
+                common_chunks: common,
+                different_chunks: different,
+                method: self.method.as_str().to_string(),
+                hash_algorithm: algorithm.as_str().to_string(),
+                processing_time_ms: elapsed.as_millis(),
+                processing_time_us: elapsed.as_micros(),
+                processing_time_ns: elapsed.as_nanos(),
+                line_diffs: Vec::new(),
+                subtree_changes: Vec::new(),
+                sequence_edits: Vec::new(),
+                edit_script: Vec::new(),
+                sketch: None,
+            };
+        }
+
+        let (chunks_a, tokens_a) = normalize_html_with_rules(html_a, self.chunking, &self.ignore_rules);
+        let (chunks_b, tokens_b) = normalize_html_with_rules(html_b, self.chunking, &self.ignore_rules);
+
+        let trees = if self.method.uses_dom_tree() {
+            Some((build_dom_tree(html_a, &self.ignore_rules), build_dom_tree(html_b, &self.ignore_rules)))
+        } else {
+            None
+        };
+
+        // Per-chunk hash, line and content; in DOM tree mode every node is a chunk
+        let (hashes_a, hashes_b, labels_a, labels_b, subtree_changes) = match trees {
+            Some((ref tree_a, ref tree_b)) => {
+                let nodes_a = tree_a.descendants();
+                let nodes_b = tree_b.descendants();
+                (
+                    nodes_a.iter().map(|n| n.hash.clone()).collect(),
+                    nodes_b.iter().map(|n| n.hash.clone()).collect(),
+                    nodes_a.iter().map(|n| (n.line_number, n.label.as_str())).collect(),
+                    nodes_b.iter().map(|n| (n.line_number, n.label.as_str())).collect(),
+                    if self.method == DiffMethod::DomTree { diff_dom_trees(tree_a, tree_b) } else { Vec::new() },
+                )
+            }
+            None => (
+                self.method.hash_chunks(&chunks_a),
+                self.method.hash_chunks(&chunks_b),
+                chunk_labels(&chunks_a, &tokens_a, self.chunking),
+                chunk_labels(&chunks_b, &tokens_b, self.chunking),
+                Vec::new(),
+            ),
+        };
+
+        let (mut percent, total_a, total_b, mut common, mut different) = detailed_diff(&hashes_a, &hashes_b);
+        let mut edit_operations = Vec::new();
+        if self.method == DiffMethod::TreeEdit
+            && let Some((ref tree_a, ref tree_b)) = trees
+        {
+            let script = edit_script(tree_a, tree_b);
+            percent = script.difference_percent();
+            common = script.unchanged;
+            different = script.nodes_a + script.nodes_b - 2 * script.unchanged;
+            edit_operations = script.operations;
+        }
+        let mut sequence_edits = Vec::new();
+        if self.mode == DiffMode::Ordered {
+            let ordered = ordered_diff(&hashes_a, &hashes_b, |in_b, idx| {
+                let (line, label) = if in_b { labels_b[idx] } else { labels_a[idx] };
+                (Some(line), label.chars().take(100).collect())
+            });
+            percent = ordered.difference_percent();
+            common = ordered.matched;
+            different = ordered.inserted + ordered.deleted;
+            sequence_edits = ordered.edits;
+        }
+
+        let line_diffs = match (self.include_line_diffs, self.mode) {
+            (false, _) => Vec::new(),
+            (true, DiffMode::Set) => generate_line_diffs(&tokens_a, &tokens_b),
+            (true, DiffMode::Ordered) => generate_ordered_line_diffs(&tokens_a, &tokens_b),
+        };
+        let elapsed = start.elapsed();
+
+        ComparisonResult {
+            version_a: version_a.to_string(),
+            version_b: version_b.to_string(),
+            difference_percent: percent,
+            total_chunks_a: total_a,
+            total_chunks_b: total_b,
+            common_chunks: common,
+            different_chunks: different,
+            method: self.method.as_str().to_string(),
+            hash_algorithm: algorithm.as_str().to_string(),
+            processing_time_ms: elapsed.as_millis(),
+            processing_time_us: elapsed.as_micros(),
+            processing_time_ns: elapsed.as_nanos(),
+            line_diffs,
+            subtree_changes,
+            sequence_edits,
+            edit_script: edit_operations,
+            sketch: None,
+        }
+    }
+
+    // Read both files and compare them, naming each version after its path
+    pub fn compare_files<P: AsRef<Path>, Q: AsRef<Path>>(&self, path_a: P, path_b: Q) -> io::Result<ComparisonResult> {
+        let content_a = fs::read_to_string(&path_a)?;
+        let content_b = fs::read_to_string(&path_b)?;
+        Ok(self.compare_named(
+            &path_a.as_ref().display().to_string(),
+            &content_a,
+            &path_b.as_ref().display().to_string(),
+            &content_b,
+        ))
+    }
+
+    // Compare two documents read incrementally, in memory bounded by the
+    // sketch capacity and the longest token rather than the documents. Only
+    // set-based `merkle_lite` comparisons without line diffs can be streamed.
+    pub fn compare_readers<R: BufRead, S: BufRead>(
+        &self,
+        version_a: &str,
+        reader_a: R,
+        version_b: &str,
+        reader_b: S,
+    ) -> io::Result<ComparisonResult> {
+        if self.method != DiffMethod::MerkleLite || self.mode != DiffMode::Set || self.include_line_diffs {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "streaming supports set-based merkle_lite comparisons without line diffs",
+            ));
+        }
+        let start = Instant::now();
+        let algorithm = get_perf_config().hash_algorithm;
+        let sketch_a = sketch_reader(reader_a, self.chunking, &self.ignore_rules, algorithm, self.sketch_capacity)?;
+        let sketch_b = sketch_reader(reader_b, self.chunking, &self.ignore_rules, algorithm, self.sketch_capacity)?;
+        let (percent, total_a, total_b, common, different) = compare_sketches(&sketch_a, &sketch_b);
+        let elapsed = start.elapsed();
+
+        Ok(ComparisonResult {
+            version_a: version_a.to_string(),
+            version_b: version_b.to_string(),
+            difference_percent: percent,
+            total_chunks_a: total_a,
+            total_chunks_b: total_b,
+            common_chunks: common,
+            different_chunks: different,
+            method: self.method.as_str().to_string(),
+            hash_algorithm: algorithm.as_str().to_string(),
+            processing_time_ms: elapsed.as_millis(),
+            processing_time_us: elapsed.as_micros(),
+            processing_time_ns: elapsed.as_nanos(),
+            line_diffs: Vec::new(),
+            subtree_changes: Vec::new(),
+            sequence_edits: Vec::new(),
+            edit_script: Vec::new(),
+            sketch: Some(SketchSummary {
+                capacity: self.sketch_capacity,
+                exact: sketch_a.is_exact() && sketch_b.is_exact(),
+            }),
+        })
+    }
+
+    // `compare_files` without reading either file into memory
+    pub fn compare_files_streaming<P: AsRef<Path>, Q: AsRef<Path>>(&self, path_a: P, path_b: Q) -> io::Result<ComparisonResult> {
+        let reader_a = BufReader::new(fs::File::open(&path_a)?);
+        let reader_b = BufReader::new(fs::File::open(&path_b)?);
+        self.compare_readers(
+            &path_a.as_ref().display().to_string(),
+            reader_a,
+            &path_b.as_ref().display().to_string(),
+            reader_b,
+        )
+    }
+}
+
+// First line and content of each chunk, for reporting sequence edits
+fn chunk_labels<'a>(chunks: &'a [String], tokens: &[TokenWithLine], chunking: Chunking) -> Vec<(usize, &'a str)> {
+    chunk_ranges(tokens, chunking)
+        .into_iter()
+        .zip(chunks)
+        .map(|(range, chunk)| (tokens[range.start].line_number, chunk.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::{ContentDefined, ElementChunks};
+    use crate::merkle::merkle_lite_hash;
+    use crate::hashing::HashAlgorithm;
+    use crate::ignore::IgnoreConfig;
+
+    fn snapshots() -> Vec<String> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots");
+        let mut paths: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "html"))
+            .collect();
+        paths.sort();
+        paths.iter().map(|path| fs::read_to_string(path).unwrap()).collect()
+    }
+
+    fn chunkings() -> Vec<Chunking> {
+        vec![
+            Chunking::Fixed(1),
+            Chunking::Fixed(3),
+            Chunking::Fixed(10),
+            Chunking::ContentDefined(ContentDefined::new(2, 8, 32).unwrap()),
+            Chunking::Elements(ElementChunks { depth: 3, max_tokens: Some(20) }),
+        ]
+    }
+
+    fn rules() -> IgnoreRules {
+        IgnoreRules::new(&IgnoreConfig {
+            selectors: vec!["script".to_string()],
+            attributes: vec!["data-*".to_string()],
+            values: vec![r"\d+".to_string()],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn span_digests_encode_to_the_string_pipeline_hashes() {
+        let pages = snapshots();
+        assert_eq!(pages.len(), 6);
+        let algorithm = get_perf_config().hash_algorithm;
+        for html in &pages {
+            for chunking in chunkings() {
+                for rules in [IgnoreRules::default(), rules()] {
+                    let (chunks, tokens) = normalize_html_with_rules(html, chunking, &rules);
+                    let hashed = hash_spans(html, chunking, &rules, algorithm);
+                    let encoded: Vec<String> = hashed.chunk_hashes.iter().map(|d| algorithm.hasher().encode(d)).collect();
+                    assert_eq!(encoded, merkle_lite_hash(&chunks), "{:?}", chunking);
+                    let lines: Vec<usize> = hashed.spans.iter().map(|s| s.line).collect();
+                    assert_eq!(lines, tokens.iter().map(|t| t.line_number).collect::<Vec<_>>());
+                }
+            }
+        }
+
+        // Every algorithm encodes its digests the way it hashes chunk text
+        let (chunks, _) = normalize_html_with_rules(&pages[0], 3, &IgnoreRules::default());
+        for algorithm in [HashAlgorithm::Xxh3_64, HashAlgorithm::Xxh3_128, HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+            let hasher = algorithm.hasher();
+            let hashed = hash_spans(&pages[0], 3, &IgnoreRules::default(), algorithm);
+            let encoded: Vec<String> = hashed.chunk_hashes.iter().map(|d| hasher.encode(d)).collect();
+            let expected: Vec<String> = chunks.iter().map(|c| hasher.hash(c.as_bytes())).collect();
+            assert_eq!(encoded, expected, "{}", algorithm.as_str());
+        }
+    }
+
+    #[test]
+    fn span_fast_path_matches_the_string_pipeline_on_every_snapshot_pair() {
+        let pages = snapshots();
+        for (chunking, rules) in [(Chunking::Fixed(1), IgnoreRules::default()), (Chunking::Fixed(3), rules())] {
+            let differ = DomDiffer::new().chunking(chunking).ignore(rules.clone());
+            let hashes: Vec<Vec<String>> =
+                pages.iter().map(|html| merkle_lite_hash(&normalize_html_with_rules(html, chunking, &rules).0)).collect();
+            for (a, hashes_a) in pages.iter().zip(&hashes) {
+                for (b, hashes_b) in pages.iter().zip(&hashes) {
+                    let result = differ.compare(a, b);
+                    let (percent, total_a, total_b, common, different) = detailed_diff(hashes_a, hashes_b);
+                    assert_eq!(result.difference_percent, percent, "{:?}", chunking);
+                    assert_eq!(
+                        (result.total_chunks_a, result.total_chunks_b, result.common_chunks, result.different_chunks),
+                        (total_a, total_b, common, different)
+                    );
+                }
+            }
+        }
+
+        // Asking for line diffs takes the string pipeline through the same counts
+        let (a, b) = (&pages[0], &pages[1]);
+        let fast = DomDiffer::new().chunk_size(3).compare(a, b);
+        let string = DomDiffer::new().chunk_size(3).line_diffs(true).compare(a, b);
+        assert!(fast.line_diffs.is_empty() && !string.line_diffs.is_empty());
+        assert_eq!(fast.difference_percent, string.difference_percent);
+        assert_eq!((fast.common_chunks, fast.different_chunks), (string.common_chunks, string.different_chunks));
+    }
+}
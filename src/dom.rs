@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::hash_chunk;
+use crate::ignore::IgnoreRules;
+use crate::normalize::walk_normalized_tokens;
+use crate::selector::SelectorElement;
+use crate::sequence::lcs_pairs;
+use crate::tokenizer::{Attribute, TokenKind, closes_implicitly, is_void_element};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomNodeKind {
+    Document,
+    Element,
+    Text,
+    Comment,
+    Doctype,
+}
+
+// A node of the DOM Merkle tree. Leaves hash their normalized token; elements
+// hash their canonical start tag (name plus sorted attributes) together with
+// the hashes of their children, so equal hashes mean equal subtrees.
+#[derive(Debug, Clone)]
+pub struct DomNode {
+    pub kind: DomNodeKind,
+    pub name: String,              // tag name for elements, empty otherwise
+    pub attributes: Vec<Attribute<'static>>,
+    pub label: String,             // normalized token, e.g. `TAG:<div class="a">` or `TEXT:Hello`
+    pub line_number: usize,
+    pub children: Vec<DomNode>,
+    pub hash: String,
+}
+
+impl DomNode {
+    fn new(kind: DomNodeKind, name: String, attributes: Vec<Attribute<'static>>, label: String, line_number: usize) -> Self {
+        DomNode {
+            kind,
+            name,
+            attributes,
+            label,
+            line_number,
+            children: Vec::new(),
+            hash: String::new(),
+        }
+    }
+
+    fn compute_hash(&mut self) {
+        self.hash = if self.kind == DomNodeKind::Element || self.kind == DomNodeKind::Document {
+            let mut combined = String::with_capacity(self.label.len() + 1 + self.children.len() * 17);
+            combined.push_str(&self.label);
+            combined.push('|');
+            for (i, child) in self.children.iter().enumerate() {
+                if i > 0 {
+                    combined.push(',');
+                }
+                combined.push_str(&child.hash);
+            }
+            hash_chunk(&combined)
+        } else {
+            hash_chunk(&self.label)
+        };
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    // Text content for text nodes, without the `TEXT:` prefix
+    pub fn text(&self) -> &str {
+        self.label.strip_prefix("TEXT:").unwrap_or(&self.label)
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.value.as_deref().unwrap_or(""))
+    }
+
+    pub fn node_count(&self) -> usize {
+        1 + self.children.iter().map(|c| c.node_count()).sum::<usize>()
+    }
+
+    // Every node below the document, in document order (preorder)
+    pub fn descendants(&self) -> Vec<&DomNode> {
+        fn collect<'a>(node: &'a DomNode, out: &mut Vec<&'a DomNode>) {
+            for child in &node.children {
+                out.push(child);
+                collect(child, out);
+            }
+        }
+        let mut nodes = Vec::new();
+        collect(self, &mut nodes);
+        nodes
+    }
+
+    // Subtree hashes of every node below the document, in document order
+    pub fn subtree_hashes(&self) -> Vec<String> {
+        self.descendants().into_iter().map(|n| n.hash.clone()).collect()
+    }
+
+    // XPath-style step for each child: `div[2]`, `text()[1]`, `comment()[1]`
+    pub fn child_steps(&self) -> Vec<String> {
+        let mut counters: HashMap<(DomNodeKind, &str), usize> = HashMap::new();
+        self.children
+            .iter()
+            .map(|child| {
+                let n = counters.entry((child.kind, child.name.as_str())).or_insert(0);
+                *n += 1;
+                node_step(child.kind, &child.name, *n)
+            })
+            .collect()
+    }
+
+    // Key used to pair up nodes that are "the same element" with changed content
+    fn match_key(&self) -> (DomNodeKind, &str) {
+        (self.kind, self.name.as_str())
+    }
+}
+
+impl SelectorElement for DomNode {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn attribute_value(&self, name: &str) -> Option<&str> {
+        self.attribute(name)
+    }
+}
+
+// Path step for the `n`th (1-based) child of this kind and tag name
+pub fn node_step(kind: DomNodeKind, name: &str, n: usize) -> String {
+    match kind {
+        DomNodeKind::Element => format!("{}[{}]", name, n),
+        DomNodeKind::Text => format!("text()[{}]", n),
+        DomNodeKind::Comment => format!("comment()[{}]", n),
+        DomNodeKind::Doctype => "doctype()".to_string(),
+        DomNodeKind::Document => String::new(),
+    }
+}
+
+// Build the DOM Merkle tree of `html`. Uses the same tokenization, tag
+// canonicalization and ignore masking as chunk-based comparison; a masked
+// subtree becomes a single leaf.
+pub fn build_dom_tree(html: &str, rules: &IgnoreRules) -> DomNode {
+    fn close_top(stack: &mut Vec<DomNode>) {
+        let mut node = stack.pop().expect("open element");
+        node.compute_hash();
+        stack.last_mut().expect("document node").children.push(node);
+    }
+
+    fn append_leaf(stack: &mut [DomNode], mut node: DomNode) {
+        node.compute_hash();
+        stack.last_mut().expect("document node").children.push(node);
+    }
+
+    let document = DomNode::new(DomNodeKind::Document, String::new(), Vec::new(), "#document".to_string(), 0);
+    let mut stack = vec![document];
+
+    walk_normalized_tokens(html, rules, |normalized| {
+        let token = normalized.token;
+        match token.kind {
+            TokenKind::StartTag => {
+                while stack.len() > 1 && closes_implicitly(&stack[stack.len() - 1].name, &token.name) {
+                    close_top(&mut stack);
+                }
+                let node = DomNode::new(
+                    DomNodeKind::Element,
+                    token.name.to_string(),
+                    token.attributes.iter().cloned().map(Attribute::into_owned).collect(),
+                    normalized.content.to_string(),
+                    normalized.line_number,
+                );
+                if normalized.masked_subtree || token.self_closing || is_void_element(&token.name) {
+                    append_leaf(&mut stack, node);
+                } else {
+                    stack.push(node);
+                }
+            }
+            TokenKind::EndTag => {
+                let open = stack.iter().rposition(|n| n.kind == DomNodeKind::Element && n.name == token.name);
+                if let Some(idx) = open {
+                    while stack.len() > idx {
+                        close_top(&mut stack);
+                    }
+                }
+            }
+            kind => {
+                let kind = match kind {
+                    TokenKind::Comment => DomNodeKind::Comment,
+                    TokenKind::Doctype => DomNodeKind::Doctype,
+                    _ => DomNodeKind::Text,
+                };
+                let node = DomNode::new(kind, String::new(), Vec::new(), normalized.content.to_string(), normalized.line_number);
+                append_leaf(&mut stack, node);
+            }
+        }
+    });
+
+    while stack.len() > 1 {
+        close_top(&mut stack);
+    }
+    let mut root = stack.pop().expect("document node");
+    root.compute_hash();
+    root
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtreeChange {
+    pub path: String,              // e.g. "/html[1]/body[1]/div[2]"
+    pub change_type: String,       // "added", "removed", "modified"
+    pub line_a: Option<usize>,
+    pub line_b: Option<usize>,
+    pub content_preview: String,
+}
+
+// How the children of two matched nodes line up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildAlignment {
+    Identical(usize, usize),       // same subtree hash
+    Changed(usize, usize),         // same element/kind, different content
+    Removed(usize),
+    Added(usize),
+}
+
+// Compare two DOM trees top-down. Identical subtrees are pruned by hash, so
+// only the element subtrees that actually differ are visited and reported.
+pub fn diff_dom_trees(a: &DomNode, b: &DomNode) -> Vec<SubtreeChange> {
+    let mut changes = Vec::new();
+    diff_nodes(a, b, "", "", &mut changes);
+    changes
+}
+
+fn preview(label: &str) -> String {
+    label.chars().take(100).collect()
+}
+
+fn diff_nodes(a: &DomNode, b: &DomNode, path_a: &str, path_b: &str, changes: &mut Vec<SubtreeChange>) {
+    if a.hash == b.hash {
+        return;
+    }
+
+    if a.label != b.label {
+        changes.push(SubtreeChange {
+            path: if path_b.is_empty() { "/".to_string() } else { path_b.to_string() },
+            change_type: "modified".to_string(),
+            line_a: Some(a.line_number),
+            line_b: Some(b.line_number),
+            content_preview: format!("~ {} -> {}", preview(&a.label), preview(&b.label)),
+        });
+    }
+
+    let steps_a = a.child_steps();
+    let steps_b = b.child_steps();
+    for alignment in align_children(&a.children, &b.children) {
+        match alignment {
+            ChildAlignment::Identical(_, _) => {}
+            ChildAlignment::Changed(i, j) => {
+                let child_a = format!("{}/{}", path_a, steps_a[i]);
+                let child_b = format!("{}/{}", path_b, steps_b[j]);
+                diff_nodes(&a.children[i], &b.children[j], &child_a, &child_b, changes);
+            }
+            ChildAlignment::Removed(i) => changes.push(SubtreeChange {
+                path: format!("{}/{}", path_a, steps_a[i]),
+                change_type: "removed".to_string(),
+                line_a: Some(a.children[i].line_number),
+                line_b: None,
+                content_preview: format!("- {}", preview(&a.children[i].label)),
+            }),
+            ChildAlignment::Added(j) => changes.push(SubtreeChange {
+                path: format!("{}/{}", path_b, steps_b[j]),
+                change_type: "added".to_string(),
+                line_a: None,
+                line_b: Some(b.children[j].line_number),
+                content_preview: format!("+ {}", preview(&b.children[j].label)),
+            }),
+        }
+    }
+}
+
+// Line up two child lists: identical subtrees first (longest common
+// subsequence of hashes), then nodes of the same kind and tag between those
+// anchors are paired in order, and whatever is left was removed or added.
+pub fn align_children(a: &[DomNode], b: &[DomNode]) -> Vec<ChildAlignment> {
+    let hashes_a: Vec<&str> = a.iter().map(|n| n.hash.as_str()).collect();
+    let hashes_b: Vec<&str> = b.iter().map(|n| n.hash.as_str()).collect();
+    let anchors = lcs_pairs(&hashes_a, &hashes_b);
+
+    let mut alignment = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    for &(ai, bj) in anchors.iter().chain(std::iter::once(&(a.len(), b.len()))) {
+        pair_gap(a, b, i..ai, j..bj, &mut alignment);
+        if ai < a.len() {
+            alignment.push(ChildAlignment::Identical(ai, bj));
+        }
+        i = ai + 1;
+        j = bj + 1;
+    }
+    alignment
+}
+
+fn pair_gap(a: &[DomNode], b: &[DomNode], gap_a: std::ops::Range<usize>, gap_b: std::ops::Range<usize>, out: &mut Vec<ChildAlignment>) {
+    let mut next_b = gap_b.start;
+    for i in gap_a {
+        let found = (next_b..gap_b.end).find(|&j| b[j].match_key() == a[i].match_key());
+        match found {
+            Some(j) => {
+                for skipped in next_b..j {
+                    out.push(ChildAlignment::Added(skipped));
+                }
+                out.push(ChildAlignment::Changed(i, j));
+                next_b = j + 1;
+            }
+            None => out.push(ChildAlignment::Removed(i)),
+        }
+    }
+    for j in next_b..gap_b.end {
+        out.push(ChildAlignment::Added(j));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(html: &str) -> DomNode {
+        build_dom_tree(html, &IgnoreRules::default())
+    }
+
+    fn changes(a: &str, b: &str) -> Vec<(String, String)> {
+        diff_dom_trees(&tree(a), &tree(b))
+            .into_iter()
+            .map(|c| (c.change_type, c.path))
+            .collect()
+    }
+
+    // Element names and text of the tree, nested like the DOM
+    fn shape(node: &DomNode) -> String {
+        let own = match node.kind {
+            DomNodeKind::Element => node.name.clone(),
+            DomNodeKind::Document => String::new(),
+            _ => format!("{:?}", node.text()),
+        };
+        if node.is_leaf() {
+            own
+        } else {
+            format!("{}({})", own, node.children.iter().map(shape).collect::<Vec<_>>().join(" "))
+        }
+    }
+
+    fn with_tag(n: usize) -> DomNode {
+        tree(&format!("<p>{}</p>", n)).children.remove(0)
+    }
+
+    fn with_name(name: &str, text: &str) -> DomNode {
+        tree(&format!("<{0}>{1}</{0}>", name, text)).children.remove(0)
+    }
+
+    #[test]
+    fn nests_elements_and_handles_void_and_unclosed_tags() {
+        // `<div>` implicitly closes the open `<p>`, and `<div/>` is taken as self-closing
+        let html = "<!DOCTYPE html><ul><li>one<li>two<br>three</ul><p>a<img src=x><p>b<div/>c</div><span>open";
+        assert_eq!(
+            shape(&tree(html)),
+            r#"("TAG:<!DOCTYPE html>" ul(li("one") li("two" br "three")) p("a" img) p("b") div "c" span("open"))"#
+        );
+
+        // Stray end tags are ignored; mismatched ones close everything above the match
+        assert_eq!(shape(&tree("<div><b>x</i></div>y</p>")), r#"(div(b("x")) "y")"#);
+        assert_eq!(shape(&tree("<section><em><b>x</section>y")), r#"(section(em(b("x"))) "y")"#);
+    }
+
+    #[test]
+    fn equal_subtrees_hash_equally_regardless_of_attribute_order() {
+        let a = tree(r#"<div id="x" class="c"><p>same</p></div>"#);
+        let b = tree(r#"<div class='c'   id=x><p>same</p></div>"#);
+        assert_eq!(a.hash, b.hash);
+        assert!(diff_dom_trees(&a, &b).is_empty());
+
+        let c = tree(r#"<div id="x" class="c"><p>other</p></div>"#);
+        assert_ne!(a.hash, c.hash);
+        assert_eq!(a.node_count(), 4);
+        assert_eq!(a.subtree_hashes().len(), 3);
+    }
+
+    #[test]
+    fn reports_changed_subtrees_by_path() {
+        let a = "<html><body><div>keep</div><div><p>old</p><p>same</p></div></body></html>";
+        let b = "<html><body><div>keep</div><div><p>new</p><p>same</p></div></body></html>";
+        assert_eq!(changes(a, b), [("modified".to_string(), "/html[1]/body[1]/div[2]/p[1]/text()[1]".to_string())]);
+
+        let b = r#"<html><body><div>keep</div><div class="x"><p>old</p><p>same</p></div></body></html>"#;
+        let reported = diff_dom_trees(&tree(a), &tree(b));
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].path, "/html[1]/body[1]/div[2]");
+        assert_eq!(reported[0].change_type, "modified");
+        assert!(reported[0].content_preview.contains(r#"-> TAG:<div class="x">"#), "{}", reported[0].content_preview);
+    }
+
+    #[test]
+    fn reports_added_and_removed_children_with_their_lines() {
+        let a = "<ul>\n<li>a</li>\n<li>b</li>\n<li>c</li>\n</ul>";
+        let b = "<ul>\n<li>a</li>\n<li>c</li>\n<!-- note -->\n<li>d</li>\n</ul>";
+        let reported = diff_dom_trees(&tree(a), &tree(b));
+        let summary: Vec<_> = reported.iter().map(|c| (c.change_type.as_str(), c.path.as_str(), c.line_a, c.line_b)).collect();
+        assert_eq!(
+            summary,
+            [
+                ("removed", "/ul[1]/li[2]", Some(3), None),
+                ("added", "/ul[1]/comment()[1]", None, Some(4)),
+                ("added", "/ul[1]/li[3]", None, Some(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_descend_into_identical_subtrees() {
+        let a = tree("<main><article><p>one</p></article><aside>two</aside></main>");
+        let mut b = tree("<main><article><p>one</p></article><aside>2</aside></main>");
+
+        // Corrupt a node inside the identical <article>; only a walk that ignores
+        // the matching subtree hash could notice
+        let article = &mut b.children[0].children[0];
+        article.children[0].label = "TAG:<section>".to_string();
+        assert_eq!(article.hash, a.children[0].children[0].hash);
+
+        assert_eq!(
+            diff_dom_trees(&a, &b).into_iter().map(|c| c.path).collect::<Vec<_>>(),
+            ["/main[1]/aside[1]/text()[1]"]
+        );
+    }
+
+    #[test]
+    fn aligns_identical_anchors_then_pairs_gaps_by_kind_and_name() {
+        let a = [with_tag(1), with_name("div", "x"), with_tag(2), with_name("em", "y"), with_tag(3)];
+        let b = [with_tag(1), with_name("span", "z"), with_name("div", "x2"), with_tag(3), with_name("em", "w")];
+        use ChildAlignment::*;
+        assert_eq!(
+            align_children(&a, &b),
+            [Identical(0, 0), Added(1), Changed(1, 2), Removed(2), Removed(3), Identical(4, 3), Added(4)]
+        );
+
+        // Within a gap, skipped nodes on the right are additions before the pairing
+        let a = [with_name("em", "1")];
+        let b = [with_name("b", "1"), with_name("i", "1"), with_name("em", "2"), with_name("b", "2")];
+        assert_eq!(align_children(&a, &b), [Added(0), Added(1), Changed(0, 2), Added(3)]);
+
+        assert_eq!(align_children(&a, &[]), [Removed(0)]);
+        assert_eq!(align_children(&[], &a), [Added(0)]);
+    }
+
+    #[test]
+    fn masked_subtrees_become_leaves() {
+        let rules = IgnoreRules::new(&crate::IgnoreConfig {
+            selectors: vec!["div.ad".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let a = build_dom_tree(r#"<div class="ad"><img src="1.png"><p>x</p></div><p>y</p>"#, &rules);
+        let b = build_dom_tree(r#"<div class="ad"><a>buy</a></div><p>y</p>"#, &rules);
+        assert!(a.children[0].is_leaf());
+        assert_eq!(a.hash, b.hash);
+        assert_eq!(a.children[1].children.len(), 1);
+    }
+}
@@ -0,0 +1,796 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use serde::{Deserialize, Serialize};
+
+use crate::dom::{DomNode, DomNodeKind, node_step};
+use crate::sequence::lcs_pairs;
+use crate::tokenizer::Attribute;
+
+// Tree edit scripts between two DOM Merkle trees.
+//
+// Nodes are matched GumTree-style: identical subtrees first by Merkle hash
+// (largest first), then elements that share most of their matched
+// descendants, then leftover children of matched parents by kind and tag.
+// The script is derived from that matching with Chawathe et al.'s algorithm,
+// so applying the operations in order to version A yields version B.
+
+// Minimum share of matched descendants for two elements to be matched
+const MIN_DICE: f64 = 0.5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditOperation {
+    pub change_type: String,       // "insert", "delete", "update_attribute", "update_text", "move"
+    pub path: String,              // target node, located in the tree as edited by the preceding operations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,    // insert/move: new parent, located after a moved node is detached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,   // insert/move: index among the new parent's children
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,   // insert: normalized token of the new node, e.g. `TAG:<li class="new">`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribute: Option<String>, // update_attribute: attribute name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_value: Option<String>, // updates: previous value, absent for a new attribute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_value: Option<String>, // updates: new value, absent for a removed attribute
+    pub line_a: Option<usize>,
+    pub line_b: Option<usize>,
+}
+
+// An edit script together with the matching statistics behind it
+#[derive(Debug, Clone)]
+pub struct EditScript {
+    pub operations: Vec<EditOperation>,
+    pub nodes_a: usize,            // nodes below the document in each version
+    pub nodes_b: usize,
+    pub unchanged: usize,          // matched nodes neither updated nor moved
+}
+
+impl EditScript {
+    // Share of nodes, in either version, that are not matched unchanged
+    pub fn difference_percent(&self) -> f64 {
+        let total = self.nodes_a + self.nodes_b;
+        if total == 0 {
+            0.0
+        } else {
+            ((total - 2 * self.unchanged) as f64 / total as f64) * 100.0
+        }
+    }
+}
+
+// Compute the edit script turning `a` into `b`
+pub fn edit_script(a: &DomNode, b: &DomNode) -> EditScript {
+    let tree_a = FlatTree::new(a);
+    let tree_b = FlatTree::new(b);
+    let matching = match_trees(&tree_a, &tree_b);
+    ScriptBuilder::new(&tree_a, &tree_b, matching).build()
+}
+
+// A DOM tree in preorder, so every subtree occupies a contiguous id range
+struct FlatTree<'a> {
+    nodes: Vec<&'a DomNode>,
+    parent: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    size: Vec<usize>,
+}
+
+impl<'a> FlatTree<'a> {
+    fn new(root: &'a DomNode) -> Self {
+        let mut tree = FlatTree {
+            nodes: Vec::new(),
+            parent: Vec::new(),
+            children: Vec::new(),
+            size: Vec::new(),
+        };
+        tree.push(root, None);
+        for id in (0..tree.len()).rev() {
+            tree.size[id] = 1 + tree.children[id].iter().map(|&c| tree.size[c]).sum::<usize>();
+        }
+        tree
+    }
+
+    fn push(&mut self, node: &'a DomNode, parent: Option<usize>) {
+        let id = self.nodes.len();
+        self.nodes.push(node);
+        self.parent.push(parent);
+        self.children.push(Vec::new());
+        self.size.push(0);
+        if let Some(p) = parent {
+            self.children[p].push(id);
+        }
+        for child in &node.children {
+            self.push(child, Some(id));
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn parent_label(&self, id: usize) -> Option<&str> {
+        self.parent[id].map(|p| self.nodes[p].label.as_str())
+    }
+
+    fn key(&self, id: usize) -> (DomNodeKind, &str) {
+        (self.nodes[id].kind, self.nodes[id].name.as_str())
+    }
+}
+
+struct Matching {
+    a_to_b: Vec<Option<usize>>,
+    b_to_a: Vec<Option<usize>>,
+}
+
+impl Matching {
+    fn link(&mut self, a: usize, b: usize) {
+        self.a_to_b[a] = Some(b);
+        self.b_to_a[b] = Some(a);
+    }
+
+    // Match two identical subtrees node by node, if none of their nodes is matched yet
+    fn link_subtrees(&mut self, tree_a: &FlatTree, tree_b: &FlatTree, a: usize, b: usize) -> bool {
+        let size = tree_a.size[a];
+        if tree_b.size[b] != size
+            || (a..a + size).any(|i| self.a_to_b[i].is_some())
+            || (b..b + size).any(|j| self.b_to_a[j].is_some())
+        {
+            return false;
+        }
+        for k in 0..size {
+            self.link(a + k, b + k);
+        }
+        true
+    }
+}
+
+fn match_trees(tree_a: &FlatTree, tree_b: &FlatTree) -> Matching {
+    let mut matching = Matching {
+        a_to_b: vec![None; tree_a.len()],
+        b_to_a: vec![None; tree_b.len()],
+    };
+
+    // Top-down: identical subtrees whose hash occurs once on each side
+    match_identical(tree_a, tree_b, &mut matching, true);
+
+    // Bottom-up: an element matches the same-tag element that holds most of its matched descendants
+    for a in (1..tree_a.len()).rev() {
+        if matching.a_to_b[a].is_some() || tree_a.children[a].is_empty() {
+            continue;
+        }
+        let mut common: HashMap<usize, usize> = HashMap::new();
+        for d in a + 1..a + tree_a.size[a] {
+            let mut ancestor = matching.a_to_b[d].and_then(|p| tree_b.parent[p]);
+            while let Some(c) = ancestor {
+                if matching.b_to_a[c].is_none() && tree_b.key(c) == tree_a.key(a) {
+                    *common.entry(c).or_insert(0) += 1;
+                }
+                ancestor = tree_b.parent[c];
+            }
+        }
+        let best = common
+            .into_iter()
+            .map(|(c, n)| (c, 2.0 * n as f64 / (tree_a.size[a] - 1 + tree_b.size[c] - 1) as f64))
+            .filter(|&(_, dice)| dice >= MIN_DICE)
+            .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap_or(std::cmp::Ordering::Equal).then(y.0.cmp(&x.0)));
+        if let Some((c, _)) = best {
+            matching.link(a, c);
+            recover(tree_a, tree_b, &mut matching, a, c);
+        }
+    }
+
+    // Documents always match; then pair whatever is left below matched parents
+    if matching.a_to_b[0].is_none() {
+        matching.link(0, 0);
+    }
+    for a in 0..tree_a.len() {
+        if let Some(b) = matching.a_to_b[a] {
+            recover(tree_a, tree_b, &mut matching, a, b);
+        }
+    }
+
+    // Repeated subtrees that could not be paired by position moved elsewhere
+    match_identical(tree_a, tree_b, &mut matching, false);
+
+    matching
+}
+
+// Match identical subtrees by hash, largest first. With `unique_only`, a hash
+// occurring several times on either side is skipped: such repeats (a `<br>`,
+// the same icon in every list item) are better paired by position under
+// matched parents than guessed here.
+fn match_identical(tree_a: &FlatTree, tree_b: &FlatTree, matching: &mut Matching, unique_only: bool) {
+    let mut by_hash_a: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (id, node) in tree_a.nodes.iter().enumerate() {
+        by_hash_a.entry(node.hash.as_str()).or_default().push(id);
+    }
+    let mut by_hash_b: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (id, node) in tree_b.nodes.iter().enumerate() {
+        by_hash_b.entry(node.hash.as_str()).or_default().push(id);
+    }
+
+    let mut order: Vec<usize> = (0..tree_b.len()).collect();
+    order.sort_by_key(|&b| Reverse(tree_b.size[b]));
+    for b in order {
+        if matching.b_to_a[b].is_some() {
+            continue;
+        }
+        let hash = tree_b.nodes[b].hash.as_str();
+        let Some(same_hash) = by_hash_a.get(hash) else {
+            continue;
+        };
+        let candidates: Vec<usize> = same_hash.iter().copied().filter(|&a| matching.a_to_b[a].is_none()).collect();
+        if candidates.is_empty() {
+            continue;
+        }
+        if unique_only {
+            let duplicates_b = by_hash_b[hash].iter().filter(|&&j| matching.b_to_a[j].is_none()).count();
+            if candidates.len() > 1 || duplicates_b > 1 {
+                continue;
+            }
+        }
+
+        // Prefer a candidate under a parent with the same tag, then the one at the closest relative position
+        let relative = |tree: &FlatTree, id: usize| id as f64 / tree.len() as f64;
+        let best = candidates
+            .into_iter()
+            .min_by(|&x, &y| {
+                let score = |a: usize| {
+                    (
+                        tree_a.parent_label(a) != tree_b.parent_label(b),
+                        (relative(tree_a, a) - relative(tree_b, b)).abs(),
+                    )
+                };
+                score(x).partial_cmp(&score(y)).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .expect("at least one candidate");
+        matching.link_subtrees(tree_a, tree_b, best, b);
+    }
+}
+
+// Match the unmatched children of two matched nodes: identical subtrees in
+// order (LCS of hashes), then same-kind/same-tag nodes between those anchors
+fn recover(tree_a: &FlatTree, tree_b: &FlatTree, matching: &mut Matching, a: usize, b: usize) {
+    let free_a: Vec<usize> = tree_a.children[a].iter().copied().filter(|&c| matching.a_to_b[c].is_none()).collect();
+    let free_b: Vec<usize> = tree_b.children[b].iter().copied().filter(|&c| matching.b_to_a[c].is_none()).collect();
+    if free_a.is_empty() || free_b.is_empty() {
+        return;
+    }
+
+    let hashes_a: Vec<&str> = free_a.iter().map(|&c| tree_a.nodes[c].hash.as_str()).collect();
+    let hashes_b: Vec<&str> = free_b.iter().map(|&c| tree_b.nodes[c].hash.as_str()).collect();
+    let anchors = lcs_pairs(&hashes_a, &hashes_b);
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for &(ai, bj) in anchors.iter().chain(std::iter::once(&(free_a.len(), free_b.len()))) {
+        let mut next_b = j;
+        for &x in &free_a[i..ai] {
+            if let Some(offset) = free_b[next_b..bj].iter().position(|&y| tree_b.key(y) == tree_a.key(x)) {
+                pairs.push((x, free_b[next_b + offset]));
+                next_b += offset + 1;
+            }
+        }
+        if ai < free_a.len() {
+            pairs.push((free_a[ai], free_b[bj]));
+        }
+        i = ai + 1;
+        j = bj + 1;
+    }
+
+    for (x, y) in pairs {
+        if !matching.link_subtrees(tree_a, tree_b, x, y) {
+            matching.link(x, y);
+            recover(tree_a, tree_b, matching, x, y);
+        }
+    }
+}
+
+// Node of the tree being edited: a copy of version A plus inserted nodes
+struct WorkNode {
+    kind: DomNodeKind,
+    name: String,
+    attributes: Vec<Attribute<'static>>,
+    label: String,
+    line_a: Option<usize>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+struct ScriptBuilder<'t, 'a> {
+    tree_b: &'t FlatTree<'a>,
+    work: Vec<WorkNode>,
+    w_to_b: Vec<Option<usize>>,
+    b_to_w: Vec<Option<usize>>,
+    in_order_w: Vec<bool>,
+    in_order_b: Vec<bool>,
+    changed: Vec<bool>,            // updated or moved
+    operations: Vec<EditOperation>,
+    nodes_a: usize,
+}
+
+impl<'t, 'a> ScriptBuilder<'t, 'a> {
+    fn new(tree_a: &FlatTree, tree_b: &'t FlatTree<'a>, matching: Matching) -> Self {
+        let work = (0..tree_a.len())
+            .map(|id| {
+                let node = tree_a.nodes[id];
+                WorkNode {
+                    kind: node.kind,
+                    name: node.name.clone(),
+                    attributes: node.attributes.clone(),
+                    label: node.label.clone(),
+                    line_a: Some(node.line_number),
+                    parent: tree_a.parent[id],
+                    children: tree_a.children[id].clone(),
+                }
+            })
+            .collect();
+        ScriptBuilder {
+            tree_b,
+            work,
+            w_to_b: matching.a_to_b,
+            b_to_w: matching.b_to_a,
+            in_order_w: vec![false; tree_a.len()],
+            in_order_b: vec![false; tree_b.len()],
+            changed: vec![false; tree_a.len()],
+            operations: Vec::new(),
+            nodes_a: tree_a.len() - 1,
+        }
+    }
+
+    fn build(mut self) -> EditScript {
+        let tree_b = self.tree_b;
+
+        // Inserts, updates and moves, visiting version B breadth-first
+        let mut queue = VecDeque::from([0]);
+        while let Some(x) = queue.pop_front() {
+            queue.extend(tree_b.children[x].iter().copied());
+            let w = match (tree_b.parent[x], self.b_to_w[x]) {
+                (None, w) => w.expect("documents are matched"),
+                (Some(y), None) => {
+                    let z = self.b_to_w[y].expect("parent is placed before its children");
+                    self.insert(x, z)
+                }
+                (Some(y), Some(w)) => {
+                    let z = self.b_to_w[y].expect("parent is placed before its children");
+                    if self.work[w].label != tree_b.nodes[x].label {
+                        self.update(w, x);
+                    }
+                    if self.work[w].parent != Some(z) {
+                        let k = self.find_position(x);
+                        self.move_node(w, x, z, k);
+                    }
+                    w
+                }
+            };
+            self.in_order_w[w] = true;
+            self.in_order_b[x] = true;
+            self.align_children(w, x);
+        }
+
+        // Deletes, children before their parents
+        let mut postorder = Vec::new();
+        let mut stack = vec![(0, false)];
+        while let Some((w, expanded)) = stack.pop() {
+            if expanded {
+                postorder.push(w);
+            } else {
+                stack.push((w, true));
+                stack.extend(self.work[w].children.iter().rev().map(|&c| (c, false)));
+            }
+        }
+        for w in postorder {
+            if self.w_to_b[w].is_none() {
+                self.operations.push(EditOperation {
+                    line_a: self.work[w].line_a,
+                    ..EditOperation::new("delete", self.path(w))
+                });
+                self.detach(w);
+            }
+        }
+
+        let unchanged = (1..self.nodes_a + 1).filter(|&w| self.w_to_b[w].is_some() && !self.changed[w]).count();
+        EditScript {
+            operations: self.operations,
+            nodes_a: self.nodes_a,
+            nodes_b: tree_b.len() - 1,
+            unchanged,
+        }
+    }
+
+    fn insert(&mut self, x: usize, z: usize) -> usize {
+        let node = self.tree_b.nodes[x];
+        let k = self.find_position(x);
+        let w = self.work.len();
+        self.work.push(WorkNode {
+            kind: node.kind,
+            name: node.name.clone(),
+            attributes: node.attributes.clone(),
+            label: node.label.clone(),
+            line_a: None,
+            parent: Some(z),
+            children: Vec::new(),
+        });
+        self.work[z].children.insert(k, w);
+        self.w_to_b.push(Some(x));
+        self.b_to_w[x] = Some(w);
+        self.in_order_w.push(false);
+        self.changed.push(true);
+
+        self.operations.push(EditOperation {
+            parent: Some(self.path(z)),
+            position: Some(k),
+            content: Some(node.label.clone()),
+            line_b: Some(node.line_number),
+            ..EditOperation::new("insert", self.path(w))
+        });
+        w
+    }
+
+    fn update(&mut self, w: usize, x: usize) {
+        let node = self.tree_b.nodes[x];
+        let path = self.path(w);
+        let line_a = self.work[w].line_a;
+        let line_b = Some(node.line_number);
+
+        if node.kind == DomNodeKind::Element {
+            for (attribute, old_value, new_value) in attribute_changes(&self.work[w].attributes, &node.attributes) {
+                self.operations.push(EditOperation {
+                    attribute: Some(attribute),
+                    old_value,
+                    new_value,
+                    line_a,
+                    line_b,
+                    ..EditOperation::new("update_attribute", path.clone())
+                });
+            }
+        } else {
+            self.operations.push(EditOperation {
+                old_value: Some(self.work[w].label.clone()),
+                new_value: Some(node.label.clone()),
+                line_a,
+                line_b,
+                ..EditOperation::new("update_text", path)
+            });
+        }
+
+        let work = &mut self.work[w];
+        work.label = node.label.clone();
+        work.attributes = node.attributes.clone();
+        self.changed[w] = true;
+    }
+
+    fn move_node(&mut self, w: usize, x: usize, z: usize, mut k: usize) {
+        let path = self.path(w);
+        let old_parent = self.work[w].parent;
+        let old_index = self.detach(w);
+        if old_parent == Some(z) && old_index < k {
+            k -= 1;
+        }
+        self.work[z].children.insert(k, w);
+        self.work[w].parent = Some(z);
+        self.changed[w] = true;
+
+        self.operations.push(EditOperation {
+            parent: Some(self.path(z)),
+            position: Some(k),
+            line_a: self.work[w].line_a,
+            line_b: Some(self.tree_b.nodes[x].line_number),
+            ..EditOperation::new("move", path)
+        });
+    }
+
+    // Remove a node from its parent's children, returning its former index
+    fn detach(&mut self, w: usize) -> usize {
+        let parent = self.work[w].parent.expect("document is never detached");
+        let siblings = &mut self.work[parent].children;
+        let index = siblings.iter().position(|&c| c == w).expect("child of its parent");
+        siblings.remove(index);
+        index
+    }
+
+    // Reorder the matched children of `w` to follow `x`, moving only those
+    // outside the longest common subsequence
+    fn align_children(&mut self, w: usize, x: usize) {
+        let tree_b = self.tree_b;
+        for &c in &self.work[w].children {
+            self.in_order_w[c] = false;
+        }
+        for &c in &tree_b.children[x] {
+            self.in_order_b[c] = false;
+        }
+
+        let partners_a: Vec<usize> = self.work[w]
+            .children
+            .iter()
+            .filter_map(|&c| self.w_to_b[c])
+            .filter(|&p| tree_b.parent[p] == Some(x))
+            .collect();
+        let partners_b: Vec<usize> = tree_b.children[x]
+            .iter()
+            .copied()
+            .filter(|&c| self.b_to_w[c].is_some_and(|p| self.work[p].parent == Some(w)))
+            .collect();
+        for (i, _) in lcs_pairs(&partners_a, &partners_b) {
+            let b = partners_a[i];
+            self.in_order_b[b] = true;
+            self.in_order_w[self.b_to_w[b].expect("matched")] = true;
+        }
+
+        for b in partners_b {
+            if !self.in_order_b[b] {
+                let a = self.b_to_w[b].expect("matched");
+                let k = self.find_position(b);
+                self.move_node(a, b, w, k);
+                self.in_order_w[a] = true;
+                self.in_order_b[b] = true;
+            }
+        }
+    }
+
+    // Index in the edited tree at which the partner of B node `x` belongs:
+    // right after the partner of its nearest in-order left sibling
+    fn find_position(&self, x: usize) -> usize {
+        let tree_b = self.tree_b;
+        let Some(y) = tree_b.parent[x] else {
+            return 0;
+        };
+        let siblings = &tree_b.children[y];
+        if siblings.iter().find(|&&c| self.in_order_b[c]) == Some(&x) {
+            return 0;
+        }
+        let index = siblings.iter().position(|&c| c == x).expect("child of its parent");
+        let Some(&v) = siblings[..index].iter().rev().find(|&&c| self.in_order_b[c]) else {
+            return 0;
+        };
+        let u = self.b_to_w[v].expect("in-order nodes are matched");
+        let parent = self.work[u].parent.expect("in-order nodes have a parent");
+        self.work[parent].children.iter().position(|&c| c == u).expect("child of its parent") + 1
+    }
+
+    // XPath-style path of a node in the tree as currently edited
+    fn path(&self, w: usize) -> String {
+        let mut steps = Vec::new();
+        let mut node = w;
+        while let Some(parent) = self.work[node].parent {
+            let key = (self.work[node].kind, self.work[node].name.as_str());
+            let mut n = 0;
+            for &c in &self.work[parent].children {
+                if (self.work[c].kind, self.work[c].name.as_str()) == key {
+                    n += 1;
+                }
+                if c == node {
+                    break;
+                }
+            }
+            steps.push(node_step(key.0, key.1, n));
+            node = parent;
+        }
+        if steps.is_empty() {
+            return "/".to_string();
+        }
+        steps.iter().rev().map(|s| format!("/{}", s)).collect()
+    }
+}
+
+impl EditOperation {
+    fn new(change_type: &str, path: String) -> Self {
+        EditOperation {
+            change_type: change_type.to_string(),
+            path,
+            parent: None,
+            position: None,
+            content: None,
+            attribute: None,
+            old_value: None,
+            new_value: None,
+            line_a: None,
+            line_b: None,
+        }
+    }
+}
+
+// Added, removed and changed attributes as (name, old value, new value), by name
+fn attribute_changes(old: &[Attribute<'_>], new: &[Attribute<'_>]) -> Vec<(String, Option<String>, Option<String>)> {
+    let value = |attributes: &[Attribute<'_>], name: &str| {
+        attributes
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.value.as_deref().unwrap_or_default().to_string())
+    };
+    let mut names: Vec<&str> = old.iter().chain(new).map(|a| a.name.as_ref()).collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (old_value, new_value) = (value(old, name), value(new, name));
+            (old_value != new_value).then(|| (name.to_string(), old_value, new_value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    use super::*;
+    use crate::dom::build_dom_tree;
+    use crate::ignore::IgnoreRules;
+    use crate::tokenizer::tokenize;
+
+    // A mutable tree to replay scripts on; elements keep their attributes by
+    // name, other nodes their normalized label
+    #[derive(Debug, Clone, PartialEq)]
+    struct Node {
+        kind: DomNodeKind,
+        name: String,
+        attributes: BTreeMap<String, String>,
+        label: String,
+        children: Vec<Node>,
+    }
+
+    // Attributes as read back from a normalized start tag, e.g. `TAG:<li class="a">`
+    fn attributes_of(label: &str) -> BTreeMap<String, String> {
+        let tag = label.strip_prefix("TAG:").unwrap_or(label);
+        let tokens = tokenize(tag);
+        tokens
+            .first()
+            .map(|t| t.attributes.iter().map(|a| (a.name.to_string(), a.value.as_deref().unwrap_or("").to_string())).collect())
+            .unwrap_or_default()
+    }
+
+    fn node(dom: &DomNode) -> Node {
+        let element = dom.kind == DomNodeKind::Element;
+        Node {
+            kind: dom.kind,
+            name: dom.name.clone(),
+            attributes: if element { attributes_of(&dom.label) } else { BTreeMap::new() },
+            label: if element { String::new() } else { dom.label.clone() },
+            children: dom.children.iter().map(node).collect(),
+        }
+    }
+
+    fn step_of(node: &Node, n: usize) -> String {
+        node_step(node.kind, &node.name, n)
+    }
+
+    // Child indexes from the root along an XPath-style path
+    fn resolve(root: &Node, path: &str) -> Vec<usize> {
+        let mut indexes = Vec::new();
+        let mut current = root;
+        for step in path.split('/').filter(|s| !s.is_empty()) {
+            let mut counters: HashMap<(DomNodeKind, &str), usize> = HashMap::new();
+            let index = current
+                .children
+                .iter()
+                .position(|child| {
+                    let n = counters.entry((child.kind, child.name.as_str())).or_insert(0);
+                    *n += 1;
+                    step_of(child, *n) == step
+                })
+                .unwrap_or_else(|| panic!("no node at {path}"));
+            indexes.push(index);
+            current = &current.children[index];
+        }
+        indexes
+    }
+
+    fn at<'n>(root: &'n mut Node, indexes: &[usize]) -> &'n mut Node {
+        indexes.iter().fold(root, |node, &i| &mut node.children[i])
+    }
+
+    fn detach(root: &mut Node, path: &str) -> Node {
+        let indexes = resolve(root, path);
+        let (last, parent) = indexes.split_last().expect("document is never detached");
+        at(root, parent).children.remove(*last)
+    }
+
+    fn attach(root: &mut Node, op: &EditOperation, child: Node) {
+        let parent = resolve(root, op.parent.as_deref().expect("parent"));
+        at(root, &parent).children.insert(op.position.expect("position"), child);
+    }
+
+    fn apply(root: &mut Node, script: &EditScript) {
+        for op in &script.operations {
+            match op.change_type.as_str() {
+                "insert" => {
+                    let content = op.content.clone().expect("content");
+                    let step = op.path.rsplit('/').next().unwrap();
+                    let kind = match step {
+                        s if s.starts_with("text()") => DomNodeKind::Text,
+                        s if s.starts_with("comment()") => DomNodeKind::Comment,
+                        s if s.starts_with("doctype()") => DomNodeKind::Doctype,
+                        _ => DomNodeKind::Element,
+                    };
+                    let element = kind == DomNodeKind::Element;
+                    let inserted = Node {
+                        kind,
+                        name: if element { step.split('[').next().unwrap().to_string() } else { String::new() },
+                        attributes: if element { attributes_of(&content) } else { BTreeMap::new() },
+                        label: if element { String::new() } else { content },
+                        children: Vec::new(),
+                    };
+                    attach(root, op, inserted);
+                }
+                "delete" => {
+                    let removed = detach(root, &op.path);
+                    assert!(removed.children.is_empty(), "{} deleted before its children", op.path);
+                }
+                "move" => {
+                    let moved = detach(root, &op.path);
+                    attach(root, op, moved);
+                }
+                "update_text" => {
+                    let target = at(root, &resolve(root, &op.path));
+                    assert_eq!(Some(&target.label), op.old_value.as_ref());
+                    target.label = op.new_value.clone().expect("new value");
+                }
+                "update_attribute" => {
+                    let target = at(root, &resolve(root, &op.path));
+                    let name = op.attribute.clone().expect("attribute");
+                    assert_eq!(target.attributes.get(&name), op.old_value.as_ref());
+                    match &op.new_value {
+                        Some(value) => target.attributes.insert(name, value.clone()),
+                        None => target.attributes.remove(&name),
+                    };
+                }
+                other => panic!("unknown operation {other}"),
+            }
+        }
+    }
+
+    fn assert_script_rebuilds(html_a: &str, html_b: &str) -> EditScript {
+        let rules = IgnoreRules::default();
+        let (a, b) = (build_dom_tree(html_a, &rules), build_dom_tree(html_b, &rules));
+        let script = edit_script(&a, &b);
+        let mut edited = node(&a);
+        apply(&mut edited, &script);
+        assert!(edited == node(&b), "script did not turn A into B: {:#?}", script.operations);
+        script
+    }
+
+    #[test]
+    fn script_turns_a_into_b() {
+        let script = assert_script_rebuilds(
+            r#"<ul id="l"><li>a</li><li>b</li><li>c</li></ul><p class="x">old</p><div><span>gone</span></div>"#,
+            r#"<p class="y" title="t">new</p><ul id="l"><li>c</li><li>a</li><li>b</li><li>d</li></ul><div></div>"#,
+        );
+        let kinds: Vec<_> = script.operations.iter().map(|op| op.change_type.as_str()).collect();
+        for kind in ["insert", "delete", "move", "update_text", "update_attribute"] {
+            assert!(kinds.contains(&kind), "no {kind} in {kinds:?}");
+        }
+    }
+
+    #[test]
+    fn identical_trees_need_no_operations() {
+        let html = "<div><p>one</p><p>two</p></div>";
+        let script = assert_script_rebuilds(html, html);
+        assert!(script.operations.is_empty());
+        assert_eq!(script.difference_percent(), 0.0);
+    }
+
+    #[test]
+    fn moves_between_parents_and_nested_inserts() {
+        assert_script_rebuilds(
+            "<section><h2>A</h2><p>1</p></section><section><h2>B</h2></section>",
+            "<section><h2>A</h2></section><section><h2>B</h2><p>1</p><ol><li>new<b>deep</b></li></ol></section>",
+        );
+        assert_script_rebuilds("<p>x</p>", "");
+        assert_script_rebuilds("", "<p>x<!-- c --></p>");
+    }
+
+    #[test]
+    fn script_rebuilds_every_snapshot_pair() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots");
+        let pages: Vec<String> = ["v1", "v2", "v3", "v4", "v5", "v10"]
+            .iter()
+            .map(|v| std::fs::read_to_string(dir.join(format!("{v}.html"))).unwrap())
+            .collect();
+        for a in &pages {
+            for b in &pages {
+                assert_script_rebuilds(a, b);
+            }
+        }
+    }
+}
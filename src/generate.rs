@@ -0,0 +1,101 @@
+use crate::diff::ComparisonResult;
+use crate::differ::DomDiffer;
+
+pub fn generate_random_dom_with_changes(base_content: &str, version: usize) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    // Create a pseudo-random seed from version number
+    let mut hasher = DefaultHasher::new();
+    version.hash(&mut hasher);
+    let seed = hasher.finish();
+
+    let mut modified = base_content.to_string();
+
+    // Random small changes based on seeded pseudo-randomness
+    let changes = [
+        // Add random attributes
+        (seed.is_multiple_of(7), format!(" data-rand='{}'", seed % 1000)),
+        // Add random classes
+        (seed.is_multiple_of(11), format!(" class='gen-{}'", seed % 100)),
+        // Add random IDs
+        (seed.is_multiple_of(13), format!(" id='elem-{}'", seed % 500)),
+        // Add random text nodes
+        (seed.is_multiple_of(17), format!("Random text {}", seed % 50)),
+        // Add random comments
+        (seed.is_multiple_of(19), format!("<!-- Random comment {} -->", seed % 200)),
+    ];
+
+    // Apply random changes
+    for (should_apply, change) in &changes {
+        if *should_apply {
+            // Insert change at random position based on seed
+            let insertion_point = (seed as usize) % modified.len().max(1);
+            modified.insert_str(insertion_point, change);
+        }
+    }
+
+    // Add some structural changes
+    if seed.is_multiple_of(23) {
+        modified.push_str(&format!("<div><span>Generated {}</span></div>", version));
+    }
+
+    if seed.is_multiple_of(29) {
+        modified = modified.replace("</head>", &format!("<meta name='version' content='{}'></head>", version));
+    }
+
+    if seed.is_multiple_of(31) {
+        modified.push_str(&format!("<script>var version = {};</script>", version));
+    }
+
+    // Small text modifications
+    if seed.is_multiple_of(37) {
+        modified = modified.replace("div", &format!("div{}", seed % 10));
+    }
+
+    modified
+}
+
+pub fn generate_random_comparisons(base_content: &str, num_comparisons: usize, differ: &DomDiffer) -> Vec<ComparisonResult> {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut results = Vec::new();
+
+    // First, generate all versions (1-100)
+    let mut versions = Vec::new();
+    versions.push(("base".to_string(), base_content.to_string()));
+
+    for i in 1..=100usize {
+        let version_content = if i.is_multiple_of(7) || i.is_multiple_of(11) {
+            // Keep some versions unchanged
+            base_content.to_string()
+        } else {
+            generate_random_dom_with_changes(base_content, i)
+        };
+        versions.push((format!("v{}", i), version_content));
+    }
+
+    // Generate random comparisons
+    for comparison_idx in 0..num_comparisons {
+        // Create pseudo-random indices based on comparison index
+        let mut hasher = DefaultHasher::new();
+        comparison_idx.hash(&mut hasher);
+        let seed = hasher.finish();
+
+        let idx_a = (seed % versions.len() as u64) as usize;
+        let idx_b = ((seed / versions.len() as u64) % versions.len() as u64) as usize;
+
+        // Skip if comparing same version
+        if idx_a == idx_b {
+            continue;
+        }
+
+        let (name_a, content_a) = &versions[idx_a];
+        let (name_b, content_b) = &versions[idx_b];
+
+        results.push(differ.compare_named(name_a, content_a, name_b, content_b));
+    }
+
+    results
+}
@@ -0,0 +1,355 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use rayon::prelude::*;
+
+use crate::diff::ComparisonResult;
+use crate::differ::DomDiffer;
+use crate::site::PageStatus;
+
+// Comparing committed HTML through the `git` executable of the repository
+// the tool runs in. Changed files are listed with `git diff --name-status`
+// and their contents read with `git cat-file`, straight from the object
+// store, so neither revision needs to be checked out. Renames are reported as
+// a removal and an addition.
+//
+// `git diff` can also call the tool itself (see `ExternalDiff`), once per
+// changed file with both versions written to temporary files.
+
+// Run git with `args`, returning its standard output
+fn git<I, S>(args: I) -> io::Result<Vec<u8>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(io::Error::other(if message.is_empty() { "git failed".to_string() } else { message }));
+    }
+    Ok(output.stdout)
+}
+
+// Whether `rev` names a commit of the current repository
+pub fn is_revision(rev: &str) -> bool {
+    git(["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", rev)]).is_ok()
+}
+
+fn is_html(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("html") || e.eq_ignore_ascii_case("htm"))
+}
+
+// A changed `.html` / `.htm` file, by its path from the repository root
+#[derive(Debug, Clone)]
+pub struct GitChange {
+    pub path: String,
+    pub status: PageStatus,
+}
+
+// HTML files that differ between `rev_a` and `rev_b` (the working tree if
+// `None`), limited to `paths` if any are given
+pub fn changed_html_files(rev_a: &str, rev_b: Option<&str>, paths: &[String]) -> io::Result<Vec<GitChange>> {
+    let mut args = vec!["diff", "--name-status", "-z", "--no-renames", "--no-ext-diff", rev_a];
+    args.extend(rev_b);
+    args.push("--");
+    args.extend(paths.iter().map(String::as_str));
+    let output = git(&args)?;
+    Ok(parse_name_status(&String::from_utf8_lossy(&output)))
+}
+
+// HTML entries of `git diff --name-status -z --no-renames` output, whose
+// fields alternate between status and path
+fn parse_name_status(output: &str) -> Vec<GitChange> {
+    let fields: Vec<&str> = output.split('\0').filter(|f| !f.is_empty()).collect();
+    fields
+        .chunks(2)
+        .filter_map(|pair| match pair {
+            [status, path] if is_html(path) => Some(GitChange {
+                path: path.to_string(),
+                status: match status.chars().next() {
+                    Some('A') => PageStatus::Added,
+                    Some('D') => PageStatus::Removed,
+                    _ => PageStatus::Changed,
+                },
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+// Top-level directory of the current repository's working tree
+pub fn toplevel() -> io::Result<PathBuf> {
+    let output = git(["rev-parse", "--show-toplevel"])?;
+    Ok(PathBuf::from(String::from_utf8_lossy(&output).trim_end()))
+}
+
+// Content of `path` at `rev`, or in the working tree under `toplevel` if `rev` is `None`
+pub fn read_file(rev: Option<&str>, path: &str, toplevel: &Path) -> io::Result<String> {
+    let bytes = match rev {
+        Some(rev) => git(["cat-file", "blob", &format!("{}:{}", rev, path)])?,
+        None => fs::read(toplevel.join(path))?,
+    };
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))
+}
+
+#[derive(Debug, Clone)]
+pub struct GitFileDiff {
+    pub path: String,
+    pub status: PageStatus,
+    pub result: ComparisonResult,
+}
+
+// Compare every changed HTML file between two revisions (or a revision and
+// the working tree) in parallel. An added or removed file is compared
+// against an empty document.
+pub fn diff_revisions(rev_a: &str, rev_b: Option<&str>, paths: &[String], differ: &DomDiffer) -> io::Result<Vec<GitFileDiff>> {
+    let root = toplevel()?;
+    changed_html_files(rev_a, rev_b, paths)?
+        .into_par_iter()
+        .map(|change| {
+            let old = match change.status {
+                PageStatus::Added => String::new(),
+                _ => read_file(Some(rev_a), &change.path, &root)?,
+            };
+            let new = match change.status {
+                PageStatus::Removed => String::new(),
+                _ => read_file(rev_b, &change.path, &root)?,
+            };
+            let label_b = format!("{}:{}", rev_b.unwrap_or("worktree"), change.path);
+            let result = differ.compare_named(&format!("{}:{}", rev_a, change.path), &old, &label_b, &new);
+            Ok(GitFileDiff { path: change.path, status: change.status, result })
+        })
+        .collect()
+}
+
+// One invocation of a `GIT_EXTERNAL_DIFF` program:
+//
+//     path old-file old-hex old-mode new-file new-hex new-mode [new-path rename-info]
+//
+// An added or deleted file is passed as `/dev/null`.
+#[derive(Debug, Clone)]
+pub struct ExternalDiff {
+    pub path: String,
+    pub old_file: String,
+    pub new_file: String,
+    pub new_path: Option<String>,  // set for renames and copies
+}
+
+impl ExternalDiff {
+    // Parse the arguments after the program name. Git exports
+    // `GIT_DIFF_PATH_COUNTER` to external diff programs, which tells them
+    // apart from an ordinary command line.
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        std::env::var_os("GIT_DIFF_PATH_COUNTER")?;
+        Self::parse(args)
+    }
+
+    fn parse(args: &[String]) -> Option<Self> {
+        if args.len() != 7 && args.len() != 9 {
+            return None;
+        }
+        Some(ExternalDiff {
+            path: args[0].clone(),
+            old_file: args[1].clone(),
+            new_file: args[4].clone(),
+            new_path: args.get(7).cloned(),
+        })
+    }
+
+    pub fn status(&self) -> PageStatus {
+        match (self.old_file.as_str(), self.new_file.as_str()) {
+            ("/dev/null", _) => PageStatus::Added,
+            (_, "/dev/null") => PageStatus::Removed,
+            _ => PageStatus::Changed,
+        }
+    }
+
+    pub fn is_html(&self) -> bool {
+        is_html(&self.path) || self.new_path.as_deref().is_some_and(is_html)
+    }
+
+    // Compare both versions, treating `/dev/null` as an empty document
+    pub fn compare(&self, differ: &DomDiffer) -> io::Result<ComparisonResult> {
+        let read = |file: &str| if file == "/dev/null" { Ok(String::new()) } else { fs::read_to_string(file) };
+        let new_path = self.new_path.as_deref().unwrap_or(&self.path);
+        Ok(differ.compare_named(&self.path, &read(&self.old_file)?, new_path, &read(&self.new_file)?))
+    }
+
+    // Git's own diff of a file the DOM comparison does not apply to, with
+    // the temporary file names in its header lines replaced by the real paths
+    pub fn plain_diff(&self) -> io::Result<String> {
+        let output = Command::new("git")
+            .args(["diff", "--no-index", "--no-ext-diff", "--", &self.old_file, &self.new_file])
+            .output()?;
+        // Exit status 1 only means the files differ
+        if !matches!(output.status.code(), Some(0) | Some(1)) {
+            return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(self.relabel(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    // Replace the temporary file names in the `diff --git`, `---` and `+++`
+    // lines (and git's "Binary files ... differ") of each file header. Lines
+    // after a hunk header are content, even if they mention a temporary file.
+    fn relabel(&self, diff: &str) -> String {
+        let new_path = self.new_path.as_deref().unwrap_or(&self.path);
+        let mut relabeled = String::with_capacity(diff.len());
+        let mut in_header = false;
+        for line in diff.split_inclusive('\n') {
+            if line.starts_with("diff --git ") {
+                in_header = true;
+            } else if line.starts_with("@@") {
+                in_header = false;
+            }
+            let names_files = line.starts_with("diff --git ")
+                || line.starts_with("--- ")
+                || line.starts_with("+++ ")
+                || line.starts_with("Binary files ");
+            if in_header && names_files {
+                let mut line = line.to_string();
+                for (file, path) in [(&self.old_file, self.path.as_str()), (&self.new_file, new_path)] {
+                    if file != "/dev/null" {
+                        line = line.replace(file.trim_start_matches('/'), path);
+                    }
+                }
+                relabeled.push_str(&line);
+            } else {
+                relabeled.push_str(line);
+            }
+        }
+        relabeled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn external(path: &str, old_file: &str, new_file: &str, new_path: Option<&str>) -> ExternalDiff {
+        ExternalDiff {
+            path: path.to_string(),
+            old_file: old_file.to_string(),
+            new_file: new_file.to_string(),
+            new_path: new_path.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn parses_name_status_output() {
+        let output = "M\0index.html\0A\0blog/new post.htm\0D\0old.html\0M\0style.css\0T\0PAGE.HTML\0";
+        let changes: Vec<_> = parse_name_status(output).into_iter().map(|c| (c.path, c.status)).collect();
+        assert_eq!(
+            changes,
+            [
+                ("index.html".to_string(), PageStatus::Changed),
+                ("blog/new post.htm".to_string(), PageStatus::Added),
+                ("old.html".to_string(), PageStatus::Removed),
+                ("PAGE.HTML".to_string(), PageStatus::Changed),
+            ]
+        );
+        assert!(parse_name_status("").is_empty());
+    }
+
+    #[test]
+    fn parses_external_diff_arguments() {
+        let modified = ExternalDiff::parse(&args(&[
+            "site/index.html", "/tmp/git-blob-a/index.html", "1111111", "100644",
+            "site/index.html", "2222222", "100644",
+        ]))
+        .unwrap();
+        assert_eq!((modified.path.as_str(), modified.old_file.as_str()), ("site/index.html", "/tmp/git-blob-a/index.html"));
+        assert_eq!(modified.new_file, "site/index.html");
+        assert_eq!((modified.new_path.as_deref(), modified.status()), (None, PageStatus::Changed));
+
+        let renamed = ExternalDiff::parse(&args(&[
+            "old.txt", "/tmp/a", "1111111", "100644", "/tmp/b", "2222222", "100644", "new.html",
+            "similarity index 90%\nrename from old.txt\nrename to new.html\n",
+        ]))
+        .unwrap();
+        assert_eq!(renamed.new_path.as_deref(), Some("new.html"));
+        assert!(renamed.is_html());
+
+        let added = ExternalDiff::parse(&args(&["a.html", "/dev/null", ".", ".", "/tmp/b", "2222222", "100644"])).unwrap();
+        assert_eq!(added.status(), PageStatus::Added);
+        let removed = ExternalDiff::parse(&args(&["a.htm", "/tmp/a", "1111111", "100644", "/dev/null", ".", "."])).unwrap();
+        assert_eq!(removed.status(), PageStatus::Removed);
+        assert!(removed.is_html() && !external("a.css", "x", "y", None).is_html());
+
+        for count in [0, 1, 6, 8, 10] {
+            assert!(ExternalDiff::parse(&vec!["x".to_string(); count]).is_none(), "{count} arguments");
+        }
+    }
+
+    #[test]
+    fn compares_dev_null_as_an_empty_document() {
+        let dir = std::env::temp_dir().join(format!("merkle-domdiff-extdiff-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let page = dir.join("page.html");
+        fs::write(&page, "<p>new page</p>").unwrap();
+        let page = page.display().to_string();
+
+        let added = external("page.html", "/dev/null", &page, None).compare(&DomDiffer::new()).unwrap();
+        assert_eq!((added.total_chunks_a, added.difference_percent), (0, 100.0));
+        let removed = external("page.html", &page, "/dev/null", None).compare(&DomDiffer::new()).unwrap();
+        assert_eq!((removed.total_chunks_b, removed.difference_percent), (0, 100.0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relabels_only_file_header_lines() {
+        let diff = external("docs/notes.txt", "/tmp/XyZ_notes.txt", "/tmp/AbC_notes.txt", None);
+        let output = "diff --git a/tmp/XyZ_notes.txt b/tmp/AbC_notes.txt\n\
+            index 1111111..2222222 100644\n\
+            --- a/tmp/XyZ_notes.txt\n\
+            +++ b/tmp/AbC_notes.txt\n\
+            @@ -1,2 +1,2 @@\n\
+            -copied from /tmp/XyZ_notes.txt\n\
+            +copied from /tmp/AbC_notes.txt\n\
+            --- a/tmp/XyZ_notes.txt\n\
+            \x20unchanged\n";
+        assert_eq!(
+            diff.relabel(output),
+            "diff --git a/docs/notes.txt b/docs/notes.txt\n\
+            index 1111111..2222222 100644\n\
+            --- a/docs/notes.txt\n\
+            +++ b/docs/notes.txt\n\
+            @@ -1,2 +1,2 @@\n\
+            -copied from /tmp/XyZ_notes.txt\n\
+            +copied from /tmp/AbC_notes.txt\n\
+            --- a/tmp/XyZ_notes.txt\n\
+            \x20unchanged\n"
+        );
+
+        let renamed = external("old.bin", "/tmp/o", "/tmp/n", Some("new.bin"));
+        assert_eq!(
+            renamed.relabel("diff --git a/tmp/o b/tmp/n\nBinary files a/tmp/o and b/tmp/n differ\n"),
+            "diff --git a/old.bin b/new.bin\nBinary files a/old.bin and b/new.bin differ\n"
+        );
+    }
+
+    #[test]
+    fn plain_diff_keeps_content_that_mentions_the_temporary_files() {
+        let dir = std::env::temp_dir().join(format!("merkle-domdiff-plain-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (old, new) = (dir.join("old_style.css"), dir.join("new_style.css"));
+        let (old_name, new_name) = (old.display().to_string(), new.display().to_string());
+        fs::write(&old, format!("/* {} */\nbody {{}}\n", old_name)).unwrap();
+        fs::write(&new, format!("/* {} */\nbody {{}}\n", new_name)).unwrap();
+
+        let diff = external("css/style.css", &old_name, &new_name, None).plain_diff().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let lines: Vec<&str> = diff.lines().collect();
+        assert_eq!(lines[0], "diff --git a/css/style.css b/css/style.css");
+        assert!(lines.contains(&"--- a/css/style.css") && lines.contains(&"+++ b/css/style.css"), "{diff}");
+        assert!(lines.contains(&format!("-/* {} */", old_name).as_str()), "{diff}");
+        assert!(lines.contains(&format!("+/* {} */", new_name).as_str()), "{diff}");
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use xxhash_rust::xxh3::{xxh3_64, xxh3_128};
+
+use crate::cache::{CacheStats, HashCache};
+
+// Raw digest of up to 32 bytes, kept inline so it can be copied, compared
+// and used as a map key without allocating. Only `ChunkHasher::encode` turns
+// it into the hex strings that are stored and reported.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HashDigest {
+    bytes: [u8; 32],
+    len: u8,
+}
+
+impl HashDigest {
+    pub fn from_slice(raw: &[u8]) -> Self {
+        assert!(raw.len() <= 32, "digest longer than 32 bytes");
+        let mut bytes = [0; 32];
+        bytes[..raw.len()].copy_from_slice(raw);
+        HashDigest { bytes, len: raw.len() as u8 }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    // First eight bytes as a number, e.g. for sketches that need uniform 64-bit values
+    pub fn to_u64(&self) -> u64 {
+        let mut value = [0; 8];
+        value.copy_from_slice(&self.bytes[..8]);
+        u64::from_be_bytes(value)
+    }
+}
+
+impl fmt::Debug for HashDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HashDigest({})", to_hex(self.as_bytes()))
+    }
+}
+
+// A hash function for chunks: raw digest bytes, and the lower-case hex form
+// used everywhere hashes are stored or reported
+pub trait ChunkHasher: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn digest(&self, data: &[u8]) -> HashDigest;
+
+    fn encode(&self, digest: &HashDigest) -> String {
+        to_hex(digest.as_bytes())
+    }
+
+    fn hash(&self, data: &[u8]) -> String {
+        self.encode(&self.digest(data))
+    }
+}
+
+pub struct Xxh3_64Hasher;
+pub struct Xxh3_128Hasher;
+pub struct Sha256Hasher;
+pub struct Blake3Hasher;
+
+impl ChunkHasher for Xxh3_64Hasher {
+    fn name(&self) -> &'static str {
+        "xxh3_64"
+    }
+
+    fn digest(&self, data: &[u8]) -> HashDigest {
+        HashDigest::from_slice(&xxh3_64(data).to_be_bytes())
+    }
+}
+
+impl ChunkHasher for Xxh3_128Hasher {
+    fn name(&self) -> &'static str {
+        "xxh3_128"
+    }
+
+    fn digest(&self, data: &[u8]) -> HashDigest {
+        HashDigest::from_slice(&xxh3_128(data).to_be_bytes())
+    }
+}
+
+impl ChunkHasher for Sha256Hasher {
+    fn name(&self) -> &'static str {
+        "sha256"
+    }
+
+    fn digest(&self, data: &[u8]) -> HashDigest {
+        HashDigest::from_slice(&Sha256::digest(data))
+    }
+}
+
+impl ChunkHasher for Blake3Hasher {
+    fn name(&self) -> &'static str {
+        "blake3"
+    }
+
+    fn digest(&self, data: &[u8]) -> HashDigest {
+        HashDigest::from_slice(blake3::hash(data).as_bytes())
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Bytes of an even-length hex string, None if it is not one
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+// Selectable chunk hash. xxh3 is fast but not collision resistant against a
+// deliberate attacker; use SHA-256 or BLAKE3 when hashes serve as evidence
+// (snapshots, inclusion proofs).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    #[default]
+    Xxh3_64,
+    Xxh3_128,
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn hasher(&self) -> &'static dyn ChunkHasher {
+        match self {
+            HashAlgorithm::Xxh3_64 => &Xxh3_64Hasher,
+            HashAlgorithm::Xxh3_128 => &Xxh3_128Hasher,
+            HashAlgorithm::Sha256 => &Sha256Hasher,
+            HashAlgorithm::Blake3 => &Blake3Hasher,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.hasher().name()
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xxh3_64" => Ok(HashAlgorithm::Xxh3_64),
+            "xxh3_128" => Ok(HashAlgorithm::Xxh3_128),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            _ => Err(format!("unknown hash '{}' (expected xxh3_64, xxh3_128, sha256 or blake3)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PerformanceConfig {
+    pub use_parallel_hashing: bool,
+    pub hash_algorithm: HashAlgorithm,
+    pub tree_version: u32,         // node hashing scheme of new Merkle trees, see merkle::TREE_VERSION
+    pub use_hash_cache: bool,      // reuse hashes of repeated chunks (off by default)
+    pub hash_cache_capacity: usize,
+}
+
+impl Default for PerformanceConfig {
+    fn default() -> Self {
+        PerformanceConfig {
+            use_parallel_hashing: true,
+            hash_algorithm: HashAlgorithm::default(),
+            tree_version: crate::merkle::TREE_VERSION,
+            use_hash_cache: false,
+            hash_cache_capacity: 10_000,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PERF_CONFIG: RwLock<PerformanceConfig> = RwLock::new(PerformanceConfig::default());
+    // Present while `use_hash_cache` is set; replaced when the capacity changes
+    static ref HASH_CACHE: RwLock<Option<Arc<HashCache>>> = RwLock::new(None);
+}
+
+pub fn get_perf_config() -> PerformanceConfig {
+    PERF_CONFIG.read().unwrap().clone()
+}
+
+pub fn set_perf_config(config: PerformanceConfig) {
+    let mut current = PERF_CONFIG.write().unwrap();
+    if config.use_hash_cache != current.use_hash_cache || config.hash_cache_capacity != current.hash_cache_capacity {
+        *HASH_CACHE.write().unwrap() = config
+            .use_hash_cache
+            .then(|| Arc::new(HashCache::new(config.hash_cache_capacity)));
+    }
+    *current = config;
+}
+
+fn hash_cache() -> Option<Arc<HashCache>> {
+    HASH_CACHE.read().unwrap().clone()
+}
+
+// Hit and miss counters of the hash cache, if it is enabled
+pub fn hash_cache_stats() -> Option<CacheStats> {
+    hash_cache().map(|cache| cache.stats())
+}
+
+// Fast non-cryptographic hash for performance
+pub fn hash_chunk_fast(chunk: &str) -> String {
+    Xxh3_64Hasher.hash(chunk.as_bytes())
+}
+
+
+// Hash with the configured algorithm
+pub fn hash_chunk(chunk: &str) -> String {
+    hash_chunk_with(get_perf_config().hash_algorithm, chunk)
+}
+
+// Hash with a given algorithm, through the cache if it is enabled
+pub fn hash_chunk_with(algorithm: HashAlgorithm, chunk: &str) -> String {
+    hash_cached(hash_cache().as_deref(), algorithm, chunk)
+}
+
+fn hash_cached(cache: Option<&HashCache>, algorithm: HashAlgorithm, chunk: &str) -> String {
+    match cache {
+        Some(cache) => cache.get_or_insert_with(algorithm, chunk, || algorithm.hasher().hash(chunk.as_bytes())),
+        None => algorithm.hasher().hash(chunk.as_bytes()),
+    }
+}
+
+
+// Configurable chunk hashing (parallel or sequential)
+pub fn hash_chunks(chunks: &[String]) -> Vec<String> {
+    let config = get_perf_config();
+    let algorithm = config.hash_algorithm;
+    let cache = hash_cache();
+    if config.use_parallel_hashing {
+        chunks.par_iter().map(|c| hash_cached(cache.as_deref(), algorithm, c)).collect()
+    } else {
+        chunks.iter().map(|c| hash_cached(cache.as_deref(), algorithm, c)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [HashAlgorithm; 4] =
+        [HashAlgorithm::Xxh3_64, HashAlgorithm::Xxh3_128, HashAlgorithm::Sha256, HashAlgorithm::Blake3];
+
+    #[test]
+    fn known_answers() {
+        let cases = [
+            (HashAlgorithm::Xxh3_64, "", "2d06800538d394c2"),
+            (HashAlgorithm::Xxh3_128, "", "99aa06d3014798d86001c324468d497f"),
+            (HashAlgorithm::Sha256, "", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (HashAlgorithm::Sha256, "abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (HashAlgorithm::Blake3, "", "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+            (HashAlgorithm::Blake3, "abc", "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+        ];
+        for (algorithm, input, expected) in cases {
+            assert_eq!(algorithm.hasher().hash(input.as_bytes()), expected, "{} of {:?}", algorithm.as_str(), input);
+            assert_eq!(hash_cached(None, algorithm, input), expected);
+        }
+        assert_eq!(hash_chunk_fast(""), "2d06800538d394c2");
+    }
+
+    #[test]
+    fn digests_are_fixed_width_hex() {
+        // Inputs whose xxh3_64 digest starts with zero bits must still be padded
+        let small = (0..)
+            .map(|i| format!("chunk {}", i))
+            .find(|chunk| xxh3_64(chunk.as_bytes()) < 1 << 56)
+            .unwrap();
+        for algorithm in ALGORITHMS {
+            let width = algorithm.hasher().digest(b"").as_bytes().len() * 2;
+            assert_eq!(width, [16, 32, 64, 64][algorithm as usize]);
+            for chunk in ["", "TAG:<div>", small.as_str()] {
+                let hex = algorithm.hasher().hash(chunk.as_bytes());
+                assert_eq!(hex.len(), width, "{} of {:?}: {}", algorithm.as_str(), chunk, hex);
+                assert!(hex.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)));
+                assert_eq!(from_hex(&hex).unwrap(), algorithm.hasher().digest(chunk.as_bytes()).as_bytes());
+            }
+        }
+        assert!(hash_chunk_fast(&small).starts_with("00"));
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for algorithm in ALGORITHMS {
+            assert_eq!(algorithm.as_str().parse::<HashAlgorithm>(), Ok(algorithm));
+            let json = serde_json::to_string(&algorithm).unwrap();
+            assert_eq!(json, format!("\"{}\"", algorithm.as_str()));
+        }
+        assert!("md5".parse::<HashAlgorithm>().unwrap_err().contains("unknown hash 'md5'"));
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(to_hex(&[0, 1, 0xab, 0xff]), "0001abff");
+        assert_eq!(from_hex("0001abff"), Some(vec![0, 1, 0xab, 0xff]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
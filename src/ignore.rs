@@ -0,0 +1,209 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::selector::{Selector, SelectorElement};
+
+// Placeholder that replaces masked attribute values, value matches and subtrees
+pub const MASK: &str = "*";
+
+// Ignore configuration as written in JSON:
+//
+//     {
+//       "selectors": ["script#__NEXT_DATA__", "[data-timestamp]"],
+//       "attributes": ["nonce", "data-react*", "csrf-*"],
+//       "values": ["/_next/static/[A-Za-z0-9_-]{8,}/", "\\d{4}-\\d{2}-\\d{2}T[\\d:.]+Z?"]
+//     }
+//
+// `selectors` mask whole element subtrees, `attributes` are glob patterns on
+// attribute names whose values are masked, and `values` are regexes whose
+// matches are masked inside attribute values and text.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IgnoreConfig {
+    #[serde(default)]
+    pub selectors: Vec<String>,
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+// Compiled form of an `IgnoreConfig`, applied during normalization
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    selectors: Vec<Selector>,
+    attribute_globs: Vec<Regex>,
+    value_patterns: Vec<Regex>,
+}
+
+impl IgnoreRules {
+    pub fn new(config: &IgnoreConfig) -> Result<Self, String> {
+        let selectors = config.selectors
+            .iter()
+            .map(|s| Selector::parse(s))
+            .collect::<Result<Vec<_>, _>>()?;
+        let attribute_globs = config.attributes
+            .iter()
+            .map(|g| glob_to_regex(g))
+            .collect::<Result<Vec<_>, _>>()?;
+        let value_patterns = config.values
+            .iter()
+            .map(|p| Regex::new(p).map_err(|e| format!("invalid value pattern '{}': {}", p, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(IgnoreRules {
+            selectors,
+            attribute_globs,
+            value_patterns,
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("could not read ignore config {}: {}", path.display(), e))?;
+        let config: IgnoreConfig = serde_json::from_str(&content)
+            .map_err(|e| format!("invalid ignore config {}: {}", path.display(), e))?;
+        Self::new(&config)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.selectors.is_empty() && self.attribute_globs.is_empty() && self.value_patterns.is_empty()
+    }
+
+    pub fn has_selectors(&self) -> bool {
+        !self.selectors.is_empty()
+    }
+
+    // Whether the element (and everything inside it) should be masked
+    pub fn masks_element<E: SelectorElement>(&self, element: &E, ancestors: &[&E]) -> bool {
+        self.selectors.iter().any(|s| s.matches(element, ancestors))
+    }
+
+    pub fn masks_attribute(&self, name: &str) -> bool {
+        self.attribute_globs.iter().any(|g| g.is_match(name))
+    }
+
+    // Replace every value-pattern match with the mask
+    pub fn mask_value<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let mut masked = Cow::Borrowed(value);
+        for pattern in &self.value_patterns {
+            if pattern.is_match(&masked) {
+                masked = Cow::Owned(pattern.replace_all(&masked, MASK).into_owned());
+            }
+        }
+        masked
+    }
+}
+
+// `data-react*` -> `^data-react.*$`, case-insensitive like HTML attribute names
+fn glob_to_regex(glob: &str) -> Result<Regex, String> {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| format!("invalid attribute glob '{}': {}", glob, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::normalize_html_with_rules;
+    use crate::tokenizer::{HtmlToken, TokenKind, tokenize};
+
+    fn rules(selectors: &[&str], attributes: &[&str], values: &[&str]) -> IgnoreRules {
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        IgnoreRules::new(&IgnoreConfig {
+            selectors: strings(selectors),
+            attributes: strings(attributes),
+            values: strings(values),
+        })
+        .unwrap()
+    }
+
+    fn tokens(html: &str, rules: &IgnoreRules) -> Vec<String> {
+        normalize_html_with_rules(html, 1, rules).0
+    }
+
+    #[test]
+    fn globs_match_whole_attribute_names_case_insensitively() {
+        let rules = rules(&[], &["data-react*", "nonce", "x-?d"], &[]);
+        for name in ["data-reactroot", "data-react", "DATA-REACT-ID", "nonce", "NONCE", "x-id"] {
+            assert!(rules.masks_attribute(name), "{name} should be masked");
+        }
+        for name in ["data-reac", "xdata-react", "nonces", "x-idd", "x-d", "x.id"] {
+            assert!(!rules.masks_attribute(name), "{name} should not be masked");
+        }
+
+        // Regex metacharacters in a glob are literal
+        let rules = self::rules(&[], &["a.b", "c+"], &[]);
+        assert!(rules.masks_attribute("a.b") && rules.masks_attribute("c+"));
+        assert!(!rules.masks_attribute("axb") && !rules.masks_attribute("cc"));
+    }
+
+    #[test]
+    fn masks_every_value_pattern_match() {
+        let rules = rules(&[], &[], &[r"\d{4}-\d{2}-\d{2}", "v[0-9]+"]);
+        assert_eq!(rules.mask_value("built 2024-01-31 from v12, v3"), "built * from *, *");
+        assert!(matches!(rules.mask_value("no volatile values"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn masks_elements_matching_a_selector() {
+        let rules = rules(&["script#__NEXT_DATA__", "aside [data-timestamp]"], &[], &[]);
+        let tokens = tokenize(
+            r#"<aside><p data-timestamp="1"></p><script id="__NEXT_DATA__"></script></aside><p data-timestamp="2">"#,
+        );
+        let opens: Vec<&HtmlToken> = tokens.iter().filter(|t| t.kind == TokenKind::StartTag).collect();
+        assert!(!rules.masks_element(opens[0], &[]));
+        assert!(rules.masks_element(opens[1], &opens[..1]));
+        assert!(rules.masks_element(opens[2], &opens[..1]));
+        assert!(!rules.masks_element(opens[3], &[]));
+    }
+
+    #[test]
+    fn masked_content_normalizes_identically() {
+        let rules = rules(&["div.ad"], &["nonce"], &[r"\d{2}:\d{2}"]);
+        let a = r#"<p>at 09:15</p><div class="ad"><img src="one.png"></div><script nonce="abc">x()</script>"#;
+        let b = r#"<p>at 17:42</p><div class="ad"><a href="/buy">Buy</a></div><script nonce="xyz">x()</script>"#;
+        assert_eq!(tokens(a, &rules), tokens(b, &rules));
+        assert_ne!(tokens(a, &IgnoreRules::default()), tokens(b, &IgnoreRules::default()));
+
+        // Unmasked differences still show up
+        let c = r#"<p>at 17:42!</p><div class="ad"></div><script nonce="xyz">x()</script>"#;
+        assert_ne!(tokens(a, &rules), tokens(c, &rules));
+    }
+
+    #[test]
+    fn reads_configs_and_reports_invalid_patterns() {
+        assert!(IgnoreRules::new(&IgnoreConfig::default()).unwrap().is_empty());
+        let rules = rules(&["p"], &[], &[]);
+        assert!(!rules.is_empty() && rules.has_selectors());
+
+        let bad_selector = IgnoreConfig { selectors: vec!["[".to_string()], ..Default::default() };
+        assert!(IgnoreRules::new(&bad_selector).unwrap_err().contains("invalid selector"));
+        let bad_value = IgnoreConfig { values: vec!["(".to_string()], ..Default::default() };
+        assert!(IgnoreRules::new(&bad_value).unwrap_err().contains("invalid value pattern '('"));
+
+        let path = std::env::temp_dir().join(format!("merkle-domdiff-ignore-{}.json", std::process::id()));
+        fs::write(&path, r#"{"attributes": ["nonce"], "values": ["\\d+"]}"#).unwrap();
+        let rules = IgnoreRules::from_file(&path).unwrap();
+        assert!(rules.masks_attribute("nonce") && !rules.has_selectors());
+        assert_eq!(rules.mask_value("a1b22"), "a*b*");
+
+        fs::write(&path, r#"{"selectors": "p"}"#).unwrap();
+        assert!(IgnoreRules::from_file(&path).unwrap_err().starts_with("invalid ignore config"));
+        fs::remove_file(&path).unwrap();
+        assert!(IgnoreRules::from_file(&path).unwrap_err().starts_with("could not read ignore config"));
+    }
+}
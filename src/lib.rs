@@ -0,0 +1,52 @@
+// Merkle DOM Diff: compare HTML documents by hashing normalized DOM chunks.
+//
+// The `merkle-domdiff` binary is a thin wrapper over this crate; services can
+// embed the same comparison through `DomDiffer`.
+
+pub mod assertions;
+pub mod benchmark;
+pub mod cache;
+pub mod chunking;
+pub mod ci;
+pub mod diff;
+pub mod differ;
+pub mod dom;
+pub mod edit;
+mod entities;
+pub mod generate;
+pub mod git;
+pub mod hashing;
+pub mod ignore;
+pub mod merkle;
+pub mod normalize;
+pub mod patch;
+pub mod proof;
+pub mod report;
+pub mod selector;
+pub mod sequence;
+pub mod similarity;
+pub mod site;
+pub mod store;
+pub mod stream;
+pub mod textdiff;
+pub mod tokenizer;
+
+pub use cache::CacheStats;
+pub use chunking::{Chunking, ContentDefined, ElementChunks};
+pub use diff::{ComparisonResult, DiffMethod, DiffMode, LineDiff};
+pub use differ::DomDiffer;
+pub use dom::{DomNode, SubtreeChange, build_dom_tree, diff_dom_trees};
+pub use edit::{EditOperation, EditScript, edit_script};
+pub use hashing::{
+    ChunkHasher, HashAlgorithm, HashDigest, PerformanceConfig, get_perf_config, hash_cache_stats, set_perf_config,
+};
+pub use ignore::{IgnoreConfig, IgnoreRules};
+pub use merkle::{MerkleNode, TreeHasher};
+pub use normalize::{HashedSpans, TokenSpan, TokenWithLine, hash_spans};
+pub use patch::{Patch, PatchHunk, apply_patch, create_patch};
+pub use proof::{InclusionProof, prove_chunk, verify_proof};
+pub use sequence::SequenceEdit;
+pub use similarity::{ClusterOptions, ClusterReport, MinHashSignature, PageSketch, SimHash, SketchKind, cluster_directory};
+pub use site::{PageDiff, PageStatus, SiteDiff, diff_directories};
+pub use store::{Snapshot, SnapshotDiff, SnapshotStore, diff_snapshots};
+pub use stream::{ChunkSketch, SketchSummary};
@@ -1,859 +1,355 @@
-use std::collections::HashSet;
 use std::env;
 use std::fs;
-use std::path::Path;
-use regex::Regex;
-use serde::{Deserialize, Serialize};
+use std::io::IsTerminal;
 use chrono::{DateTime, Utc};
-use std::time::Instant;
-use rayon::prelude::*;
-use xxhash_rust::xxh3::xxh3_64;
-use std::hint::black_box;
-use std::collections::HashMap;
-use std::sync::Mutex;
-use std::sync::Arc;
 
-// Global hash cache for avoiding redundant computations
-lazy_static::lazy_static! {
-    static ref HASH_CACHE: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(HashMap::new()));
-}
-
-// Add lazy_static dependency for global cache
-// Note: In production, consider using a more sophisticated cache with LRU eviction
-
-#[derive(Debug, Clone)]
-struct PerformanceConfig {
-    use_parallel_hashing: bool,
-}
-
-impl Default for PerformanceConfig {
-    fn default() -> Self {
-        PerformanceConfig {
-            use_parallel_hashing: true,
-        }
-    }
-}
-
-lazy_static::lazy_static! {
-    static ref PERF_CONFIG: Arc<Mutex<PerformanceConfig>> = Arc::new(Mutex::new(PerformanceConfig::default()));
-}
-
-fn get_perf_config() -> PerformanceConfig {
-    PERF_CONFIG.lock().unwrap().clone()
-}
-
-
-#[derive(Serialize, Deserialize)]
-struct LineDiff {
-    line_range: String,    // e.g., "L100-L120"
-    change_type: String,   // "added", "removed", "modified"
-    content_preview: String,
-}
+use merkle_domdiff::{
+    Chunking, ComparisonResult, ContentDefined, DiffMethod, DiffMode, DomDiffer, ElementChunks, HashAlgorithm, IgnoreRules, InclusionProof,
+    PageStatus, Patch, PerformanceConfig, Snapshot, SnapshotStore, TreeHasher, apply_patch, create_patch, diff_directories,
+    diff_snapshots, get_perf_config, hash_cache_stats, prove_chunk, set_perf_config, verify_proof,
+};
+use merkle_domdiff::assertions::{AssertionSummary, Assertions};
+use merkle_domdiff::benchmark::{CountingAllocator, run_benchmark};
+use merkle_domdiff::ci::{FileComparison, OutputFormat, render};
+use merkle_domdiff::git::{ExternalDiff, diff_revisions, is_revision};
+use merkle_domdiff::generate::{generate_random_comparisons, generate_random_dom_with_changes};
+use merkle_domdiff::report::html_report;
+use merkle_domdiff::selector::Selector;
+use merkle_domdiff::similarity::{ClusterOptions, DEFAULT_SIGNATURE_SIZE, DEFAULT_THRESHOLD, SketchKind, cluster_directory};
+use merkle_domdiff::stream::DEFAULT_SKETCH_CAPACITY;
+use merkle_domdiff::textdiff::{DEFAULT_CONTEXT, DEFAULT_WIDTH, side_by_side, unified_diff};
 
-#[derive(Serialize, Deserialize)]
-struct ComparisonResult {
-    version_a: String,
-    version_b: String,
-    difference_percent: f64,
-    total_chunks_a: usize,
-    total_chunks_b: usize,
-    common_chunks: usize,
-    different_chunks: usize,
-    method: String,
-    processing_time_ms: u128,
-    processing_time_us: u128,  // microseconds
-    processing_time_ns: u128,  // nanoseconds
-    line_diffs: Vec<LineDiff>,
-}
-
-#[derive(Debug, Clone)]
-struct MerkleNode {
-    hash: String,
-    left: Option<Arc<MerkleNode>>,
-    right: Option<Arc<MerkleNode>>,
-}
+// Counts heap allocations so `--benchmark` can report them
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
-// Optimized MerkleNode creation
-impl MerkleNode {
-    fn new_leaf(hash: String) -> Self {
-        MerkleNode {
-            hash,
-            left: None,
-            right: None,
-        }
-    }
-    
-    fn new_internal(hash: String, left: Arc<MerkleNode>, right: Option<Arc<MerkleNode>>) -> Self {
-        MerkleNode {
-            hash,
-            left: Some(left),
-            right,
-        }
+// Print one file's comparison in the style of a `git diff` section
+fn print_file_diff(path_a: &str, path_b: &str, status: PageStatus, result: &ComparisonResult, chunking: Chunking) {
+    println!("diff --dom a/{} b/{} ({})", path_a, path_b, status.as_str());
+    println!(
+        "DOM diff is {:.2}% ({}): {} common, {} different chunks",
+        result.difference_percent, chunking, result.common_chunks, result.different_chunks
+    );
+    for diff in &result.line_diffs {
+        println!("{}: {}", diff.line_range, diff.content_preview);
     }
 }
 
-#[derive(Debug, Clone)]
-struct TokenWithLine {
-    content: String,
-    line_number: usize,
+// Write the `--report html` page of a comparison to `result/`, returning its path
+fn save_html_report(result: &ComparisonResult, html_a: &str, html_b: &str, rules: &IgnoreRules) -> String {
+    fs::create_dir_all("result").unwrap_or_else(|_| {
+        eprintln!("Error: Could not create result directory");
+        std::process::exit(1);
+    });
+    let timestamp = Utc::now().format("%Y%m%d_%H%M%S").to_string();
+    let filename = format!("result/report-{}.html", timestamp);
+    fs::write(&filename, html_report(result, html_a, html_b, rules)).unwrap_or_else(|_| {
+        eprintln!("Error: Could not write report file {}", filename);
+        std::process::exit(1);
+    });
+    filename
 }
 
-fn normalize_html_with_lines(html: &str, chunk_size: usize) -> (Vec<String>, Vec<TokenWithLine>) {
-    // Pre-allocate with estimated capacity to reduce reallocations
-    let estimated_tokens = html.len() / 20; // Rough estimate
-    let mut tokens_with_lines = Vec::with_capacity(estimated_tokens);
-    let tag_re = Regex::new(r"<[^>]+>").unwrap();
-    
-    let mut last_end = 0;
-    
-    // Pre-compute line positions for better performance
-    let line_positions: Vec<usize> = html.char_indices()
-        .filter_map(|(i, c)| if c == '\n' { Some(i) } else { None })
-        .collect();
-    
-    // Count newlines up to each position using binary search
-    fn count_lines_up_to(line_positions: &[usize], pos: usize) -> usize {
-        match line_positions.binary_search(&pos) {
-            Ok(idx) => idx + 2, // Found exact position, line number is index + 2
-            Err(idx) => idx + 1, // Insert position gives us the line number
-        }
+// Report the outcome of the assertions with the console output
+fn print_assertions(summary: Option<&AssertionSummary>) {
+    let Some(summary) = summary else { return };
+    for violation in &summary.violations {
+        println!("Assertion failed: {}", violation.message);
     }
-    
-    for mat in tag_re.find_iter(html) {
-        // Add text content before this tag
-        if mat.start() > last_end {
-            let text = html[last_end..mat.start()].trim();
-            if !text.is_empty() {
-                let line_num = count_lines_up_to(&line_positions, mat.start());
-                // Use String::with_capacity to reduce allocations
-                let mut token_content = String::with_capacity(5 + text.len());
-                token_content.push_str("TEXT:");
-                token_content.push_str(text);
-                tokens_with_lines.push(TokenWithLine {
-                    content: token_content,
-                    line_number: line_num,
-                });
-            }
-        }
-        
-        // Add the tag itself, normalized
-        let tag = mat.as_str().trim();
-        if !tag.is_empty() {
-            let line_num = count_lines_up_to(&line_positions, mat.start());
-            // Optimize tag normalization to avoid collect/join
-            let mut normalized_tag = String::with_capacity(tag.len());
-            let mut first = true;
-            for word in tag.split_whitespace() {
-                if !first {
-                    normalized_tag.push(' ');
-                }
-                normalized_tag.push_str(word);
-                first = false;
-            }
-            let mut token_content = String::with_capacity(4 + normalized_tag.len());
-            token_content.push_str("TAG:");
-            token_content.push_str(&normalized_tag);
-            tokens_with_lines.push(TokenWithLine {
-                content: token_content,
-                line_number: line_num,
-            });
-        }
-        
-        last_end = mat.end();
-    }
-    
-    // Add any remaining text after the last tag
-    if last_end < html.len() {
-        let text = html[last_end..].trim();
-        if !text.is_empty() {
-            let line_num = count_lines_up_to(&line_positions, html.len());
-            let mut token_content = String::with_capacity(5 + text.len());
-            token_content.push_str("TEXT:");
-            token_content.push_str(text);
-            tokens_with_lines.push(TokenWithLine {
-                content: token_content,
-                line_number: line_num,
-            });
-        }
+    if summary.passed {
+        println!("Assertions passed");
     }
-    
-    // Group tokens into chunks of specified size
-    let tokens: Vec<String> = tokens_with_lines.iter().map(|t| t.content.clone()).collect();
-    
-    let chunks = if chunk_size <= 1 {
-        tokens
-    } else {
-        let mut chunks = Vec::new();
-        for chunk in tokens.chunks(chunk_size) {
-            let combined_chunk = chunk.join("|");
-            chunks.push(combined_chunk);
-        }
-        chunks
-    };
-    
-    (chunks, tokens_with_lines)
 }
 
-fn normalize_html(html: &str, chunk_size: usize) -> Vec<String> {
-    let (chunks, _) = normalize_html_with_lines(html, chunk_size);
-    chunks
-}
-
-// Fast non-cryptographic hash for performance
-fn hash_chunk_fast(chunk: &str) -> String {
-    format!("{:x}", xxh3_64(chunk.as_bytes()))
-}
-
-
-// Default to fast hash with caching
-fn hash_chunk(chunk: &str) -> String {
-    // Check cache first
-    if let Ok(cache) = HASH_CACHE.lock() {
-        if let Some(cached_hash) = cache.get(chunk) {
-            return cached_hash.clone();
-        }
-    }
-    
-    let hash = hash_chunk_fast(chunk);
-    
-    // Store in cache
-    if let Ok(mut cache) = HASH_CACHE.lock() {
-        // Simple cache size limit to prevent unbounded growth
-        if cache.len() > 10000 {
-            cache.clear(); // Simple eviction strategy
-        }
-        cache.insert(chunk.to_string(), hash.clone());
+// Print the assertion summary as one JSON line on stderr, exiting with its
+// status if an assertion failed
+fn finish_assertions(summary: Option<AssertionSummary>) {
+    let Some(summary) = summary else { return };
+    eprintln!("{}", serde_json::to_string(&summary).expect("Failed to serialize to JSON"));
+    if !summary.passed {
+        std::process::exit(summary.exit_code);
     }
-    
-    hash
 }
 
-
-// Configurable chunk hashing (parallel or sequential)
-fn hash_chunks(chunks: &[String]) -> Vec<String> {
-    let config = get_perf_config();
-    if config.use_parallel_hashing {
-        chunks.par_iter().map(|c| hash_chunk(c)).collect()
-    } else {
-        chunks.iter().map(|c| hash_chunk(c)).collect()
+// Remove `flag <value>` from the argument list, returning the value
+fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let idx = args.iter().position(|a| a == flag)?;
+    if idx + 1 >= args.len() {
+        eprintln!("Error: {} requires a value", flag);
+        std::process::exit(1);
     }
+    let value = args.remove(idx + 1);
+    args.remove(idx);
+    Some(value)
 }
 
-
-// Merkle Lite: Simple hashing approach (current implementation)
-fn merkle_lite_hash(chunks: &[String]) -> Vec<String> {
-    hash_chunks(chunks)
-}
-
-// Optimized Full Merkle Tree implementation with Arc to avoid cloning
-fn build_merkle_tree(chunks: &[String]) -> Option<Arc<MerkleNode>> {
-    if chunks.is_empty() {
-        return None;
-    }
-    
-    // Use parallel iterator for leaf node creation
-    let mut nodes: Vec<Arc<MerkleNode>> = chunks
-        .par_iter()
-        .map(|chunk| Arc::new(MerkleNode::new_leaf(hash_chunk(chunk))))
-        .collect();
-    
-    while nodes.len() > 1 {
-        let mut next_level = Vec::new();
-        
-        for i in (0..nodes.len()).step_by(2) {
-            let left = nodes[i].clone(); // Arc clone is cheap
-            let right = if i + 1 < nodes.len() {
-                Some(nodes[i + 1].clone())
-            } else {
-                None
-            };
-            
-            let combined_hash = if let Some(ref r) = right {
-                hash_chunk(&format!("{}{}", left.hash, r.hash))
-            } else {
-                left.hash.clone()
-            };
-            
-            next_level.push(Arc::new(MerkleNode::new_internal(combined_hash, left, right)));
+// Remove a boolean `flag` from the argument list, returning whether it was present
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|a| a == flag) {
+        Some(idx) => {
+            args.remove(idx);
+            true
         }
-        
-        nodes = next_level;
+        None => false,
     }
-    
-    nodes.into_iter().next()
 }
 
-fn extract_merkle_hashes(node: &Arc<MerkleNode>) -> Vec<String> {
-    let mut hashes = Vec::new();
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
     
-    // Collect leaf node hashes with optimized traversal
-    fn collect_leaves(node: &Arc<MerkleNode>, hashes: &mut Vec<String>) {
-        if node.left.is_none() && node.right.is_none() {
-            // Leaf node
-            hashes.push(node.hash.clone());
-        } else {
-            if let Some(ref left) = node.left {
-                collect_leaves(left, hashes);
-            }
-            if let Some(ref right) = node.right {
-                collect_leaves(right, hashes);
-            }
-        }
+    let ignore_rules = take_flag_value(&mut args, "--ignore")
+        .map(|path| {
+            IgnoreRules::from_file(&path).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            })
+        })
+        .unwrap_or_default();
+    let method: DiffMethod = take_flag_value(&mut args, "--method")
+        .map(|name| {
+            name.parse().unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            })
+        })
+        .unwrap_or(DiffMethod::MerkleLite);
+    let mode = if take_flag(&mut args, "--ordered") { DiffMode::Ordered } else { DiffMode::Set };
+    if let Some(name) = take_flag_value(&mut args, "--hash") {
+        let hash_algorithm: HashAlgorithm = name.parse().unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+        set_perf_config(PerformanceConfig { hash_algorithm, ..get_perf_config() });
     }
-    
-    collect_leaves(node, &mut hashes);
-    hashes
-}
-
-fn merkle_tree_hash(chunks: &[String]) -> Vec<String> {
-    if let Some(tree) = build_merkle_tree(chunks) {
-        extract_merkle_hashes(&tree)
-    } else {
-        Vec::new()
+    if let Some(version) = take_flag_value(&mut args, "--tree-version") {
+        let tree_version = version
+            .parse()
+            .map_err(|_| format!("tree version must be a number, got '{}'", version))
+            .and_then(|v| TreeHasher::new(HashAlgorithm::default(), v).map(|_| v))
+            .unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+        set_perf_config(PerformanceConfig { tree_version, ..get_perf_config() });
     }
-}
-
-fn detailed_diff(hashes_a: &[String], hashes_b: &[String]) -> (f64, usize, usize, usize, usize) {
-    let set_a: HashSet<_> = hashes_a.iter().collect();
-    let set_b: HashSet<_> = hashes_b.iter().collect();
-
-    let total = set_a.union(&set_b).count();
-    let common = set_a.intersection(&set_b).count();
-    let diff = set_a.symmetric_difference(&set_b).count();
-
-    let percent = if total == 0 {
-        0.0
-    } else {
-        (diff as f64 / total as f64) * 100.0
-    };
-
-    (percent, hashes_a.len(), hashes_b.len(), common, diff)
-}
-
-fn generate_line_diffs(tokens_a: &[TokenWithLine], tokens_b: &[TokenWithLine]) -> Vec<LineDiff> {
-    let mut line_diffs = Vec::new();
-    
-    // Create hash sets for comparison
-    let set_a: HashSet<_> = tokens_a.iter().map(|t| &t.content).collect();
-    let set_b: HashSet<_> = tokens_b.iter().map(|t| &t.content).collect();
-    
-    // Find added tokens (in b but not in a) with content
-    let mut added_items = Vec::new();
-    for token in tokens_b {
-        if !set_a.contains(&token.content) {
-            added_items.push((token.line_number, token.content.clone()));
-        }
+    if let Some(capacity) = take_flag_value(&mut args, "--hash-cache") {
+        let hash_cache_capacity: usize = capacity.parse().unwrap_or_else(|_| {
+            eprintln!("Error: hash cache capacity must be a number");
+            std::process::exit(1);
+        });
+        set_perf_config(PerformanceConfig { use_hash_cache: true, hash_cache_capacity, ..get_perf_config() });
     }
-    
-    // Find removed tokens (in a but not in b) with content
-    let mut removed_items = Vec::new();
-    for token in tokens_a {
-        if !set_b.contains(&token.content) {
-            removed_items.push((token.line_number, token.content.clone()));
+    let store_dir = take_flag_value(&mut args, "--store").unwrap_or_else(|| ".merkle-store".to_string());
+    let stream = take_flag(&mut args, "--stream");
+    let sketch_size: usize = take_flag_value(&mut args, "--sketch-size")
+        .map(|size| {
+            size.parse().unwrap_or_else(|_| {
+                eprintln!("Error: sketch size must be a number");
+                std::process::exit(1);
+            })
+        })
+        .unwrap_or(DEFAULT_SKETCH_CAPACITY);
+    // Content-defined or element chunk boundaries replace the fixed chunk_size of a command
+    let content_defined: Option<ContentDefined> = take_flag_value(&mut args, "--cdc")
+        .map(|sizes| {
+            sizes.parse().unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            })
+        });
+    let element_depth = take_flag_value(&mut args, "--element-depth");
+    let element_tokens = take_flag_value(&mut args, "--element-tokens");
+    let elements = (element_depth.is_some() || element_tokens.is_some()).then(|| {
+        let parse = |value: Option<String>, what: &str| {
+            value.map(|v| {
+                v.parse::<usize>().unwrap_or_else(|_| {
+                    eprintln!("Error: {} must be a number", what);
+                    std::process::exit(1);
+                })
+            })
+        };
+        ElementChunks {
+            depth: parse(element_depth, "element depth").unwrap_or(0),
+            max_tokens: parse(element_tokens, "element token budget").filter(|&n| n > 0),
         }
-    }
-    
-    // Group consecutive line numbers into ranges with content
-    fn group_consecutive_lines_with_content(mut items: Vec<(usize, String)>) -> Vec<(String, String)> {
-        if items.is_empty() {
-            return Vec::new();
+    });
+    let chunking_override: Option<Chunking> = match (content_defined, elements) {
+        (Some(_), Some(_)) => {
+            eprintln!("Error: --cdc cannot be combined with --element-depth or --element-tokens");
+            std::process::exit(1);
         }
-        
-        items.sort_by_key(|&(line, _)| line);
-        
-        let mut ranges = Vec::new();
-        let mut start = items[0].0;
-        let mut end = items[0].0;
-        let mut content_samples = vec![items[0].1.clone()];
-        
-        for &(line, ref content) in &items[1..] {
-            if line == end + 1 {
-                end = line;
-                if content_samples.len() < 3 {
-                    content_samples.push(content.clone());
-                }
-            } else {
-                let range = if start == end {
-                    format!("L{}", start)
-                } else {
-                    format!("L{}-L{}", start, end)
-                };
-                
-                let preview = if content_samples.len() <= 2 {
-                    content_samples.join(", ")
-                } else {
-                    format!("{}, {} ... ({} more)", 
-                        content_samples[0], content_samples[1], content_samples.len() - 2)
-                };
-                
-                ranges.push((range, preview));
-                start = line;
-                end = line;
-                content_samples = vec![content.clone()];
+        (Some(sizes), None) => Some(sizes.into()),
+        (None, Some(elements)) => Some(elements.into()),
+        (None, None) => None,
+    };
+    let chunking = |chunk_size: usize| chunking_override.unwrap_or(Chunking::from(chunk_size));
+    let threshold: f64 = take_flag_value(&mut args, "--threshold")
+        .map(|value| match value.parse::<f64>() {
+            Ok(t) if (0.0..=1.0).contains(&t) => t,
+            _ => {
+                eprintln!("Error: threshold must be a number between 0 and 1");
+                std::process::exit(1);
+            }
+        })
+        .unwrap_or(DEFAULT_THRESHOLD);
+    let sketch_kind: SketchKind = take_flag_value(&mut args, "--sketch")
+        .map(|name| {
+            name.parse().unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            })
+        })
+        .unwrap_or_default();
+    let signature_size: usize = take_flag_value(&mut args, "--signature-size")
+        .map(|value| match value.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                eprintln!("Error: signature size must be a positive number");
+                std::process::exit(1);
             }
+        })
+        .unwrap_or(DEFAULT_SIGNATURE_SIZE);
+    // Text renderings of --line-diff
+    let unified = take_flag(&mut args, "--unified");
+    let side_by_side_view = take_flag(&mut args, "--side-by-side");
+    if unified && side_by_side_view {
+        eprintln!("Error: --unified and --side-by-side cannot be combined");
+        std::process::exit(1);
+    }
+    let context: usize = take_flag_value(&mut args, "--context")
+        .map(|value| {
+            value.parse().unwrap_or_else(|_| {
+                eprintln!("Error: context must be a number of lines");
+                std::process::exit(1);
+            })
+        })
+        .unwrap_or(DEFAULT_CONTEXT);
+    // Terminal width for --side-by-side, from the shell if it exports COLUMNS
+    let width: usize = take_flag_value(&mut args, "--width")
+        .or_else(|| env::var("COLUMNS").ok())
+        .map(|value| {
+            value.parse().unwrap_or_else(|_| {
+                eprintln!("Error: width must be a number of columns");
+                std::process::exit(1);
+            })
+        })
+        .unwrap_or(DEFAULT_WIDTH);
+    let html_report_requested = match take_flag_value(&mut args, "--report").as_deref() {
+        Some("html") => true,
+        Some(other) => {
+            eprintln!("Error: unknown report '{}' (expected html)", other);
+            std::process::exit(1);
         }
-        
-        // Add the last range
-        let range = if start == end {
-            format!("L{}", start)
-        } else {
-            format!("L{}-L{}", start, end)
-        };
-        
-        let preview = if content_samples.len() <= 2 {
-            content_samples.join(", ")
-        } else {
-            format!("{}, {} ... ({} more)", 
-                content_samples[0], content_samples[1], content_samples.len() - 2)
-        };
-        
-        ranges.push((range, preview));
-        ranges
+        None => false,
+    };
+    if html_report_requested && (stream || unified) {
+        eprintln!("Error: --report needs both documents in memory and cannot be used with --stream or --unified");
+        std::process::exit(1);
     }
-    
-    // Generate line diffs for added content
-    for (range, content) in group_consecutive_lines_with_content(added_items) {
-        line_diffs.push(LineDiff {
-            line_range: range,
-            change_type: "added".to_string(),
-            content_preview: format!("+ {}", content.chars().take(100).collect::<String>()),
+    // JUnit or SARIF on stdout in place of the console output
+    let output_format: Option<OutputFormat> = take_flag_value(&mut args, "--format")
+        .map(|name| {
+            name.parse().unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            })
         });
+    if output_format.is_some() && (stream || unified || side_by_side_view || html_report_requested) {
+        eprintln!("Error: --format needs line diffs and replaces the console output; it cannot be used with --stream, --unified, --side-by-side or --report");
+        std::process::exit(1);
     }
-    
-    // Generate line diffs for removed content
-    for (range, content) in group_consecutive_lines_with_content(removed_items) {
-        line_diffs.push(LineDiff {
-            line_range: range,
-            change_type: "removed".to_string(),
-            content_preview: format!("- {}", content.chars().take(100).collect::<String>()),
-        });
+    // Assertions that gate the plain comparison and --line-diff
+    let mut forbid_change = Vec::new();
+    while let Some(selector) = take_flag_value(&mut args, "--forbid-change") {
+        forbid_change.push(Selector::parse(&selector).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }));
     }
-    
-    line_diffs
-}
-
-fn compare_with_method(chunks_a: &[String], chunks_b: &[String], use_merkle_tree: bool) -> (f64, usize, usize, usize, usize, u128, u128, u128) {
-    let start = Instant::now();
-    
-    let (hashes_a, hashes_b) = if use_merkle_tree {
-        (merkle_tree_hash(chunks_a), merkle_tree_hash(chunks_b))
-    } else {
-        (merkle_lite_hash(chunks_a), merkle_lite_hash(chunks_b))
+    let mut line_limit = |flag: &str| {
+        take_flag_value(&mut args, flag).map(|value| {
+            value.parse::<usize>().unwrap_or_else(|_| {
+                eprintln!("Error: {} must be a number of lines", flag);
+                std::process::exit(1);
+            })
+        })
     };
-    
-    let (percent, total_a, total_b, common, different) = detailed_diff(&hashes_a, &hashes_b);
-    let elapsed = start.elapsed();
-    let duration_ms = elapsed.as_millis();
-    let duration_us = elapsed.as_micros();
-    let duration_ns = elapsed.as_nanos();
-    
-    (percent, total_a, total_b, common, different, duration_ms, duration_us, duration_ns)
-}
-
-fn compare_with_line_diffs(content_a: &str, content_b: &str, chunk_size: usize, use_merkle_tree: bool) -> (f64, usize, usize, usize, usize, u128, u128, u128, Vec<LineDiff>) {
-    let start = Instant::now();
-    
-    let (chunks_a, tokens_a) = normalize_html_with_lines(content_a, chunk_size);
-    let (chunks_b, tokens_b) = normalize_html_with_lines(content_b, chunk_size);
-    
-    let (hashes_a, hashes_b) = if use_merkle_tree {
-        (merkle_tree_hash(&chunks_a), merkle_tree_hash(&chunks_b))
-    } else {
-        (merkle_lite_hash(&chunks_a), merkle_lite_hash(&chunks_b))
+    let (max_added, max_removed) = (line_limit("--max-added"), line_limit("--max-removed"));
+    let assertions = Assertions {
+        fail_above: take_flag_value(&mut args, "--fail-above").map(|value| match value.parse::<f64>() {
+            Ok(p) if (0.0..=100.0).contains(&p) => p,
+            _ => {
+                eprintln!("Error: --fail-above must be a percentage between 0 and 100");
+                std::process::exit(1);
+            }
+        }),
+        max_added,
+        max_removed,
+        forbid_change,
     };
-    
-    let (percent, total_a, total_b, common, different) = detailed_diff(&hashes_a, &hashes_b);
-    let line_diffs = generate_line_diffs(&tokens_a, &tokens_b);
-    let elapsed = start.elapsed();
-    let duration_ms = elapsed.as_millis();
-    let duration_us = elapsed.as_micros();
-    let duration_ns = elapsed.as_nanos();
-    
-    (percent, total_a, total_b, common, different, duration_ms, duration_us, duration_ns, line_diffs)
-}
-
-fn process_file<P: AsRef<Path>>(path: P, chunk_size: usize) -> Vec<String> {
-    let content = fs::read_to_string(path).expect("Failed to read file");
-    let chunks = normalize_html(&content, chunk_size);
-    hash_chunks(&chunks)
-}
-
-fn generate_random_dom_with_changes(base_content: &str, version: usize) -> String {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    // Create a pseudo-random seed from version number
-    let mut hasher = DefaultHasher::new();
-    version.hash(&mut hasher);
-    let seed = hasher.finish();
-    
-    let mut modified = base_content.to_string();
-    
-    // Random small changes based on seeded pseudo-randomness
-    let changes = vec![
-        // Add random attributes
-        (seed % 7 == 0, format!(" data-rand='{}'", seed % 1000)),
-        // Add random classes
-        (seed % 11 == 0, format!(" class='gen-{}'", seed % 100)),
-        // Add random IDs
-        (seed % 13 == 0, format!(" id='elem-{}'", seed % 500)),
-        // Add random text nodes
-        (seed % 17 == 0, format!("Random text {}", seed % 50)),
-        // Add random comments
-        (seed % 19 == 0, format!("<!-- Random comment {} -->", seed % 200)),
-    ];
-    
-    // Apply random changes
-    for (should_apply, change) in &changes {
-        if *should_apply {
-            // Insert change at random position based on seed
-            let insertion_point = (seed as usize) % modified.len().max(1);
-            modified.insert_str(insertion_point, change);
-        }
-    }
-    
-    // Add some structural changes
-    if seed % 23 == 0 {
-        modified.push_str(&format!("<div><span>Generated {}</span></div>", version));
+    if stream && (assertions.needs_line_diffs() || !assertions.forbid_change.is_empty()) {
+        eprintln!("Error: --max-added, --max-removed and --forbid-change need both documents in memory and cannot be used with --stream");
+        std::process::exit(1);
     }
-    
-    if seed % 29 == 0 {
-        modified = modified.replace("</head>", &format!("<meta name='version' content='{}'></head>", version));
+    // Only the plain comparison and --line-diff check assertions; anywhere else they would silently pass
+    let command = args.get(1).map(String::as_str).filter(|arg| arg.starts_with("--"));
+    if !assertions.is_empty() && command.is_some_and(|command| command != "--line-diff") {
+        eprintln!("Error: --fail-above, --max-added, --max-removed and --forbid-change apply only to the plain comparison and --line-diff");
+        std::process::exit(1);
     }
-    
-    if seed % 31 == 0 {
-        modified.push_str(&format!("<script>var version = {};</script>", version));
+    if html_report_requested && command.is_some_and(|command| command != "--line-diff") {
+        eprintln!("Error: --report applies only to the plain comparison and --line-diff");
+        std::process::exit(1);
     }
-    
-    // Small text modifications
-    if seed % 37 == 0 {
-        modified = modified.replace("div", &format!("div{}", seed % 10));
+    if output_format.is_some() && command.is_some_and(|command| !matches!(command, "--line-diff" | "--git" | "--diff-dir")) {
+        eprintln!("Error: --format applies only to the plain comparison, --line-diff, --git, --diff-dir and the external diff");
+        std::process::exit(1);
     }
-    
-    modified
-}
-
-fn generate_random_comparisons(base_content: &str, num_comparisons: usize, chunk_size: usize, use_merkle_tree: bool, include_line_diffs: bool) -> Vec<ComparisonResult> {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    let mut results = Vec::new();
-    
-    // First, generate all versions (1-100)
-    let mut versions = Vec::new();
-    versions.push(("base".to_string(), base_content.to_string()));
-    
-    for i in 1..=100 {
-        let version_content = if i % 7 == 0 || i % 11 == 0 {
-            // Keep some versions unchanged
-            base_content.to_string()
-        } else {
-            generate_random_dom_with_changes(base_content, i)
-        };
-        versions.push((format!("v{}", i), version_content));
+    if stream && (method != DiffMethod::MerkleLite || mode != DiffMode::Set) {
+        eprintln!("Error: --stream supports only the set-based merkle_lite comparison");
+        std::process::exit(1);
     }
     
-    // Generate random comparisons
-    for comparison_idx in 0..num_comparisons {
-        // Create pseudo-random indices based on comparison index
-        let mut hasher = DefaultHasher::new();
-        comparison_idx.hash(&mut hasher);
-        let seed = hasher.finish();
-        
-        let idx_a = (seed % versions.len() as u64) as usize;
-        let idx_b = ((seed / versions.len() as u64) % versions.len() as u64) as usize;
-        
-        // Skip if comparing same version
-        if idx_a == idx_b {
-            continue;
+    // Invoked by git as GIT_EXTERNAL_DIFF or a diff driver command
+    if let Some(external) = ExternalDiff::from_args(&args[1..]) {
+        if !assertions.is_empty() {
+            eprintln!("Error: assertions cannot be checked when invoked as an external diff");
+            std::process::exit(1);
         }
-        
-        let (name_a, content_a) = &versions[idx_a];
-        let (name_b, content_b) = &versions[idx_b];
-        
-        let (percent, total_a, total_b, common, different, duration_ms, duration_us, duration_ns, line_diffs) = if include_line_diffs {
-            compare_with_line_diffs(content_a, content_b, chunk_size, use_merkle_tree)
-        } else {
-            let chunks_a = normalize_html(content_a, chunk_size);
-            let chunks_b = normalize_html(content_b, chunk_size);
-            let (p, ta, tb, c, d, dur_ms, dur_us, dur_ns) = compare_with_method(&chunks_a, &chunks_b, use_merkle_tree);
-            (p, ta, tb, c, d, dur_ms, dur_us, dur_ns, Vec::new())
-        };
-        
-        results.push(ComparisonResult {
-            version_a: name_a.clone(),
-            version_b: name_b.clone(),
-            difference_percent: percent,
-            total_chunks_a: total_a,
-            total_chunks_b: total_b,
-            common_chunks: common,
-            different_chunks: different,
-            method: if use_merkle_tree { "merkle_tree".to_string() } else { "merkle_lite".to_string() },
-            processing_time_ms: duration_ms,
-            processing_time_us: duration_us,
-            processing_time_ns: duration_ns,
-            line_diffs,
-        });
-    }
-    
-    results
-}
-
-fn get_memory_usage() -> usize {
-    // This is a simplified memory usage estimation
-    // In practice, you'd use a proper memory profiling tool
-    use std::process::Command;
-    
-    let output = Command::new("ps")
-        .args(&["-o", "rss=", "-p", &std::process::id().to_string()])
-        .output();
-    
-    if let Ok(output) = output {
-        let rss_str = String::from_utf8_lossy(&output.stdout);
-        rss_str.trim().parse::<usize>().unwrap_or(0) * 1024 // Convert KB to bytes
-    } else {
-        0
-    }
-}
-
-#[derive(Serialize)]
-struct BenchmarkResult {
-    method: String,
-    chunk_size: usize,
-    num_tests: usize,
-    avg_time_ms: f64,
-    avg_time_us: f64,  // microseconds
-    avg_time_ns: f64,  // nanoseconds
-    min_time_ms: u128,
-    min_time_us: u128,
-    min_time_ns: u128,
-    max_time_ms: u128,
-    max_time_us: u128,
-    max_time_ns: u128,
-    total_time_ms: u128,
-    total_time_us: u128,
-    total_time_ns: u128,
-    memory_usage_bytes: usize,
-    throughput_comparisons_per_sec: f64,
-}
-
-fn run_benchmark(num_tests: usize) {
-    println!("Running benchmark with {} tests...", num_tests);
-    
-    let base_html = r##"<!DOCTYPE html>
-<html>
-<head>
-    <title>Benchmark HTML</title>
-    <meta charset="utf-8">
-    <style>
-        body { font-family: Arial, sans-serif; }
-        .container { max-width: 1200px; margin: 0 auto; }
-        .header { background: #333; color: white; padding: 20px; }
-        .content { padding: 20px; }
-        .footer { background: #666; color: white; padding: 10px; }
-    </style>
-</head>
-<body>
-    <div class="container">
-        <header class="header">
-            <h1>Performance Test Page</h1>
-            <nav>
-                <ul>
-                    <li><a href="#home">Home</a></li>
-                    <li><a href="#about">About</a></li>
-                    <li><a href="#contact">Contact</a></li>
-                </ul>
-            </nav>
-        </header>
-        <main class="content">
-            <section>
-                <h2>Content Section</h2>
-                <p>This is a test paragraph with <strong>bold</strong> and <em>italic</em> text.</p>
-                <div class="card">
-                    <h3>Card Title</h3>
-                    <p>Card content goes here with more text to analyze.</p>
-                    <button onclick="alert('clicked')">Click Me</button>
-                </div>
-            </section>
-            <section>
-                <h2>List Section</h2>
-                <ul>
-                    <li>Item 1</li>
-                    <li>Item 2</li>
-                    <li>Item 3</li>
-                </ul>
-                <table>
-                    <tr><th>Column 1</th><th>Column 2</th></tr>
-                    <tr><td>Data 1</td><td>Data 2</td></tr>
-                </table>
-            </section>
-        </main>
-        <footer class="footer">
-            <p>&copy; 2025 Benchmark Test</p>
-        </footer>
-    </div>
-    <script>
-        console.log('Page loaded');
-        function testFunction() {
-            return 'test';
+        if html_report_requested {
+            eprintln!("Error: --report cannot be written when invoked as an external diff");
+            std::process::exit(1);
         }
-    </script>
-</body>
-</html>"##;
-    
-    let chunk_sizes = vec![1, 2, 3, 5];
-    let methods = vec![("merkle_lite", false), ("merkle_tree", true)];
-    
-    let mut benchmark_results = Vec::new();
-    
-    for chunk_size in chunk_sizes {
-        for (method_name, use_merkle_tree) in &methods {
-            println!("Testing {} with chunk size {}...", method_name, chunk_size);
-            
-            let mut times = Vec::new();
-            let memory_before = get_memory_usage();
-            
-            let total_start = Instant::now();
-            
-            for test_idx in 0..num_tests {
-                // Generate a slightly different version for each test
-                let modified_html = format!("{}<div id='test-{}'></div>", base_html, test_idx);
-                
-                let (chunks_a, _) = normalize_html_with_lines(base_html, chunk_size);
-                let (chunks_b, _) = normalize_html_with_lines(&modified_html, chunk_size);
-                
-                // Use black_box to prevent compiler optimizations
-                let (_percent, _total_a, _total_b, _common, _different, duration_ms, duration_us, duration_ns) = 
-                    compare_with_method(
-                        &black_box(chunks_a), 
-                        &black_box(chunks_b), 
-                        black_box(*use_merkle_tree)
-                    );
-                
-                times.push(black_box(duration_ms));
+        if !external.is_html() {
+            // CI formats report DOM changes only; git's own diff would corrupt the document
+            if output_format.is_some() {
+                return;
             }
-            
-            let total_elapsed = total_start.elapsed();
-            let total_duration_ms = total_elapsed.as_millis();
-            let total_duration_us = total_elapsed.as_micros();
-            let total_duration_ns = total_elapsed.as_nanos();
-            let memory_after = get_memory_usage();
-            
-            let avg_time_ms = times.iter().sum::<u128>() as f64 / times.len() as f64;
-            let min_time_ms = *times.iter().min().unwrap_or(&0);
-            let max_time_ms = *times.iter().max().unwrap_or(&0);
-            
-            // Calculate averages for all time units
-            let avg_time_us = avg_time_ms * 1000.0;
-            let avg_time_ns = avg_time_us * 1000.0;
-            let min_time_us = min_time_ms * 1000;
-            let min_time_ns = min_time_us * 1000;
-            let max_time_us = max_time_ms * 1000;
-            let max_time_ns = max_time_us * 1000;
-            
-            let throughput = if total_duration_ms > 0 {
-                (num_tests as f64 * 1000.0) / total_duration_ms as f64
-            } else {
-                0.0
-            };
-            
-            benchmark_results.push(BenchmarkResult {
-                method: method_name.to_string(),
-                chunk_size,
-                num_tests,
-                avg_time_ms: avg_time_ms,
-                avg_time_us: avg_time_us,
-                avg_time_ns: avg_time_ns,
-                min_time_ms: min_time_ms,
-                min_time_us: min_time_us,
-                min_time_ns: min_time_ns,
-                max_time_ms: max_time_ms,
-                max_time_us: max_time_us,
-                max_time_ns: max_time_ns,
-                total_time_ms: total_duration_ms,
-                total_time_us: total_duration_us,
-                total_time_ns: total_duration_ns,
-                memory_usage_bytes: memory_after.saturating_sub(memory_before),
-                throughput_comparisons_per_sec: throughput,
+            let plain = external.plain_diff().unwrap_or_else(|e| {
+                eprintln!("Error: Could not diff {}: {}", external.path, e);
+                std::process::exit(1);
             });
+            print!("{}", plain);
+            return;
         }
-    }
-    
-    // Create result directory if it doesn't exist
-    fs::create_dir_all("result").unwrap_or_else(|_| {
-        eprintln!("Error: Could not create result directory");
-        std::process::exit(1);
-    });
-    
-    // Generate timestamp
-    let now: DateTime<Utc> = Utc::now();
-    let timestamp = now.format("%Y%m%d_%H%M%S").to_string();
-    let filename = format!("result/benchmark-{}.json", timestamp);
-    
-    // Save benchmark results
-    let json_output = serde_json::to_string_pretty(&benchmark_results).expect("Failed to serialize benchmark results");
-    fs::write(&filename, &json_output).unwrap_or_else(|_| {
-        eprintln!("Error: Could not write benchmark file {}", filename);
-        std::process::exit(1);
-    });
-    
-    // Print summary
-    println!("\n=== BENCHMARK RESULTS ===");
-    println!("Benchmark results saved to: {}", filename);
-    println!();
-    
-    for result in &benchmark_results {
-        println!("Method: {} (chunk size: {})", result.method, result.chunk_size);
-        
-        // Choose best unit for display based on timing
-        if result.avg_time_ms >= 1.0 {
-            println!("  Average time: {:.3} ms ({:.1} μs, {:.0} ns)", result.avg_time_ms, result.avg_time_us, result.avg_time_ns);
-            println!("  Min/Max time: {} ms / {} ms", result.min_time_ms, result.max_time_ms);
-        } else if result.avg_time_us >= 1.0 {
-            println!("  Average time: {:.3} μs ({:.3} ms, {:.0} ns)", result.avg_time_us, result.avg_time_ms, result.avg_time_ns);
-            println!("  Min/Max time: {} μs / {} μs", result.min_time_us, result.max_time_us);
-        } else {
-            println!("  Average time: {:.0} ns ({:.3} μs, {:.3} ms)", result.avg_time_ns, result.avg_time_us, result.avg_time_ms);
-            println!("  Min/Max time: {} ns / {} ns", result.min_time_ns, result.max_time_ns);
-        }
-        
-        println!("  Total time: {} ms ({} μs, {} ns)", result.total_time_ms, result.total_time_us, result.total_time_ns);
-        println!("  Memory usage: {} bytes ({:.2} KB)", result.memory_usage_bytes, result.memory_usage_bytes as f64 / 1024.0);
-        println!("  Throughput: {:.2} comparisons/sec", result.throughput_comparisons_per_sec);
-        println!();
-    }
-    
-    // Performance comparison
-    println!("=== PERFORMANCE COMPARISON ===");
-    for chunk_size in vec![1, 2, 3, 5] {
-        let lite_result = benchmark_results.iter().find(|r| r.method == "merkle_lite" && r.chunk_size == chunk_size);
-        let tree_result = benchmark_results.iter().find(|r| r.method == "merkle_tree" && r.chunk_size == chunk_size);
-        
-        if let (Some(lite), Some(tree)) = (lite_result, tree_result) {
-            let speed_ratio = if tree.avg_time_ms > 0.0 { 
-                lite.avg_time_ms / tree.avg_time_ms 
-            } else { 
-                1.0 
-            };
-            let winner = if lite.avg_time_ms < tree.avg_time_ms { "Merkle Lite" } else { "Merkle Tree" };
-            
-            println!("Chunk Size {}: {} is {:.2}x faster", chunk_size, winner, speed_ratio.max(1.0 / speed_ratio));
-            
-            // Show timing in most appropriate unit
-            if lite.avg_time_ms >= 1.0 {
-                println!("  Merkle Lite: {:.3} ms avg, {:.2} comparisons/sec", lite.avg_time_ms, lite.throughput_comparisons_per_sec);
-                println!("  Merkle Tree: {:.3} ms avg, {:.2} comparisons/sec", tree.avg_time_ms, tree.throughput_comparisons_per_sec);
-            } else {
-                println!("  Merkle Lite: {:.1} μs avg, {:.2} comparisons/sec", lite.avg_time_us, lite.throughput_comparisons_per_sec);
-                println!("  Merkle Tree: {:.1} μs avg, {:.2} comparisons/sec", tree.avg_time_us, tree.throughput_comparisons_per_sec);
-            }
-            println!();
+        let differ = DomDiffer::new()
+            .chunking(chunking(1))
+            .method(method)
+            .mode(mode)
+            .line_diffs(true)
+            .ignore(ignore_rules);
+        let result = external.compare(&differ).unwrap_or_else(|e| {
+            eprintln!("Error: Could not read {}: {}", external.path, e);
+            std::process::exit(1);
+        });
+        let new_path = external.new_path.as_deref().unwrap_or(&external.path);
+        if let Some(format) = output_format {
+            print!("{}", render(format, &[FileComparison { old_path: &external.path, new_path, result: &result }]));
+            return;
         }
+        print_file_diff(&external.path, new_path, external.status(), &result, chunking(1));
+        return;
     }
-}
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
     
     if args.len() >= 2 {
         match args[1].as_str() {
@@ -876,7 +372,7 @@ fn main() {
                 
                 // Generate DOM versions and save to files
                 for i in 1..=num_versions {
-                    let version_content = if i % 7 == 0 || i % 11 == 0 {
+                    let version_content = if i.is_multiple_of(7) || i.is_multiple_of(11) {
                         base_content.clone()
                     } else {
                         generate_random_dom_with_changes(&base_content, i)
@@ -934,7 +430,8 @@ fn main() {
 </body>
 </html>"#;
                 
-                let results = generate_random_comparisons(base_html, num_comparisons, chunk_size, false, true);
+                let differ = DomDiffer::new().chunk_size(chunk_size).line_diffs(true);
+                let results = generate_random_comparisons(base_html, num_comparisons, &differ);
                 let json_output = serde_json::to_string_pretty(&results).expect("Failed to serialize to JSON");
                 
                 // Create result directory if it doesn't exist
@@ -998,7 +495,8 @@ fn main() {
 </body>
 </html>"#;
                 
-                let results = generate_random_comparisons(base_html, num_comparisons, chunk_size, false, false);
+                let differ = DomDiffer::new().chunk_size(chunk_size);
+                let results = generate_random_comparisons(base_html, num_comparisons, &differ);
                 let json_output = serde_json::to_string_pretty(&results).expect("Failed to serialize to JSON");
                 
                 // Create result directory if it doesn't exist
@@ -1038,13 +536,227 @@ fn main() {
                 return;
             },
             
+            "--patch" => {
+                if args.len() != 5 {
+                    eprintln!("Usage: {} --patch <old.html> <new.html> <patch.json>", args[0]);
+                    eprintln!("  Writes the delta that --apply replays onto old.html to get new.html");
+                    std::process::exit(1);
+                }
+                
+                let old_html = fs::read_to_string(&args[2]).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not read file {}", args[2]);
+                    std::process::exit(1);
+                });
+                let new_html = fs::read_to_string(&args[3]).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not read file {}", args[3]);
+                    std::process::exit(1);
+                });
+                
+                let patch = create_patch(&old_html, &new_html);
+                let json_output = serde_json::to_string(&patch).expect("Failed to serialize to JSON");
+                fs::write(&args[4], &json_output).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not write patch file {}", args[4]);
+                    std::process::exit(1);
+                });
+                
+                println!(
+                    "Patch saved to: {} ({} hunks, {} bytes inserted, target {} bytes)",
+                    args[4], patch.hunks.len(), patch.inserted_bytes(), patch.target_length
+                );
+                return;
+            },
+            
+            "--apply" => {
+                if args.len() < 4 || args.len() > 5 {
+                    eprintln!("Usage: {} --apply <old.html> <patch.json> [output.html]", args[0]);
+                    eprintln!("  Writes the patched document to output.html, or to stdout");
+                    std::process::exit(1);
+                }
+                
+                let old_html = fs::read_to_string(&args[2]).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not read file {}", args[2]);
+                    std::process::exit(1);
+                });
+                let patch = Patch::from_file(&args[3]).unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                });
+                let new_html = apply_patch(&old_html, &patch).unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                });
+                
+                match args.get(4) {
+                    Some(output) => {
+                        fs::write(output, &new_html).unwrap_or_else(|_| {
+                            eprintln!("Error: Could not write file {}", output);
+                            std::process::exit(1);
+                        });
+                        println!("Patched document saved to: {}", output);
+                    }
+                    None => print!("{}", new_html),
+                }
+                return;
+            },
+            
+            "--snapshot" => {
+                if args.len() < 4 || args.len() > 5 {
+                    eprintln!("Usage: {} --snapshot <name> <file.html> [chunk_size] [--store <dir>]", args[0]);
+                    eprintln!("  Diffs file.html against the last snapshot of <name>, then stores it as the new baseline");
+                    std::process::exit(1);
+                }
+                
+                let name = &args[2];
+                let file = &args[3];
+                let chunk_size: usize = if args.len() == 5 {
+                    args[4].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: chunk_size must be a number");
+                        std::process::exit(1);
+                    })
+                } else {
+                    1 // Default chunk size
+                };
+                
+                let html = fs::read_to_string(file).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not read file {}", file);
+                    std::process::exit(1);
+                });
+                let store = SnapshotStore::open(&store_dir).unwrap_or_else(|e| {
+                    eprintln!("Error: Could not open snapshot store {}: {}", store_dir, e);
+                    std::process::exit(1);
+                });
+                let baseline = store.baseline(name).unwrap_or_else(|e| {
+                    eprintln!("Error: Could not load baseline of {}: {}", name, e);
+                    std::process::exit(1);
+                });
+                
+                let start = std::time::Instant::now();
+                let (snapshot, stats) = Snapshot::build(&html, chunking(chunk_size), &ignore_rules, baseline.as_ref());
+                // Hashes from another algorithm or tree version never match, so such a baseline is only replaced
+                let diff = baseline
+                    .as_ref()
+                    .filter(|b| b.tree_hasher() == snapshot.tree_hasher())
+                    .map(|b| diff_snapshots(b, &snapshot));
+                let elapsed = start.elapsed();
+                
+                println!("Snapshot {}: root {} ({} chunks, {})", name, snapshot.root, snapshot.chunks.len(), snapshot.chunk_size);
+                println!(
+                    "Hashed {} chunks and {} internal nodes, reused {} and {} from the baseline ({} μs)",
+                    stats.hashed_leaves, stats.hashed_nodes, stats.reused_leaves, stats.reused_nodes, elapsed.as_micros()
+                );
+                match diff {
+                    Some(diff) => {
+                        println!("DOM diff against baseline {} is {:.2}%", diff.baseline_root, diff.difference_percent);
+                        println!(
+                            "Compared {} tree nodes, {} chunks differ from the baseline at the same position",
+                            diff.compared_nodes, diff.changed_chunks.len()
+                        );
+                        let mut ranges: Vec<(usize, usize)> = Vec::new();
+                        for &chunk in &diff.changed_chunks {
+                            match ranges.last_mut() {
+                                Some(range) if range.1 + 1 == chunk => range.1 = chunk,
+                                _ => ranges.push((chunk, chunk)),
+                            }
+                        }
+                        for (first, last) in ranges {
+                            let (line_first, line_last) = (snapshot.lines[first], snapshot.lines[last]);
+                            if line_first == line_last {
+                                println!("  chunks {}-{} (L{})", first, last, line_first);
+                            } else {
+                                println!("  chunks {}-{} (L{}-L{})", first, last, line_first, line_last);
+                            }
+                        }
+                    }
+                    None => match baseline {
+                        Some(b) => println!(
+                            "Baseline {} of {} uses {} hashes with tree version {}, not compared",
+                            b.root, name, b.hash_algorithm.as_str(), b.tree_version
+                        ),
+                        None => println!("No baseline for {} yet", name),
+                    },
+                }
+                
+                store.save(&snapshot).and_then(|_| store.set_ref(name, &snapshot.root)).unwrap_or_else(|e| {
+                    eprintln!("Error: Could not store snapshot: {}", e);
+                    std::process::exit(1);
+                });
+                println!("Stored as new baseline in {}", store_dir);
+                return;
+            },
+            
+            "--prove" => {
+                if args.len() < 4 || args.len() > 5 {
+                    eprintln!("Usage: {} --prove <file.html> <chunk_index> [chunk_size]", args[0]);
+                    eprintln!("  Writes a JSON inclusion proof for one chunk to stdout");
+                    std::process::exit(1);
+                }
+                
+                let html = fs::read_to_string(&args[2]).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not read file {}", args[2]);
+                    std::process::exit(1);
+                });
+                let index: usize = args[3].parse().unwrap_or_else(|_| {
+                    eprintln!("Error: chunk_index must be a number");
+                    std::process::exit(1);
+                });
+                let chunk_size: usize = if args.len() == 5 {
+                    args[4].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: chunk_size must be a number");
+                        std::process::exit(1);
+                    })
+                } else {
+                    1 // Default chunk size
+                };
+                
+                let (snapshot, _) = Snapshot::build(&html, chunking(chunk_size), &ignore_rules, None);
+                let proof = prove_chunk(&snapshot, index).unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                });
+                println!("{}", serde_json::to_string_pretty(&proof).expect("Failed to serialize to JSON"));
+                return;
+            },
+            
+            "--verify-proof" => {
+                if args.len() < 3 || args.len() > 4 {
+                    eprintln!("Usage: {} --verify-proof <proof.json> [root]", args[0]);
+                    eprintln!("  root: Published root hash to check against (default: the root in the proof)");
+                    std::process::exit(1);
+                }
+                
+                let proof = InclusionProof::from_file(&args[2]).unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                });
+                let root = args.get(3).unwrap_or(&proof.root);
+                if let Err(e) = verify_proof(&proof, root) {
+                    eprintln!("Proof invalid: {}", e);
+                    std::process::exit(1);
+                }
+                
+                println!(
+                    "Proof valid: chunk {} of {} (L{}, {}) is included under root {}",
+                    proof.chunk_index, proof.chunk_count, proof.line, proof.chunk_size, root
+                );
+                if args.len() == 3 {
+                    println!("Note: checked against the root recorded in the proof; pass the published root to rely on it");
+                }
+                return;
+            },
+            
             "--line-diff" => {
                 if args.len() < 4 || args.len() > 5 {
-                    eprintln!("Usage: {} --line-diff <file1.html> <file2.html> [chunk_size]", args[0]);
+                    eprintln!("Usage: {} --line-diff <file1.html> <file2.html> [chunk_size] [--unified | --side-by-side]", args[0]);
                     eprintln!("  Generates detailed line-by-line diff with L100-L120 format");
+                    eprintln!("  --unified prints a unified diff of the sources instead, --side-by-side shows them in two columns");
                     std::process::exit(1);
                 }
                 
+                if stream {
+                    eprintln!("Error: --line-diff needs both documents in memory and cannot be used with --stream");
+                    std::process::exit(1);
+                }
+
                 let file1 = &args[2];
                 let file2 = &args[3];
                 let chunk_size: usize = if args.len() == 5 {
@@ -1066,12 +778,35 @@ fn main() {
                     std::process::exit(1);
                 });
                 
-                let (percent, total_a, total_b, common, different, duration_ms, duration_us, duration_ns, line_diffs) = 
-                    compare_with_line_diffs(&content1, &content2, chunk_size, false);
+                let mut result = DomDiffer::new()
+                    .chunking(chunking(chunk_size))
+                    .method(method)
+                    .mode(mode)
+                    .line_diffs(true)
+                    .ignore(ignore_rules.clone())
+                    .compare_named(file1, &content1, file2, &content2);
+                result.method = format!("{}_with_lines", method.as_str());
+                let summary = (!assertions.is_empty()).then(|| assertions.check(&result, &content1, &content2, &ignore_rules));
+                
+                if let Some(format) = output_format {
+                    print!("{}", render(format, &[FileComparison { old_path: file1, new_path: file2, result: &result }]));
+                    finish_assertions(summary);
+                    return;
+                }
                 
+                // A unified diff goes to stdout on its own, so it can be piped to patch tools
+                if unified {
+                    print!("{}", unified_diff(&result, &content1, &content2, context));
+                    finish_assertions(summary);
+                    return;
+                }
+                
+                let (duration_ms, duration_us, duration_ns) =
+                    (result.processing_time_ms, result.processing_time_us, result.processing_time_ns);
+
                 println!("=== LINE DIFF ANALYSIS ===");
                 println!("Files: {} vs {}", file1, file2);
-                println!("Overall difference: {:.2}%", percent);
+                println!("Overall difference: {:.2}%", result.difference_percent);
                 
                 // Display timing in most appropriate unit
                 if duration_ms >= 1 {
@@ -1081,19 +816,24 @@ fn main() {
                 } else {
                     println!("Processing time: {} ns ({} μs, {} ms)", duration_ns, duration_us, duration_ms);
                 }
-                println!("Total chunks: {} vs {}", total_a, total_b);
-                println!("Common chunks: {}, Different chunks: {}", common, different);
+                println!("Total chunks: {} vs {}", result.total_chunks_a, result.total_chunks_b);
+                println!("Common chunks: {}, Different chunks: {}", result.common_chunks, result.different_chunks);
                 println!();
                 
-                if line_diffs.is_empty() {
+                if side_by_side_view {
+                    let color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+                    println!("=== SIDE-BY-SIDE ===");
+                    print!("{}", side_by_side(&result, &content1, &content2, context, width, color));
+                    println!();
+                } else if result.line_diffs.is_empty() {
                     println!("No line-level differences found.");
                 } else {
                     println!("=== LINE-BY-LINE CHANGES ===");
-                    for diff in &line_diffs {
+                    for diff in &result.line_diffs {
                         println!("{}: {}", diff.line_range, diff.content_preview);
                     }
                     println!();
-                    println!("Total line changes: {}", line_diffs.len());
+                    println!("Total line changes: {}", result.line_diffs.len());
                 }
                 
                 // Save detailed results to JSON
@@ -1106,27 +846,247 @@ fn main() {
                 let timestamp = now.format("%Y%m%d_%H%M%S").to_string();
                 let filename = format!("result/line-diff-{}.json", timestamp);
                 
-                let result = ComparisonResult {
-                    version_a: file1.to_string(),
-                    version_b: file2.to_string(),
-                    difference_percent: percent,
-                    total_chunks_a: total_a,
-                    total_chunks_b: total_b,
-                    common_chunks: common,
-                    different_chunks: different,
-                    method: "merkle_lite_with_lines".to_string(),
-                    processing_time_ms: duration_ms,
-                    processing_time_us: duration_us,
-                    processing_time_ns: duration_ns,
-                    line_diffs,
+                let json_output = serde_json::to_string_pretty(&result).expect("Failed to serialize to JSON");
+                fs::write(&filename, &json_output).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not write result file {}", filename);
+                    std::process::exit(1);
+                });
+                
+                println!("Detailed results saved to: {}", filename);
+                if html_report_requested {
+                    println!("HTML report saved to: {}", save_html_report(&result, &content1, &content2, &ignore_rules));
+                }
+                print_assertions(summary.as_ref());
+                finish_assertions(summary);
+                return;
+            },
+            
+            "--diff-dir" => {
+                if args.len() < 4 || args.len() > 5 {
+                    eprintln!("Usage: {} --diff-dir <old_dir> <new_dir> [chunk_size]", args[0]);
+                    eprintln!("  Compares the .html pages of two site snapshots, matched by relative path");
+                    std::process::exit(1);
+                }
+                
+                if stream {
+                    eprintln!("Error: --diff-dir compares pages in memory and cannot be used with --stream");
+                    std::process::exit(1);
+                }
+                
+                let old_dir = &args[2];
+                let new_dir = &args[3];
+                let chunk_size: usize = if args.len() == 5 {
+                    args[4].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: chunk_size must be a number");
+                        std::process::exit(1);
+                    })
+                } else {
+                    1 // Default chunk size
                 };
                 
-                let json_output = serde_json::to_string_pretty(&result).expect("Failed to serialize to JSON");
+                let differ = DomDiffer::new()
+                    .chunking(chunking(chunk_size))
+                    .method(method)
+                    .mode(mode)
+                    .line_diffs(output_format.is_some())
+                    .ignore(ignore_rules);
+                let site = diff_directories(old_dir, new_dir, &differ).unwrap_or_else(|e| {
+                    eprintln!("Error: Could not read site directories: {}", e);
+                    std::process::exit(1);
+                });
+                
+                if let Some(format) = output_format {
+                    for skipped in &site.skipped {
+                        eprintln!("Skipped {}", skipped);
+                    }
+                    // Annotations point at the pages themselves, not at their relative paths
+                    let paths: Vec<(String, String)> = site
+                        .pages
+                        .iter()
+                        .map(|p| (format!("{}/{}", old_dir.trim_end_matches('/'), p.path), format!("{}/{}", new_dir.trim_end_matches('/'), p.path)))
+                        .collect();
+                    let files: Vec<FileComparison> = site
+                        .pages
+                        .iter()
+                        .zip(&paths)
+                        .filter_map(|(page, (old_path, new_path))| {
+                            page.result.as_ref().map(|result| FileComparison { old_path, new_path, result })
+                        })
+                        .collect();
+                    print!("{}", render(format, &files));
+                    return;
+                }
+                
+                println!("=== SITE DIFF ===");
+                println!("Sites: {} vs {}", old_dir, new_dir);
+                println!("Site difference: {:.2}% ({})", site.difference_percent, chunking(chunk_size));
+                println!(
+                    "Pages: {} vs {} ({} added, {} removed, {} changed, {} unchanged)",
+                    site.pages_old, site.pages_new, site.added, site.removed, site.changed, site.unchanged
+                );
+                println!("Processing time: {} ms", site.processing_time_ms);
+                
+                let mut listed: Vec<_> = site.pages.iter().filter(|p| p.status != PageStatus::Unchanged).collect();
+                listed.sort_by(|a, b| b.difference_percent.total_cmp(&a.difference_percent).then(a.path.cmp(&b.path)));
+                if !listed.is_empty() {
+                    println!();
+                    for page in listed {
+                        println!("  {:<9} {:>6.2}%  {}", page.status.as_str(), page.difference_percent, page.path);
+                    }
+                }
+                for skipped in &site.skipped {
+                    eprintln!("Skipped {}", skipped);
+                }
+                
+                fs::create_dir_all("result").unwrap_or_else(|_| {
+                    eprintln!("Error: Could not create result directory");
+                    std::process::exit(1);
+                });
+                
+                let now: DateTime<Utc> = Utc::now();
+                let timestamp = now.format("%Y%m%d_%H%M%S").to_string();
+                let filename = format!("result/site-diff-{}.json", timestamp);
+                
+                let json_output = serde_json::to_string_pretty(&site).expect("Failed to serialize to JSON");
+                fs::write(&filename, &json_output).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not write result file {}", filename);
+                    std::process::exit(1);
+                });
+                
+                println!();
+                println!("Detailed results saved to: {}", filename);
+                return;
+            },
+            
+            "--git" => {
+                if args.len() < 3 {
+                    eprintln!("Usage: {} --git <rev_a> [rev_b] [paths...]", args[0]);
+                    eprintln!("  Compares the .html files changed between two commits, or rev_a and the working tree");
+                    std::process::exit(1);
+                }
+                
+                // A second revision is optional, as with `git diff`; `--` ends the revisions
+                let rev_a = &args[2];
+                let mut rest = &args[3..];
+                let rev_b = match rest.first() {
+                    Some(arg) if arg == "--" => {
+                        rest = &rest[1..];
+                        None
+                    }
+                    Some(arg) if is_revision(arg) => {
+                        rest = &rest[1..];
+                        Some(arg.as_str())
+                    }
+                    _ => None,
+                };
+                let paths: Vec<String> = rest.iter().filter(|p| *p != "--").cloned().collect();
+                
+                let differ = DomDiffer::new()
+                    .chunking(chunking(1))
+                    .method(method)
+                    .mode(mode)
+                    .line_diffs(true)
+                    .ignore(ignore_rules);
+                let diffs = diff_revisions(rev_a, rev_b, &paths, &differ).unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                });
+                
+                if let Some(format) = output_format {
+                    let files: Vec<FileComparison> = diffs
+                        .iter()
+                        .map(|d| FileComparison { old_path: &d.path, new_path: &d.path, result: &d.result })
+                        .collect();
+                    print!("{}", render(format, &files));
+                    return;
+                }
+                
+                for (i, diff) in diffs.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    print_file_diff(&diff.path, &diff.path, diff.status, &diff.result, chunking(1));
+                }
+                if !diffs.is_empty() {
+                    println!();
+                }
+                println!(
+                    "Changed HTML files between {} and {}: {}",
+                    rev_a, rev_b.unwrap_or("the working tree"), diffs.len()
+                );
+                return;
+            },
+            
+            "--cluster" => {
+                if args.len() < 3 || args.len() > 4 {
+                    eprintln!("Usage: {} --cluster <dir> [chunk_size] [--threshold <0..1>] [--sketch <minhash|simhash>]", args[0]);
+                    eprintln!("  Groups the near-duplicate .html pages under <dir>");
+                    std::process::exit(1);
+                }
+                
+                let dir = &args[2];
+                let chunk_size: usize = if args.len() == 4 {
+                    args[3].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: chunk_size must be a number");
+                        std::process::exit(1);
+                    })
+                } else {
+                    1 // Default chunk size
+                };
+                
+                let options = ClusterOptions {
+                    kind: sketch_kind,
+                    threshold,
+                    signature_size,
+                    chunking: chunking(chunk_size),
+                    rules: ignore_rules,
+                };
+                let report = cluster_directory(dir, &options).unwrap_or_else(|e| {
+                    eprintln!("Error: Could not read directory {}: {}", dir, e);
+                    std::process::exit(1);
+                });
+                
+                println!("=== NEAR-DUPLICATE CLUSTERS ===");
+                println!(
+                    "Sketched {} pages in {} ms ({}, {} similarity threshold)",
+                    report.pages, report.processing_time_ms, report.sketch, report.threshold
+                );
+                match (report.lsh, report.max_distance) {
+                    (Some(lsh), _) => println!("LSH: {} bands of {} rows", lsh.bands, lsh.rows),
+                    (None, Some(bits)) => println!("LSH: pairs within {} of 64 bits", bits),
+                    (None, None) => {}
+                }
+                println!("Similar pairs: {}, groups: {}", report.similar_pairs, report.groups.len());
+                for (i, group) in report.groups.iter().enumerate() {
+                    println!();
+                    println!(
+                        "Group {} ({} pages, similarity {:.2}-{:.2}):",
+                        i + 1, group.pages.len(), group.min_similarity, group.max_similarity
+                    );
+                    for page in &group.pages {
+                        println!("  {}", page);
+                    }
+                }
+                for skipped in &report.skipped {
+                    eprintln!("Skipped {}", skipped);
+                }
+                
+                fs::create_dir_all("result").unwrap_or_else(|_| {
+                    eprintln!("Error: Could not create result directory");
+                    std::process::exit(1);
+                });
+                
+                let now: DateTime<Utc> = Utc::now();
+                let timestamp = now.format("%Y%m%d_%H%M%S").to_string();
+                let filename = format!("result/cluster-{}.json", timestamp);
+                
+                let json_output = serde_json::to_string_pretty(&report).expect("Failed to serialize to JSON");
                 fs::write(&filename, &json_output).unwrap_or_else(|_| {
                     eprintln!("Error: Could not write result file {}", filename);
                     std::process::exit(1);
                 });
                 
+                println!();
                 println!("Detailed results saved to: {}", filename);
                 return;
             },
@@ -1142,7 +1102,40 @@ fn main() {
         eprintln!("   or: {} --compare-random-fast <num_comparisons> [chunk_size]  (without line diffs)", args[0]);
         eprintln!("   or: {} --benchmark <num_tests>", args[0]);
         eprintln!("   or: {} --line-diff <file1.html> <file2.html> [chunk_size]", args[0]);
+        eprintln!("   or: {} --patch <old.html> <new.html> <patch.json>", args[0]);
+        eprintln!("   or: {} --apply <old.html> <patch.json> [output.html]", args[0]);
+        eprintln!("   or: {} --snapshot <name> <file.html> [chunk_size]  (incremental diff against a stored baseline)", args[0]);
+        eprintln!("   or: {} --prove <file.html> <chunk_index> [chunk_size]  (inclusion proof on stdout)", args[0]);
+        eprintln!("   or: {} --verify-proof <proof.json> [root]", args[0]);
+        eprintln!("   or: {} --cluster <dir> [chunk_size]  (groups of near-duplicate pages)", args[0]);
+        eprintln!("   or: {} --diff-dir <old_dir> <new_dir> [chunk_size]  (site diff by relative path)", args[0]);
+        eprintln!("   or: {} --git <rev_a> [rev_b] [paths...]  (changed .html files, or GIT_EXTERNAL_DIFF={})", args[0], args[0]);
         eprintln!("  chunk_size: Number of tokens per chunk (default: 1)");
+        eprintln!("  --cdc <avg|min,avg,max>: Content-defined chunk boundaries instead of chunk_size (not for --compare-random)");
+        eprintln!("  --element-depth <n>: One chunk per element subtree at depth n (0 = top level) instead of chunk_size");
+        eprintln!("  --element-tokens <n>: One chunk per element subtree of at most n tokens (with or without --element-depth)");
+        eprintln!("  --ignore <config.json>: Mask volatile attributes, values and subtrees before hashing");
+        eprintln!("  --method <merkle_lite|merkle_tree|dom_tree|tree_edit>: Comparison method (default: merkle_lite)");
+        eprintln!("  --ordered: Compare chunks as ordered sequences, reporting insertions, deletions and moves");
+        eprintln!("  --hash <xxh3_64|xxh3_128|sha256|blake3>: Chunk hash algorithm (default: xxh3_64)");
+        eprintln!("  --hash-cache <capacity>: Cache up to <capacity> chunk hashes, reporting hits and misses (default: off)");
+        eprintln!("  --tree-version <1|2>: Merkle node hashing for --snapshot and --prove (default: 2)");
+        eprintln!("  --store <dir>: Snapshot store for --snapshot (default: .merkle-store)");
+        eprintln!("  --stream: Read both files incrementally in bounded memory (set-based merkle_lite only)");
+        eprintln!("  --sketch-size <n>: Distinct chunk hashes kept per file with --stream (default: 16384)");
+        eprintln!("  --threshold <0..1>: Similarity at which --cluster groups pages (default: 0.8)");
+        eprintln!("  --sketch <minhash|simhash>: Page sketch used by --cluster (default: minhash)");
+        eprintln!("  --signature-size <n>: MinHash values per page with --cluster (default: 128)");
+        eprintln!("  --unified: Print a unified diff of the sources with --line-diff, ready for patch tools");
+        eprintln!("  --side-by-side: Show both sources in two columns with --line-diff (coloured on a terminal)");
+        eprintln!("  --context <n>: Unchanged lines around each hunk of --unified and --side-by-side (default: 3)");
+        eprintln!("  --width <n>: Total width of --side-by-side (default: $COLUMNS, or 160)");
+        eprintln!("  --report html: Also write a self-contained HTML report (plain comparison and --line-diff)");
+        eprintln!("  --format <junit|sarif>: Print line changes as JUnit XML or SARIF for CI (plain comparison, --line-diff, --git, --diff-dir)");
+        eprintln!("  --fail-above <percent>: Fail the plain comparison or --line-diff if the difference is above <percent>");
+        eprintln!("  --max-added <n>, --max-removed <n>: Fail the plain comparison or --line-diff if more than <n> lines were added or removed");
+        eprintln!("  --forbid-change <selector>: Fail the plain comparison or --line-diff if an element matching <selector> changed (repeatable)");
+        eprintln!("  Failed assertions exit with status 3 and print a JSON summary on stderr");
         std::process::exit(1);
     }
 
@@ -1158,13 +1151,115 @@ fn main() {
         1 // Default chunk size
     };
 
-    let hashes1 = process_file(file1, chunk_size);
-    let hashes2 = process_file(file2, chunk_size);
-
-    let (percent, _total_a, _total_b, _common, _different) = detailed_diff(&hashes1, &hashes2);
+    // The report, CI formats and line limits need the changed lines of both documents
+    let differ = DomDiffer::new()
+        .chunking(chunking(chunk_size))
+        .method(method)
+        .mode(mode)
+        .line_diffs(html_report_requested || output_format.is_some() || assertions.needs_line_diffs())
+        .ignore(ignore_rules.clone())
+        .sketch_capacity(sketch_size);
+    let result = if stream { differ.compare_files_streaming(file1, file2) } else { differ.compare_files(file1, file2) }
+        .unwrap_or_else(|e| {
+            eprintln!("Error: Could not read input files: {}", e);
+            std::process::exit(1);
+        });
+    
+    // The sources are read again only for the report and protected regions
+    let read = |file: &str| {
+        fs::read_to_string(file).unwrap_or_else(|_| {
+            eprintln!("Error: Could not read file {}", file);
+            std::process::exit(1);
+        })
+    };
+    let documents = (html_report_requested || !assertions.forbid_change.is_empty()).then(|| (read(file1), read(file2)));
+    let (html_a, html_b) = documents.as_ref().map(|(a, b)| (a.as_str(), b.as_str())).unwrap_or(("", ""));
+    let summary = (!assertions.is_empty()).then(|| assertions.check(&result, html_a, html_b, &ignore_rules));
+    
+    if let Some(format) = output_format {
+        print!("{}", render(format, &[FileComparison { old_path: file1, new_path: file2, result: &result }]));
+        finish_assertions(summary);
+        return;
+    }
+    let percent = result.difference_percent;
 
     println!(
-        "DOM diff between {} and {} is {:.2}% (chunk size: {})",
-        file1, file2, percent, chunk_size
+        "DOM diff between {} and {} is {:.2}% ({})",
+        file1, file2, percent, chunking(chunk_size)
     );
+    if let Some(stats) = hash_cache_stats() {
+        println!(
+            "Hash cache: {} hits, {} misses ({:.1}% hit rate), {} evictions",
+            stats.hits, stats.misses, stats.hit_rate() * 100.0, stats.evictions
+        );
+    }
+    if let Some(sketch) = &result.sketch
+        && !sketch.exact
+    {
+        println!(
+            "Estimated from {}-hash sketches: about {} common and {} different distinct chunks",
+            sketch.capacity, result.common_chunks, result.different_chunks
+        );
+    }
+    
+    if !result.subtree_changes.is_empty() {
+        println!("Changed subtrees: {}", result.subtree_changes.len());
+        for change in &result.subtree_changes {
+            let lines = match (change.line_a, change.line_b) {
+                (Some(a), Some(b)) => format!("L{} -> L{}", a, b),
+                (Some(a), None) => format!("L{}", a),
+                (None, Some(b)) => format!("L{}", b),
+                (None, None) => String::new(),
+            };
+            println!("  {} ({}): {}", change.path, lines, change.content_preview);
+        }
+    }
+
+    if !result.sequence_edits.is_empty() {
+        println!("Sequence edits: {}", result.sequence_edits.len());
+        for edit in &result.sequence_edits {
+            let lines = match (edit.line_a, edit.line_b) {
+                (Some(a), Some(b)) => format!("L{} -> L{}", a, b),
+                (Some(a), None) => format!("L{}", a),
+                (None, Some(b)) => format!("L{}", b),
+                (None, None) => String::new(),
+            };
+            println!("  {} x{} ({}): {}", edit.change_type, edit.length, lines, edit.content_preview);
+        }
+    }
+
+    if !result.edit_script.is_empty() {
+        println!("Edit script: {} operations", result.edit_script.len());
+        for op in &result.edit_script {
+            let detail = match op.change_type.as_str() {
+                "insert" => format!(
+                    "{} into {} at {}",
+                    op.content.as_deref().unwrap_or("").chars().take(60).collect::<String>(),
+                    op.parent.as_deref().unwrap_or(""),
+                    op.position.unwrap_or(0)
+                ),
+                "move" => format!("into {} at {}", op.parent.as_deref().unwrap_or(""), op.position.unwrap_or(0)),
+                "update_attribute" => format!(
+                    "{}: {} -> {}",
+                    op.attribute.as_deref().unwrap_or(""),
+                    op.old_value.as_deref().unwrap_or("(none)"),
+                    op.new_value.as_deref().unwrap_or("(none)")
+                ),
+                "update_text" => format!(
+                    "{} -> {}",
+                    op.old_value.as_deref().unwrap_or("").chars().take(60).collect::<String>(),
+                    op.new_value.as_deref().unwrap_or("").chars().take(60).collect::<String>()
+                ),
+                _ => String::new(),
+            };
+            println!("  {} {} {}", op.change_type, op.path, detail);
+        }
+    }
+
+    if html_report_requested {
+        println!("HTML report saved to: {}", save_html_report(&result, html_a, html_b, &ignore_rules));
+    }
+
+    print_assertions(summary.as_ref());
+    finish_assertions(summary);
 }
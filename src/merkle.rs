@@ -0,0 +1,323 @@
+use std::sync::Arc;
+use rayon::prelude::*;
+
+use crate::hashing::{HashAlgorithm, from_hex, get_perf_config, hash_chunk_with, hash_chunks, to_hex};
+
+#[derive(Debug, Clone)]
+pub struct MerkleNode {
+    pub hash: String,
+    pub left: Option<Arc<MerkleNode>>,
+    pub right: Option<Arc<MerkleNode>>,
+}
+
+// Optimized MerkleNode creation
+impl MerkleNode {
+    pub fn new_leaf(hash: String) -> Self {
+        MerkleNode {
+            hash,
+            left: None,
+            right: None,
+        }
+    }
+
+    pub fn new_internal(hash: String, left: Arc<MerkleNode>, right: Option<Arc<MerkleNode>>) -> Self {
+        MerkleNode {
+            hash,
+            left: Some(left),
+            right,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.left.is_none() && self.right.is_none()
+    }
+}
+
+// How tree nodes are hashed. Stored snapshots and proofs record the version
+// they were built with, so roots published under version 1 still verify.
+//
+//   1  leaf = H(chunk), node = H(hex(left) + hex(right)); an odd node is
+//      carried up unchanged
+//   2  leaf = H(0x00 || chunk), node = H(0x01 || left || right) over the raw
+//      digests; an odd node becomes H(0x02 || child)
+//
+// Version 2 keeps leaves and internal nodes apart, so no chunk can pose as a
+// subtree, and every node above the leaves is hashed.
+pub const TREE_VERSION: u32 = 2;
+
+// Version of trees stored before it was recorded
+pub(crate) fn legacy_tree_version() -> u32 {
+    1
+}
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+const ODD_NODE_PREFIX: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeHasher {
+    pub algorithm: HashAlgorithm,
+    pub version: u32,
+}
+
+impl TreeHasher {
+    pub fn new(algorithm: HashAlgorithm, version: u32) -> Result<Self, String> {
+        match version {
+            1 | 2 => Ok(TreeHasher { algorithm, version }),
+            _ => Err(format!("unsupported tree version {} (expected 1 or 2)", version)),
+        }
+    }
+
+    // Algorithm and version from the global PerformanceConfig
+    pub fn from_config() -> Self {
+        let config = get_perf_config();
+        TreeHasher {
+            algorithm: config.hash_algorithm,
+            version: config.tree_version,
+        }
+    }
+
+    pub fn leaf(&self, chunk: &str) -> String {
+        match self.version {
+            1 => hash_chunk_with(self.algorithm, chunk),
+            _ => self.hash_parts(&[&[LEAF_PREFIX], chunk.as_bytes()]),
+        }
+    }
+
+    pub fn node(&self, left: &str, right: &str) -> String {
+        match self.version {
+            1 => hash_chunk_with(self.algorithm, &format!("{}{}", left, right)),
+            _ => self.hash_parts(&[&[NODE_PREFIX], &raw_digest(left), &raw_digest(right)]),
+        }
+    }
+
+    // Parent of the last node of an odd-sized level
+    pub fn odd_node(&self, child: &str) -> String {
+        match self.version {
+            1 => child.to_string(),
+            _ => self.hash_parts(&[&[ODD_NODE_PREFIX], &raw_digest(child)]),
+        }
+    }
+
+    // Whether `hash` has the form this hasher produces, for untrusted input
+    pub fn is_well_formed(&self, hash: &str) -> bool {
+        match self.version {
+            1 => !hash.is_empty() && hash.bytes().all(|b| b.is_ascii_hexdigit()),
+            _ => from_hex(hash).is_some_and(|raw| raw.len() == self.algorithm.hasher().digest(&[]).as_bytes().len()),
+        }
+    }
+
+    fn hash_parts(&self, parts: &[&[u8]]) -> String {
+        let hasher = self.algorithm.hasher();
+        to_hex(hasher.digest(&parts.concat()).as_bytes())
+    }
+}
+
+// Raw bytes of a hex digest; hashes from this crate always decode
+fn raw_digest(hash: &str) -> Vec<u8> {
+    from_hex(hash).unwrap_or_else(|| hash.as_bytes().to_vec())
+}
+
+// Merkle Lite: Simple hashing approach (current implementation)
+pub fn merkle_lite_hash(chunks: &[String]) -> Vec<String> {
+    hash_chunks(chunks)
+}
+
+// Optimized Full Merkle Tree implementation with Arc to avoid cloning
+pub fn build_merkle_tree(chunks: &[String]) -> Option<Arc<MerkleNode>> {
+    // Use parallel iterator for leaf hashing
+    let hasher = TreeHasher::from_config();
+    let leaf_hashes: Vec<String> = chunks.par_iter().map(|chunk| hasher.leaf(chunk)).collect();
+    build_merkle_tree_from_hashes(leaf_hashes, hasher, |_, _| None)
+}
+
+// Build a tree over precomputed leaf hashes. `known` may supply the hash of an
+// internal node from its children's hashes (e.g. from a stored tree), which
+// skips hashing it again.
+pub fn build_merkle_tree_from_hashes<F>(leaf_hashes: Vec<String>, hasher: TreeHasher, mut known: F) -> Option<Arc<MerkleNode>>
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    if leaf_hashes.is_empty() {
+        return None;
+    }
+
+    let mut nodes: Vec<Arc<MerkleNode>> = leaf_hashes
+        .into_iter()
+        .map(|hash| Arc::new(MerkleNode::new_leaf(hash)))
+        .collect();
+
+    while nodes.len() > 1 {
+        let mut next_level = Vec::new();
+
+        for i in (0..nodes.len()).step_by(2) {
+            let left = nodes[i].clone(); // Arc clone is cheap
+            let right = if i + 1 < nodes.len() {
+                Some(nodes[i + 1].clone())
+            } else {
+                None
+            };
+
+            let combined_hash = if let Some(ref r) = right {
+                known(&left.hash, &r.hash).unwrap_or_else(|| hasher.node(&left.hash, &r.hash))
+            } else {
+                hasher.odd_node(&left.hash)
+            };
+
+            next_level.push(Arc::new(MerkleNode::new_internal(combined_hash, left, right)));
+        }
+
+        nodes = next_level;
+    }
+
+    nodes.into_iter().next()
+}
+
+// Hashes of each tree level, leaves first and the root last. Every leaf sits
+// at the same depth (an odd node gets a one-child parent), so
+// level `k`, index `i` always covers leaves `i * 2^k .. (i + 1) * 2^k`.
+pub fn merkle_levels(root: &Arc<MerkleNode>) -> Vec<Vec<String>> {
+    let mut levels = Vec::new();
+    let mut level = vec![root.clone()];
+    while !level.is_empty() {
+        levels.push(level.iter().map(|n| n.hash.clone()).collect());
+        level = level
+            .iter()
+            .flat_map(|n| n.left.iter().chain(n.right.iter()).cloned())
+            .collect();
+    }
+    levels.reverse();
+    levels
+}
+
+// Sibling hashes on the way from leaf `index` to the root, bottom first. An
+// odd node has no sibling and adds nothing to the path.
+pub fn merkle_path(levels: &[Vec<String>], index: usize) -> Option<Vec<String>> {
+    levels.first()?.get(index)?;
+    let mut path = Vec::new();
+    let mut i = index;
+    for level in &levels[..levels.len() - 1] {
+        if let Some(sibling) = level.get(i ^ 1) {
+            path.push(sibling.clone());
+        }
+        i /= 2;
+    }
+    Some(path)
+}
+
+// Root hash implied by a leaf and its sibling path. The leaf's position and
+// the number of leaves decide which side each sibling is on; None if the path
+// does not fit that tree shape or holds malformed hashes.
+pub fn merkle_root_from_path(
+    hasher: TreeHasher,
+    leaf_hash: &str,
+    index: usize,
+    leaf_count: usize,
+    path: &[String],
+) -> Option<String> {
+    if index >= leaf_count
+        || !hasher.is_well_formed(leaf_hash)
+        || !path.iter().all(|h| hasher.is_well_formed(h))
+    {
+        return None;
+    }
+    let mut hash = leaf_hash.to_string();
+    let mut siblings = path.iter();
+    let (mut i, mut width) = (index, leaf_count);
+    while width > 1 {
+        if i % 2 == 1 {
+            hash = hasher.node(siblings.next()?, &hash);
+        } else if i + 1 < width {
+            hash = hasher.node(&hash, siblings.next()?);
+        } else {
+            hash = hasher.odd_node(&hash);
+        }
+        i /= 2;
+        width = width.div_ceil(2);
+    }
+    if siblings.next().is_some() {
+        return None;
+    }
+    Some(hash)
+}
+
+pub fn extract_merkle_hashes(node: &Arc<MerkleNode>) -> Vec<String> {
+    let mut hashes = Vec::new();
+
+    // Collect leaf node hashes with optimized traversal
+    fn collect_leaves(node: &Arc<MerkleNode>, hashes: &mut Vec<String>) {
+        if node.is_leaf() {
+            hashes.push(node.hash.clone());
+        } else {
+            if let Some(ref left) = node.left {
+                collect_leaves(left, hashes);
+            }
+            if let Some(ref right) = node.right {
+                collect_leaves(right, hashes);
+            }
+        }
+    }
+
+    collect_leaves(node, &mut hashes);
+    hashes
+}
+
+pub fn merkle_tree_hash(chunks: &[String]) -> Vec<String> {
+    if let Some(tree) = build_merkle_tree(chunks) {
+        extract_merkle_hashes(&tree)
+    } else {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_path_leads_back_to_the_root() {
+        for version in [1, 2] {
+            let hasher = TreeHasher::new(HashAlgorithm::Sha256, version).unwrap();
+            for count in 1..=17 {
+                let leaves: Vec<String> = (0..count).map(|i| hasher.leaf(&format!("chunk {}", i))).collect();
+                let tree = build_merkle_tree_from_hashes(leaves.clone(), hasher, |_, _| None).unwrap();
+                let levels = merkle_levels(&tree);
+                assert_eq!(levels[0], leaves);
+                for (index, leaf) in leaves.iter().enumerate() {
+                    let path = merkle_path(&levels, index).unwrap();
+                    assert_eq!(
+                        merkle_root_from_path(hasher, leaf, index, count, &path).as_deref(),
+                        Some(tree.hash.as_str()),
+                        "v{version}, leaf {index} of {count}"
+                    );
+                }
+                assert!(merkle_path(&levels, count).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn paths_that_do_not_fit_the_tree_shape_are_rejected() {
+        let hasher = TreeHasher::new(HashAlgorithm::Sha256, TREE_VERSION).unwrap();
+        let leaves: Vec<String> = (0..5).map(|i| hasher.leaf(&i.to_string())).collect();
+        let tree = build_merkle_tree_from_hashes(leaves.clone(), hasher, |_, _| None).unwrap();
+        let path = merkle_path(&merkle_levels(&tree), 1).unwrap();
+
+        let mut long = path.clone();
+        long.push(leaves[0].clone());
+        assert_eq!(merkle_root_from_path(hasher, &leaves[1], 1, 5, &long), None);
+        assert_eq!(merkle_root_from_path(hasher, &leaves[1], 1, 5, &path[..path.len() - 1]), None);
+        assert_eq!(merkle_root_from_path(hasher, &leaves[1], 5, 5, &path), None);
+        assert_eq!(merkle_root_from_path(hasher, "not hex", 1, 5, &path), None);
+    }
+
+    #[test]
+    fn version_2_separates_leaves_from_nodes() {
+        let hasher = TreeHasher::new(HashAlgorithm::Xxh3_64, 2).unwrap();
+        let (a, b) = (hasher.leaf("a"), hasher.leaf("b"));
+        assert_ne!(hasher.node(&a, &b), hasher.leaf(&format!("{}{}", a, b)));
+        assert_ne!(hasher.odd_node(&a), a);
+        let legacy = TreeHasher::new(HashAlgorithm::Xxh3_64, 1).unwrap();
+        assert_eq!(legacy.odd_node(&a), a);
+    }
+}
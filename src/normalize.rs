@@ -0,0 +1,362 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::chunking::{ChunkJoiner, Chunking, chunk_ranges};
+use crate::hashing::{HashAlgorithm, HashDigest};
+use crate::ignore::{IgnoreRules, MASK};
+use crate::tokenizer::{
+    HtmlToken, TokenKind, closes_implicitly, decode_character_references, is_void_element, tokenize, update_open_elements,
+};
+
+#[derive(Debug, Clone)]
+pub struct TokenWithLine {
+    pub content: String,
+    pub line_number: usize,
+}
+
+// Attributes whose presence is their value: `disabled`, `disabled=""` and
+// `disabled="disabled"` all mean the same thing
+const BOOLEAN_ATTRIBUTES: &[&str] = &[
+    "allowfullscreen", "async", "autofocus", "autoplay", "checked", "controls", "default", "defer", "disabled",
+    "formnovalidate", "hidden", "inert", "ismap", "itemscope", "loop", "multiple", "muted", "nomodule", "novalidate",
+    "open", "playsinline", "readonly", "required", "reversed", "selected",
+];
+
+// Canonical text of a tag token, so that only semantic differences change its hash.
+//
+// Start tags become `<name a="1" b="2">` with lower-cased attribute names sorted
+// alphabetically, values decoded and re-quoted with `"`, value-less attributes
+// treated as empty, and boolean attributes reduced to their bare name. The
+// self-closing slash is dropped on void elements where it has no meaning.
+// Comments and doctypes are whitespace-collapsed.
+pub fn canonical_tag(token: &HtmlToken, html: &str) -> String {
+    let mut out = String::with_capacity(token.span.len());
+    write_canonical_tag(token, html, &mut out);
+    out
+}
+
+// `canonical_tag`, appended to `out`
+pub fn write_canonical_tag(token: &HtmlToken, html: &str, out: &mut String) {
+    match token.kind {
+        TokenKind::StartTag => {
+            let mut attributes: Vec<_> = token.attributes.iter().collect();
+            attributes.sort_by(|a, b| a.name.cmp(&b.name));
+
+            out.push('<');
+            out.push_str(&token.name);
+            for attribute in attributes {
+                let value = attribute.value.as_deref().unwrap_or("");
+                out.push(' ');
+                out.push_str(&attribute.name);
+                let is_boolean = BOOLEAN_ATTRIBUTES.contains(&attribute.name.as_ref())
+                    && (value.is_empty() || value.eq_ignore_ascii_case(&attribute.name));
+                if !is_boolean {
+                    out.push_str("=\"");
+                    for c in value.chars() {
+                        match c {
+                            '&' => out.push_str("&amp;"),
+                            '"' => out.push_str("&quot;"),
+                            _ => out.push(c),
+                        }
+                    }
+                    out.push('"');
+                }
+            }
+            if token.self_closing && !is_void_element(&token.name) {
+                out.push_str(" /");
+            }
+            out.push('>');
+        }
+        TokenKind::EndTag => {
+            out.push_str("</");
+            out.push_str(&token.name);
+            out.push('>');
+        }
+        TokenKind::Doctype => {
+            // `<!DOCTYPE html>` and `<!doctype  HTML>` are the same doctype;
+            // quoted public/system identifiers keep their case
+            let body = token.inner(html);
+            let body = body.get(7..).unwrap_or("");
+            out.push_str("<!DOCTYPE");
+            for word in body.split_whitespace() {
+                out.push(' ');
+                if word.starts_with('"') || word.starts_with('\'') {
+                    out.push_str(word);
+                } else {
+                    out.extend(word.chars().map(|c| c.to_ascii_lowercase()));
+                }
+            }
+            out.push('>');
+        }
+        _ => {
+            let mut first = true;
+            for word in token.source(html).split_whitespace() {
+                if !first {
+                    out.push(' ');
+                }
+                out.push_str(word);
+                first = false;
+            }
+        }
+    }
+}
+
+pub fn normalize_html_with_lines(html: &str, chunking: impl Into<Chunking>) -> (Vec<String>, Vec<TokenWithLine>) {
+    normalize_html_with_rules(html, chunking, &IgnoreRules::default())
+}
+
+// Normalize with ignore rules applied: masked subtrees collapse into a single
+// `TAG:<name>*</name>` token, and masked attribute values and value-pattern
+// matches are replaced by `*` before hashing.
+pub fn normalize_html_with_rules(
+    html: &str,
+    chunking: impl Into<Chunking>,
+    rules: &IgnoreRules,
+) -> (Vec<String>, Vec<TokenWithLine>) {
+    // Pre-allocate with estimated capacity to reduce reallocations
+    let estimated_tokens = html.len() / 20; // Rough estimate
+    let mut tokens_with_lines = Vec::with_capacity(estimated_tokens);
+
+    walk_normalized_tokens(html, rules, |normalized| {
+        tokens_with_lines.push(TokenWithLine {
+            content: normalized.content.to_string(),
+            line_number: normalized.line_number,
+        });
+    });
+
+    // Group tokens into chunks
+    let chunking = chunking.into();
+    let chunks = if chunking.fixed_size() == Some(1) {
+        tokens_with_lines.iter().map(|t| t.content.clone()).collect()
+    } else {
+        chunk_ranges(&tokens_with_lines, chunking)
+            .into_iter()
+            .map(|range| {
+                let contents: Vec<&str> = tokens_with_lines[range].iter().map(|t| t.content.as_str()).collect();
+                contents.join("|")
+            })
+            .collect()
+    };
+
+    (chunks, tokens_with_lines)
+}
+
+// A normalized token as a span of the source: the kind and byte range of the
+// tokenizer token it came from, and the line its content starts on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenSpan {
+    pub kind: TokenKind,
+    pub range: Range<usize>,
+    pub line: usize,
+}
+
+// Token spans of a document and the digest of each chunk
+#[derive(Debug, Clone, Default)]
+pub struct HashedSpans {
+    pub spans: Vec<TokenSpan>,
+    pub chunk_hashes: Vec<HashDigest>,
+}
+
+// Normalize and hash `html` without keeping any normalized text. Chunks are
+// assembled in one reused buffer, joined with `|` exactly as in
+// `normalize_html_with_rules`, and only their digests are kept: encoding a digest
+// with `algorithm.hasher().encode` gives the hash the string pipeline reports.
+pub fn hash_spans(html: &str, chunking: impl Into<Chunking>, rules: &IgnoreRules, algorithm: HashAlgorithm) -> HashedSpans {
+    let chunking = chunking.into();
+    let hasher = algorithm.hasher();
+    let estimated_tokens = html.len() / 20;
+    let mut hashed = HashedSpans {
+        spans: Vec::with_capacity(estimated_tokens),
+        chunk_hashes: Vec::with_capacity(estimated_tokens / chunking.fixed_size().unwrap_or(1)),
+    };
+    let mut joiner = ChunkJoiner::new(chunking);
+
+    walk_normalized_tokens(html, rules, |normalized| {
+        hashed.spans.push(TokenSpan {
+            kind: normalized.token.kind,
+            range: normalized.token.span.clone(),
+            line: normalized.line_number,
+        });
+        joiner.push(normalized.content, |chunk| hashed.chunk_hashes.push(hasher.digest(chunk.as_bytes())));
+    });
+    joiner.finish(|chunk| hashed.chunk_hashes.push(hasher.digest(chunk.as_bytes())));
+    hashed
+}
+
+// One normalized token together with the tokenizer token it came from
+pub struct NormalizedToken<'t> {
+    pub token: &'t HtmlToken<'t>,  // with attribute masks applied
+    pub content: &'t str,          // `TAG:...` / `TEXT:...`, valid until the next token
+    pub line_number: usize,
+    pub masked_subtree: bool,      // token stands in for a whole masked element
+}
+
+// Tokenize, canonicalize and mask `html`, calling `visit` for every token that
+// survives normalization (whitespace-only text is dropped). End tags are
+// visited too, so callers can rebuild the element structure.
+pub fn walk_normalized_tokens<F>(html: &str, rules: &IgnoreRules, mut visit: F)
+where
+    F: FnMut(NormalizedToken<'_>),
+{
+    Normalizer::new(rules).visit_tokens(html, &tokenize(html), 1, &mut visit);
+}
+
+// Normalization state carried from one token to the next, so a document can
+// also be fed in consecutive pieces (see `stream`)
+pub(crate) struct Normalizer<'r> {
+    rules: &'r IgnoreRules,
+    open_elements: Vec<HtmlToken<'static>>, // only tracked for selector rules
+    masked_depth: Option<usize>,
+    // Every token's content is built here in turn, so the walk itself allocates no strings
+    content: String,
+}
+
+impl<'r> Normalizer<'r> {
+    pub(crate) fn new(rules: &'r IgnoreRules) -> Self {
+        Normalizer {
+            rules,
+            open_elements: Vec::new(),
+            masked_depth: None,
+            content: String::new(),
+        }
+    }
+
+    // Normalize `tokens` of `html`, whose first byte is on line `first_line`
+    pub(crate) fn visit_tokens<F>(&mut self, html: &str, tokens: &[HtmlToken<'_>], first_line: usize, visit: &mut F)
+    where
+        F: FnMut(NormalizedToken<'_>),
+    {
+        // Pre-compute line positions for better performance
+        let line_positions: Vec<usize> = html.char_indices()
+            .filter_map(|(i, c)| if c == '\n' { Some(i) } else { None })
+            .collect();
+
+        // Count newlines up to each position using binary search
+        let count_lines_up_to = |pos: usize| -> usize {
+            first_line - 1 + match line_positions.binary_search(&pos) {
+                Ok(idx) => idx + 2, // Found exact position, line number is index + 2
+                Err(idx) => idx + 1, // Insert position gives us the line number
+            }
+        };
+
+        let rules = self.rules;
+        for token in tokens {
+            // Skip everything inside a masked subtree until its element closes
+            if let Some(depth) = self.masked_depth {
+                if token.kind == TokenKind::StartTag {
+                    while self.open_elements.len() > depth
+                        && self.open_elements.last().is_some_and(|top| closes_implicitly(&top.name, &token.name))
+                    {
+                        self.open_elements.pop();
+                    }
+                }
+                if self.open_elements.len() <= depth {
+                    // A start tag that implicitly closed the masked element lies outside it
+                    self.masked_depth = None;
+                } else {
+                    update_open_elements(&mut self.open_elements, token);
+                    if self.open_elements.len() <= depth {
+                        self.masked_depth = None;
+                    }
+                    continue;
+                }
+            }
+
+            if token.kind == TokenKind::StartTag && rules.has_selectors() && self.masks_element(token) {
+                self.content.clear();
+                for part in ["TAG:<", &token.name, ">", MASK, "</", &token.name, ">"] {
+                    self.content.push_str(part);
+                }
+                visit(NormalizedToken {
+                    token,
+                    content: &self.content,
+                    line_number: count_lines_up_to(token.span.start),
+                    masked_subtree: true,
+                });
+                let depth = self.open_elements.len();
+                update_open_elements(&mut self.open_elements, token);
+                if self.open_elements.len() > depth {
+                    self.masked_depth = Some(depth);
+                }
+                continue;
+            }
+            if rules.has_selectors() {
+                update_open_elements(&mut self.open_elements, token);
+            }
+
+            match token.kind {
+                TokenKind::Text | TokenKind::RawText | TokenKind::CData => {
+                    let source = match token.kind {
+                        TokenKind::CData => token.inner(html),
+                        _ => token.source(html),
+                    };
+                    let text = source.trim();
+                    if text.is_empty() {
+                        continue;
+                    }
+                    // Report the line where the text itself starts, not where the token's whitespace does
+                    let offset = token.span.start + (source.len() - source.trim_start().len());
+                    let line_num = count_lines_up_to(offset);
+                    let text = if token.kind == TokenKind::Text {
+                        decode_character_references(text, false)
+                    } else {
+                        Cow::Borrowed(text)
+                    };
+                    let text = rules.mask_value(&text);
+                    self.content.clear();
+                    self.content.push_str("TEXT:");
+                    self.content.push_str(&text);
+                    visit(NormalizedToken {
+                        token,
+                        content: &self.content,
+                        line_number: line_num,
+                        masked_subtree: false,
+                    });
+                }
+                _ => {
+                    let line_num = count_lines_up_to(token.span.start);
+                    let masked;
+                    let token = if token.kind == TokenKind::StartTag && !rules.is_empty() {
+                        masked = mask_attributes(token, rules);
+                        &masked
+                    } else {
+                        token
+                    };
+                    self.content.clear();
+                    self.content.push_str("TAG:");
+                    write_canonical_tag(token, html, &mut self.content);
+                    visit(NormalizedToken {
+                        token,
+                        content: &self.content,
+                        line_number: line_num,
+                        masked_subtree: false,
+                    });
+                }
+            }
+        }
+    }
+
+    fn masks_element(&self, token: &HtmlToken<'_>) -> bool {
+        let ancestors: Vec<&HtmlToken> = self.open_elements.iter().collect();
+        self.rules.masks_element(token, &ancestors)
+    }
+}
+
+fn mask_attributes<'a>(token: &HtmlToken<'a>, rules: &IgnoreRules) -> HtmlToken<'a> {
+    let mut masked = token.clone();
+    for attribute in &mut masked.attributes {
+        if rules.masks_attribute(&attribute.name) {
+            attribute.value = Some(Cow::Borrowed(MASK));
+        } else if let Some(ref value) = attribute.value
+            && let Cow::Owned(replaced) = rules.mask_value(value)
+        {
+            attribute.value = Some(Cow::Owned(replaced));
+        }
+    }
+    masked
+}
+
+pub fn normalize_html(html: &str, chunking: impl Into<Chunking>) -> Vec<String> {
+    let (chunks, _) = normalize_html_with_lines(html, chunking);
+    chunks
+}
@@ -0,0 +1,308 @@
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::hash_chunk_fast;
+use crate::sequence::{DiffOp, diff_sequences};
+use crate::tokenizer::tokenize;
+
+// Compact DOM deltas: the changes between two HTML documents as byte splices
+// on the old one. Hunks come from an ordered diff of the raw tokens, narrowed
+// to the characters that actually differ, so applying a patch reproduces the
+// target byte for byte (including whitespace the comparison ignores).
+
+pub const PATCH_FORMAT: &str = "merkle-domdiff-patch";
+pub const PATCH_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Patch {
+    pub format: String,
+    pub version: u32,
+    pub base_hash: String,         // xxh3 of the document the patch applies to
+    pub base_length: usize,
+    pub target_hash: String,       // xxh3 of the document it produces
+    pub target_length: usize,
+    pub hunks: Vec<PatchHunk>,
+}
+
+// Replace `delete` bytes at `offset` in the base document with `insert`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchHunk {
+    pub offset: usize,
+    pub delete: usize,
+    pub insert: String,
+    pub line: usize,               // line of `offset` in the base, for reading
+}
+
+impl Patch {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("could not read patch {}: {}", path.display(), e))?;
+        let patch: Patch = serde_json::from_str(&content)
+            .map_err(|e| format!("invalid patch {}: {}", path.display(), e))?;
+        if patch.format != PATCH_FORMAT || patch.version != PATCH_VERSION {
+            return Err(format!(
+                "unsupported patch format {} v{} in {}",
+                patch.format, patch.version, path.display()
+            ));
+        }
+        Ok(patch)
+    }
+
+    // Bytes of inserted content, a rough measure of the patch size
+    pub fn inserted_bytes(&self) -> usize {
+        self.hunks.iter().map(|h| h.insert.len()).sum()
+    }
+}
+
+// Build the patch that turns `base` into `target`
+pub fn create_patch(base: &str, target: &str) -> Patch {
+    let tokens_a = tokenize(base);
+    let tokens_b = tokenize(target);
+    let sources_a: Vec<&str> = tokens_a.iter().map(|t| t.source(base)).collect();
+    let sources_b: Vec<&str> = tokens_b.iter().map(|t| t.source(target)).collect();
+
+    // Tokens cover every byte, so each run of deleted and inserted tokens
+    // between two equal ones is a contiguous range on both sides
+    let mut hunks = Vec::new();
+    let (mut start_a, mut end_a, mut start_b, mut end_b) = (0, 0, 0, 0);
+    let ops = diff_sequences(&sources_a, &sources_b);
+    for op in ops.into_iter().chain(std::iter::once(DiffOp::Equal(tokens_a.len(), tokens_b.len()))) {
+        match op {
+            DiffOp::Delete(i) => end_a = tokens_a[i].span.end,
+            DiffOp::Insert(j) => end_b = tokens_b[j].span.end,
+            DiffOp::Equal(i, j) => {
+                if end_a > start_a || end_b > start_b {
+                    hunks.extend(narrow_hunk(base, target, start_a..end_a, start_b..end_b));
+                }
+                start_a = tokens_a.get(i).map_or(base.len(), |t| t.span.end);
+                start_b = tokens_b.get(j).map_or(target.len(), |t| t.span.end);
+                end_a = start_a;
+                end_b = start_b;
+            }
+        }
+    }
+
+    // Line numbers, in one pass over the base
+    let mut line = 1;
+    let mut scanned = 0;
+    for hunk in &mut hunks {
+        line += base.as_bytes()[scanned..hunk.offset].iter().filter(|&&b| b == b'\n').count();
+        scanned = hunk.offset;
+        hunk.line = line;
+    }
+
+    Patch {
+        format: PATCH_FORMAT.to_string(),
+        version: PATCH_VERSION,
+        base_hash: hash_chunk_fast(base),
+        base_length: base.len(),
+        target_hash: hash_chunk_fast(target),
+        target_length: target.len(),
+        hunks,
+    }
+}
+
+// Above this many words, a replaced range is not refined any further
+const MAX_REFINED_WORDS: usize = 50_000;
+// Changes closer than this many bytes share one hunk
+const MIN_HUNK_GAP: usize = 16;
+
+// Narrow a replaced token range to the words that actually change, e.g. one
+// edited value inside a large JSON script becomes a hunk of its own
+fn narrow_hunk(base: &str, target: &str, range_a: std::ops::Range<usize>, range_b: std::ops::Range<usize>) -> Vec<PatchHunk> {
+    let (prefix, suffix) = common_affixes(&base[range_a.clone()], &target[range_b.clone()]);
+    let range_a = range_a.start + prefix..range_a.end - suffix;
+    let range_b = range_b.start + prefix..range_b.end - suffix;
+    let (old, new) = (&base[range_a.clone()], &target[range_b.clone()]);
+
+    let words_a = words(old);
+    let words_b = words(new);
+    if words_a.len() + words_b.len() > MAX_REFINED_WORDS {
+        return vec![PatchHunk {
+            offset: range_a.start,
+            delete: old.len(),
+            insert: new.to_string(),
+            line: 0,
+        }];
+    }
+
+    // Byte offset of every word, plus the end
+    let offsets = |words: &[&str]| -> Vec<usize> {
+        std::iter::once(0).chain(words.iter().scan(0, |end, w| {
+            *end += w.len();
+            Some(*end)
+        })).collect()
+    };
+    let (offsets_a, offsets_b) = (offsets(&words_a), offsets(&words_b));
+
+    // Changed byte ranges (old, new), merging those separated by a short common run
+    let mut changes: Vec<(usize, usize, usize, usize)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for op in diff_sequences(&words_a, &words_b) {
+        let (start_a, start_b) = (offsets_a[i], offsets_b[j]);
+        match op {
+            DiffOp::Equal(ei, ej) => {
+                i = ei + 1;
+                j = ej + 1;
+                continue;
+            }
+            DiffOp::Delete(di) => i = di + 1,
+            DiffOp::Insert(dj) => j = dj + 1,
+        }
+        let (end_a, end_b) = (offsets_a[i], offsets_b[j]);
+        match changes.last_mut() {
+            Some(last) if start_a - last.1 < MIN_HUNK_GAP => {
+                last.1 = end_a;
+                last.3 = end_b;
+            }
+            _ => changes.push((start_a, end_a, start_b, end_b)),
+        }
+    }
+
+    changes
+        .into_iter()
+        .map(|(start_a, end_a, start_b, end_b)| {
+            // `div7` -> `div` is a one-byte deletion, not a word replacement
+            let (prefix, suffix) = common_affixes(&old[start_a..end_a], &new[start_b..end_b]);
+            PatchHunk {
+                offset: range_a.start + start_a + prefix,
+                delete: end_a - start_a - prefix - suffix,
+                insert: new[start_b + prefix..end_b - suffix].to_string(),
+                line: 0,
+            }
+        })
+        .collect()
+}
+
+// Byte lengths of the longest common prefix and (non-overlapping) suffix
+fn common_affixes(old: &str, new: &str) -> (usize, usize) {
+    let prefix: usize = old
+        .chars()
+        .zip(new.chars())
+        .take_while(|(x, y)| x == y)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+    let suffix: usize = old[prefix..]
+        .chars()
+        .rev()
+        .zip(new[prefix..].chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+    (prefix, suffix)
+}
+
+// Split text into runs of alphanumeric characters and single other characters
+fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if !c.is_alphanumeric() {
+            if start < i {
+                words.push(&text[start..i]);
+            }
+            words.push(&text[i..i + c.len_utf8()]);
+            start = i + c.len_utf8();
+        }
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+// Replay a patch onto its base document, checking both ends against the
+// recorded hashes
+pub fn apply_patch(base: &str, patch: &Patch) -> Result<String, String> {
+    if base.len() != patch.base_length || hash_chunk_fast(base) != patch.base_hash {
+        return Err("patch does not apply: base document differs from the one it was created from".to_string());
+    }
+
+    let mut target = String::with_capacity(patch.target_length);
+    let mut copied = 0;
+    for hunk in &patch.hunks {
+        let end = hunk.offset.checked_add(hunk.delete).filter(|&end| end <= base.len());
+        let (Some(end), true) = (end, hunk.offset >= copied) else {
+            return Err(format!("patch hunk at offset {} is out of order or out of range", hunk.offset));
+        };
+        let (Some(kept), true) = (base.get(copied..hunk.offset), base.is_char_boundary(end)) else {
+            return Err(format!("patch hunk at offset {} splits a character", hunk.offset));
+        };
+        target.push_str(kept);
+        target.push_str(&hunk.insert);
+        copied = end;
+    }
+    target.push_str(&base[copied..]);
+
+    if target.len() != patch.target_length || hash_chunk_fast(&target) != patch.target_hash {
+        return Err("patch produced a document that does not match its target hash".to_string());
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshots() -> Vec<(String, String)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots");
+        let mut pages: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "html"))
+            .map(|path| (path.display().to_string(), fs::read_to_string(&path).unwrap()))
+            .collect();
+        pages.sort();
+        pages
+    }
+
+    #[test]
+    fn round_trips_every_snapshot_pair() {
+        let pages = snapshots();
+        assert_eq!(pages.len(), 6);
+        for (name_a, base) in &pages {
+            for (name_b, target) in &pages {
+                let patch = create_patch(base, target);
+                let applied = apply_patch(base, &patch).unwrap_or_else(|e| panic!("{name_a} -> {name_b}: {e}"));
+                assert!(applied == *target, "{name_a} -> {name_b} did not reproduce the target");
+                if name_a == name_b {
+                    assert!(patch.hunks.is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let pages = snapshots();
+        let patch = create_patch(&pages[0].1, &pages[1].1);
+        let json = serde_json::to_string(&patch).unwrap();
+        let patch: Patch = serde_json::from_str(&json).unwrap();
+        assert_eq!(apply_patch(&pages[0].1, &patch).unwrap(), pages[1].1);
+    }
+
+    #[test]
+    fn rejects_a_base_with_a_different_hash() {
+        let base = "<div><p>one</p></div>";
+        let patch = create_patch(base, "<div><p>two</p></div>");
+
+        // Same length, different content
+        let err = apply_patch("<div><p>eno</p></div>", &patch).unwrap_err();
+        assert!(err.contains("base document differs"), "{err}");
+
+        let mut tampered = patch.clone();
+        tampered.base_hash = hash_chunk_fast("something else");
+        assert!(apply_patch(base, &tampered).is_err());
+    }
+
+    #[test]
+    fn rejects_hunks_that_do_not_produce_the_target() {
+        let base = "<p>one</p>";
+        let mut patch = create_patch(base, "<p>two</p>");
+        patch.hunks[0].insert = "owt".to_string();
+        let err = apply_patch(base, &patch).unwrap_err();
+        assert!(err.contains("target hash"), "{err}");
+    }
+}
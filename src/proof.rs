@@ -0,0 +1,166 @@
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::chunking::Chunking;
+use crate::hashing::HashAlgorithm;
+use crate::merkle::{TreeHasher, merkle_path, merkle_root_from_path};
+use crate::store::Snapshot;
+
+// Inclusion proofs: evidence that one normalized chunk is part of a page whose
+// Merkle root is known, e.g. one published with `--snapshot`. The proof holds
+// the chunk and the sibling hashes from its leaf up to the root, so it can be
+// checked without the rest of the page.
+
+pub const PROOF_FORMAT: &str = "merkle-domdiff-proof";
+pub const PROOF_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub format: String,
+    pub version: u32,
+    pub root: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default = "crate::merkle::legacy_tree_version")]
+    pub tree_version: u32,         // node hashing scheme, see merkle::TREE_VERSION
+    pub chunk_size: Chunking,
+    pub chunk_index: usize,
+    pub chunk_count: usize,        // leaves of the tree, which fixes its shape
+    pub line: usize,               // first line of the chunk, for reading
+    pub chunk: String,             // normalized chunk content
+    pub leaf_hash: String,
+    pub path: Vec<String>,         // sibling hashes, leaf level first
+}
+
+impl InclusionProof {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("could not read proof {}: {}", path.display(), e))?;
+        let proof: InclusionProof = serde_json::from_str(&content)
+            .map_err(|e| format!("invalid proof {}: {}", path.display(), e))?;
+        if proof.format != PROOF_FORMAT || proof.version != PROOF_VERSION {
+            return Err(format!(
+                "unsupported proof format {} v{} in {}",
+                proof.format, proof.version, path.display()
+            ));
+        }
+        Ok(proof)
+    }
+}
+
+// Proof that chunk `index` of `snapshot` is included under its root
+pub fn prove_chunk(snapshot: &Snapshot, index: usize) -> Result<InclusionProof, String> {
+    let path = merkle_path(&snapshot.levels, index).ok_or_else(|| {
+        format!("chunk index {} is out of range ({} chunks)", index, snapshot.chunks.len())
+    })?;
+    Ok(InclusionProof {
+        format: PROOF_FORMAT.to_string(),
+        version: PROOF_VERSION,
+        root: snapshot.root.clone(),
+        hash_algorithm: snapshot.hash_algorithm,
+        tree_version: snapshot.tree_version,
+        chunk_size: snapshot.chunk_size,
+        chunk_index: index,
+        chunk_count: snapshot.chunks.len(),
+        line: snapshot.lines[index],
+        chunk: snapshot.chunks[index].clone(),
+        leaf_hash: snapshot.leaf_hashes()[index].clone(),
+        path,
+    })
+}
+
+// Check that the proof's chunk hashes up to `root`. Pass the published root
+// rather than `proof.root`, which only tells the proof is self-consistent.
+pub fn verify_proof(proof: &InclusionProof, root: &str) -> Result<(), String> {
+    let hasher = TreeHasher::new(proof.hash_algorithm, proof.tree_version)?;
+    if hasher.leaf(&proof.chunk) != proof.leaf_hash {
+        return Err("proof chunk does not match its leaf hash".to_string());
+    }
+    let computed = merkle_root_from_path(
+        hasher,
+        &proof.leaf_hash,
+        proof.chunk_index,
+        proof.chunk_count,
+        &proof.path,
+    )
+    .ok_or_else(|| format!("proof path does not fit chunk {} of a {}-chunk tree", proof.chunk_index, proof.chunk_count))?;
+    if computed != root {
+        return Err(format!("proof leads to root {}, not {}", computed, root));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ignore::IgnoreRules;
+
+    fn snapshot() -> Snapshot {
+        let html = "<ul>\n<li>one</li>\n<li>two</li>\n<li>three</li>\n</ul>";
+        let (snapshot, _) = Snapshot::build(html, 1, &IgnoreRules::default(), None);
+        assert!(snapshot.chunks.len() > 8);
+        snapshot
+    }
+
+    // Change the last hex digit, keeping the hash well-formed
+    fn flip(hash: &str) -> String {
+        let (head, last) = hash.split_at(hash.len() - 1);
+        format!("{}{}", head, if last == "0" { "1" } else { "0" })
+    }
+
+    #[test]
+    fn every_chunk_proves_against_the_root() {
+        let snapshot = snapshot();
+        for index in 0..snapshot.chunks.len() {
+            let proof = prove_chunk(&snapshot, index).unwrap();
+            assert_eq!(verify_proof(&proof, &snapshot.root), Ok(()), "chunk {index}");
+        }
+        assert!(prove_chunk(&snapshot, snapshot.chunks.len()).is_err());
+    }
+
+    #[test]
+    fn tampered_sibling_fails() {
+        let snapshot = snapshot();
+        let proof = prove_chunk(&snapshot, 3).unwrap();
+        for level in 0..proof.path.len() {
+            let mut tampered = proof.clone();
+            tampered.path[level] = flip(&tampered.path[level]);
+            assert!(verify_proof(&tampered, &snapshot.root).is_err(), "sibling at level {level}");
+        }
+        let mut swapped = proof.clone();
+        swapped.path.swap(0, 1);
+        assert!(verify_proof(&swapped, &snapshot.root).is_err());
+    }
+
+    #[test]
+    fn tampered_index_fails() {
+        let snapshot = snapshot();
+        let proof = prove_chunk(&snapshot, 4).unwrap();
+        for index in [0, 3, 5, snapshot.chunks.len() - 1, snapshot.chunks.len()] {
+            let tampered = InclusionProof { chunk_index: index, ..proof.clone() };
+            assert!(verify_proof(&tampered, &snapshot.root).is_err(), "index {index}");
+        }
+    }
+
+    #[test]
+    fn tampered_chunk_fails() {
+        let snapshot = snapshot();
+        let proof = prove_chunk(&snapshot, 2).unwrap();
+        let edited = InclusionProof { chunk: "TEXT:forged".to_string(), ..proof.clone() };
+        assert!(verify_proof(&edited, &snapshot.root).unwrap_err().contains("leaf hash"));
+
+        // A consistent leaf hash for the forged chunk still leads to another root
+        let hasher = TreeHasher::new(proof.hash_algorithm, proof.tree_version).unwrap();
+        let rehashed = InclusionProof { leaf_hash: hasher.leaf("TEXT:forged"), ..edited };
+        assert!(verify_proof(&rehashed, &snapshot.root).unwrap_err().contains("not"));
+    }
+
+    #[test]
+    fn proof_only_verifies_against_its_own_root() {
+        let snapshot = snapshot();
+        let proof = prove_chunk(&snapshot, 0).unwrap();
+        assert!(verify_proof(&proof, &flip(&snapshot.root)).is_err());
+    }
+}
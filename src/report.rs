@@ -0,0 +1,384 @@
+use crate::diff::{ComparisonResult, LineDiff, parse_line_range};
+use crate::dom::{ChildAlignment, DomNode, DomNodeKind, align_children, build_dom_tree};
+use crate::ignore::IgnoreRules;
+use crate::textdiff::source_lines;
+
+// Self-contained HTML report of one comparison, for readers who do not want
+// the JSON. Everything is inline (styles, no scripts, no external assets), so
+// the file can be mailed around or archived and opened offline:
+//
+//   - summary figures from the `ComparisonResult`
+//   - a DOM outline of both documents merged, with added, removed and
+//     modified subtrees badged; the outline is nested `<details>` elements,
+//     changed subtrees open and unchanged ones collapsed into a single line
+//   - the list of line changes, linking into
+//   - both sources with the lines of each change highlighted
+//
+// Source highlighting follows `result.line_diffs`, so the comparison must
+// have been run with line diffs.
+
+const STYLE: &str = "
+body { font: 14px/1.4 system-ui, sans-serif; margin: 0 24px 48px; color: #1f2328; }
+h1 { font-size: 20px; margin: 24px 0 4px; word-break: break-all; }
+h2 { font-size: 16px; margin: 32px 0 8px; }
+.stats { display: flex; flex-wrap: wrap; gap: 8px; margin-top: 16px; }
+.stat { border: 1px solid #d0d7de; border-radius: 6px; padding: 8px 12px; min-width: 110px; }
+.stat b { display: block; font-size: 18px; }
+.stat span { color: #656d76; font-size: 12px; }
+.bar { height: 6px; background: #d0d7de; border-radius: 3px; margin-top: 6px; }
+.bar div { height: 100%; background: #cf222e; border-radius: 3px; }
+code, .src, .outline { font: 12px/1.5 ui-monospace, SFMono-Regular, Menlo, monospace; }
+.outline ul { list-style: none; padding-left: 18px; margin: 0; }
+.outline > ul { padding-left: 0; }
+.outline summary { cursor: pointer; }
+.outline .same { color: #656d76; }
+.badge { display: inline-block; border-radius: 10px; padding: 0 7px; font-size: 11px; margin-left: 4px; }
+.badge.added { background: #dafbe1; color: #116329; }
+.badge.removed { background: #ffebe9; color: #a40e26; }
+.badge.modified { background: #fff8c5; color: #7d4e00; }
+.lines, .count { color: #8c959f; margin-left: 6px; }
+table.changes { border-collapse: collapse; }
+table.changes td { border-top: 1px solid #d0d7de; padding: 3px 10px 3px 0; vertical-align: top; }
+.panes { display: grid; grid-template-columns: 1fr 1fr; gap: 12px; }
+.pane { border: 1px solid #d0d7de; border-radius: 6px; overflow: auto; max-height: 80vh; }
+.pane h3 { font-size: 13px; margin: 0; padding: 6px 10px; background: #f6f8fa; border-bottom: 1px solid #d0d7de; position: sticky; top: 0; }
+table.src { border-collapse: collapse; width: 100%; }
+table.src td { padding: 0 8px; white-space: pre-wrap; word-break: break-all; vertical-align: top; }
+table.src td.ln { color: #8c959f; text-align: right; user-select: none; white-space: nowrap; width: 1%; }
+tr.added { background: #dafbe1; }
+tr.removed { background: #ffebe9; }
+tr.modified { background: #fff8c5; }
+tr:target { outline: 2px solid #0969da; }
+";
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Changes found in one subtree of the outline
+#[derive(Debug, Clone, Copy, Default)]
+struct ChangeCounts {
+    added: usize,
+    removed: usize,
+    modified: usize,
+}
+
+impl ChangeCounts {
+    fn add(&mut self, other: ChangeCounts) {
+        self.added += other.added;
+        self.removed += other.removed;
+        self.modified += other.modified;
+    }
+
+    fn total(&self) -> usize {
+        self.added + self.removed + self.modified
+    }
+
+    fn badges(&self) -> String {
+        let mut badges = String::new();
+        for (count, class, sign) in [(self.added, "added", '+'), (self.removed, "removed", '-'), (self.modified, "modified", '~')] {
+            if count > 0 {
+                badges.push_str(&format!("<span class=\"badge {}\">{}{}</span>", class, sign, count));
+            }
+        }
+        badges
+    }
+}
+
+// Short display form of a node: the tag without its `TAG:` prefix, or quoted text
+fn node_label(node: &DomNode) -> String {
+    let label = match node.kind {
+        DomNodeKind::Text => format!("\"{}\"", node.text()),
+        _ => node.label.strip_prefix("TAG:").unwrap_or(&node.label).to_string(),
+    };
+    let mut short: String = label.chars().take(120).collect();
+    if short.len() < label.len() {
+        short.push('…');
+    }
+    escape_html(&short)
+}
+
+fn line_note(a: Option<&DomNode>, b: Option<&DomNode>) -> String {
+    match (a, b) {
+        (Some(a), Some(b)) if a.line_number != b.line_number => format!("L{} → L{}", a.line_number, b.line_number),
+        (Some(node), _) | (None, Some(node)) => format!("L{}", node.line_number),
+        (None, None) => String::new(),
+    }
+}
+
+// A subtree that is only in one document, as a single outline entry
+fn outline_one_sided(node: &DomNode, class: &str, out: &mut String) {
+    let count = node.node_count();
+    out.push_str(&format!(
+        "<li><span class=\"badge {}\">{}</span> {}<span class=\"lines\">{}</span>",
+        class, class, node_label(node), line_note(Some(node), None)
+    ));
+    if count > 1 {
+        out.push_str(&format!("<span class=\"count\">{} nodes</span>", count));
+    }
+    out.push_str("</li>\n");
+}
+
+// Outline entries for the aligned children of two matched nodes
+fn outline_children(a: &DomNode, b: &DomNode, out: &mut String) -> ChangeCounts {
+    let mut counts = ChangeCounts::default();
+    for alignment in align_children(&a.children, &b.children) {
+        match alignment {
+            ChildAlignment::Identical(_, j) => {
+                let node = &b.children[j];
+                out.push_str(&format!("<li class=\"same\">{}<span class=\"lines\">{}</span>", node_label(node), line_note(None, Some(node))));
+                if !node.is_leaf() {
+                    out.push_str(&format!("<span class=\"count\">{} nodes, unchanged</span>", node.node_count()));
+                }
+                out.push_str("</li>\n");
+            }
+            ChildAlignment::Changed(i, j) => counts.add(outline_pair(&a.children[i], &b.children[j], out)),
+            ChildAlignment::Removed(i) => {
+                outline_one_sided(&a.children[i], "removed", out);
+                counts.removed += 1;
+            }
+            ChildAlignment::Added(j) => {
+                outline_one_sided(&b.children[j], "added", out);
+                counts.added += 1;
+            }
+        }
+    }
+    counts
+}
+
+// A node present in both documents with different subtrees
+fn outline_pair(a: &DomNode, b: &DomNode, out: &mut String) -> ChangeCounts {
+    let mut children = String::new();
+    let mut counts = outline_children(a, b, &mut children);
+    let label = if a.label == b.label {
+        node_label(b)
+    } else {
+        counts.modified += 1;
+        format!("{} → {}", node_label(a), node_label(b))
+    };
+    let lines = line_note(Some(a), Some(b));
+    if children.is_empty() {
+        out.push_str(&format!("<li>{}{}<span class=\"lines\">{}</span></li>\n", label, counts.badges(), lines));
+    } else {
+        out.push_str(&format!(
+            "<li><details open><summary>{}{}<span class=\"lines\">{}</span></summary><ul>\n{}</ul></details></li>\n",
+            label, counts.badges(), lines, children
+        ));
+    }
+    counts
+}
+
+// Highlight class of every source line (index 0 is line 1) on one side
+fn line_classes(line_diffs: &[LineDiff], lines: usize, new_side: bool) -> Vec<Option<&'static str>> {
+    let mut classes = vec![None; lines];
+    for diff in line_diffs {
+        let (range, class) = match (diff.change_type.as_str(), new_side) {
+            ("added", true) => (Some(diff.line_range.as_str()), "added"),
+            ("removed", false) => (Some(diff.line_range.as_str()), "removed"),
+            ("modified", false) => (diff.old_line_range.as_deref(), "modified"),
+            ("modified", true) => (diff.new_line_range.as_deref(), "modified"),
+            _ => continue,
+        };
+        if let Some((start, end)) = range.and_then(parse_line_range) {
+            for line in start.max(1)..=end.min(lines) {
+                classes[line - 1] = Some(class);
+            }
+        }
+    }
+    classes
+}
+
+fn source_pane(title: &str, html: &str, line_diffs: &[LineDiff], side: char) -> String {
+    let lines = source_lines(html);
+    let classes = line_classes(line_diffs, lines.len(), side == 'b');
+    let mut pane = format!("<div class=\"pane\"><h3>{}</h3><table class=\"src\">\n", escape_html(title));
+    for (n, (line, class)) in lines.iter().zip(classes).enumerate() {
+        let class = class.map(|c| format!(" class=\"{}\"", c)).unwrap_or_default();
+        pane.push_str(&format!(
+            "<tr id=\"{}-L{}\"{}><td class=\"ln\">{}</td><td>{}</td></tr>\n",
+            side, n + 1, class, n + 1, escape_html(line.trim_end_matches(['\n', '\r']))
+        ));
+    }
+    pane.push_str("</table></div>\n");
+    pane
+}
+
+// Link to the first line of `range` in one of the source panes
+fn line_link(range: &str, side: char) -> String {
+    match parse_line_range(range) {
+        Some((start, _)) => format!("<a href=\"#{}-L{}\">{}</a>", side, start, escape_html(range)),
+        None => escape_html(range),
+    }
+}
+
+fn stat(value: &str, caption: &str) -> String {
+    format!("<div class=\"stat\"><b>{}</b><span>{}</span></div>\n", value, caption)
+}
+
+// The report for `result`, the comparison of `html_a` with `html_b` under `rules`
+pub fn html_report(result: &ComparisonResult, html_a: &str, html_b: &str, rules: &IgnoreRules) -> String {
+    let title = format!("DOM diff: {} vs {}", result.version_a, result.version_b);
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape_html(&title), STYLE, escape_html(&title)
+    );
+
+    // Summary
+    let count = |kind: &str| result.line_diffs.iter().filter(|d| d.change_type == kind).count();
+    page.push_str("<div class=\"stats\">\n");
+    page.push_str(&format!(
+        "<div class=\"stat\"><b>{:.2}%</b><span>different</span><div class=\"bar\"><div style=\"width: {:.2}%\"></div></div></div>\n",
+        result.difference_percent, result.difference_percent.clamp(0.0, 100.0)
+    ));
+    page.push_str(&stat(&format!("{} / {}", result.total_chunks_a, result.total_chunks_b), "chunks A / B"));
+    page.push_str(&stat(&result.common_chunks.to_string(), "common chunks"));
+    page.push_str(&stat(&result.different_chunks.to_string(), "different chunks"));
+    page.push_str(&stat(&format!("+{} / -{} / ~{}", count("added"), count("removed"), count("modified")), "line changes"));
+    if !result.subtree_changes.is_empty() {
+        page.push_str(&stat(&result.subtree_changes.len().to_string(), "changed subtrees"));
+    }
+    if !result.sequence_edits.is_empty() {
+        page.push_str(&stat(&result.sequence_edits.len().to_string(), "sequence edits"));
+    }
+    if !result.edit_script.is_empty() {
+        page.push_str(&stat(&result.edit_script.len().to_string(), "edit operations"));
+    }
+    page.push_str(&stat(&escape_html(&result.method), "method"));
+    page.push_str(&stat(&escape_html(&result.hash_algorithm), "hash"));
+    page.push_str(&stat(&format!("{} μs", result.processing_time_us), "processing time"));
+    page.push_str("</div>\n");
+
+    // DOM outline
+    let tree_a = build_dom_tree(html_a, rules);
+    let tree_b = build_dom_tree(html_b, rules);
+    let mut outline = String::new();
+    let counts = if tree_a.hash == tree_b.hash { ChangeCounts::default() } else { outline_children(&tree_a, &tree_b, &mut outline) };
+    page.push_str(&format!("<h2>DOM outline{}</h2>\n", counts.badges()));
+    if counts.total() == 0 {
+        page.push_str("<p>Both documents have the same DOM.</p>\n");
+    } else {
+        page.push_str(&format!("<div class=\"outline\"><ul>\n{}</ul></div>\n", outline));
+    }
+
+    // Line changes
+    page.push_str(&format!("<h2>Line changes ({})</h2>\n", result.line_diffs.len()));
+    if result.line_diffs.is_empty() {
+        page.push_str("<p>No line-level differences found.</p>\n");
+    } else {
+        page.push_str("<table class=\"changes\">\n");
+        for diff in &result.line_diffs {
+            let lines = match diff.change_type.as_str() {
+                "added" => line_link(&diff.line_range, 'b'),
+                "removed" => line_link(&diff.line_range, 'a'),
+                _ => format!(
+                    "{} → {}",
+                    line_link(diff.old_line_range.as_deref().unwrap_or(""), 'a'),
+                    line_link(diff.new_line_range.as_deref().unwrap_or(&diff.line_range), 'b')
+                ),
+            };
+            page.push_str(&format!(
+                "<tr><td><span class=\"badge {}\">{}</span></td><td>{}</td><td><code>{}</code></td></tr>\n",
+                escape_html(&diff.change_type), escape_html(&diff.change_type), lines, escape_html(&diff.content_preview)
+            ));
+        }
+        page.push_str("</table>\n");
+    }
+
+    // Sources
+    page.push_str("<h2>Documents</h2>\n<div class=\"panes\">\n");
+    page.push_str(&source_pane(&result.version_a, html_a, &result.line_diffs, 'a'));
+    page.push_str(&source_pane(&result.version_b, html_b, &result.line_diffs, 'b'));
+    page.push_str("</div>\n</body>\n</html>\n");
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DomDiffer;
+
+    fn report(name_a: &str, a: &str, name_b: &str, b: &str) -> String {
+        let result = DomDiffer::new().line_diffs(true).compare_named(name_a, a, name_b, b);
+        html_report(&result, a, b, &IgnoreRules::default())
+    }
+
+    // Names of every start tag in `page`
+    fn tag_names(page: &str) -> Vec<String> {
+        page.match_indices('<')
+            .filter_map(|(i, _)| {
+                let name: String = page[i + 1..].chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '!').collect();
+                (!name.is_empty()).then(|| name.to_ascii_lowercase())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn escapes_untrusted_sources_previews_and_names() {
+        let a = "<p title=\"x\">safe</p>\n<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n";
+        let b = "<p title='\"><script>alert(2)</script>'>safe</p>\n<script>alert(\"3\")</script>\n<img src=x onerror=\"alert(4)\">\n";
+        let page = report("<script>alert(5)</script>\".html", a, "b\"><img src=x onerror=alert(6)>", b);
+
+        let allowed = [
+            "!doctype", "html", "head", "meta", "title", "style", "body", "h1", "h2", "h3", "div", "b", "span", "ul",
+            "li", "details", "summary", "table", "tr", "td", "a", "code", "p",
+        ];
+        for name in tag_names(&page) {
+            assert!(allowed.contains(&name.as_str()), "raw <{name}> in the report");
+        }
+        assert!(!page.contains("<p title"), "source tags must be escaped");
+        assert!(!page.contains("onerror=\""));
+        assert!(page.contains("&lt;script&gt;alert(5)&lt;/script&gt;&quot;.html"));
+        assert!(page.contains("b&quot;&gt;&lt;img src=x onerror=alert(6)&gt;"));
+        assert!(page.contains("&lt;script&gt;alert(&quot;3&quot;)&lt;/script&gt;"));
+        assert!(page.contains("&amp;lt;script&amp;gt;alert(1)"));
+        // Previews of changed tokens are escaped too
+        assert!(page.contains("<code>+ TAG:&lt;script&gt;"), "{page}");
+    }
+
+    #[test]
+    fn escape_html_covers_markup_characters() {
+        assert_eq!(escape_html(r#"<a href="x">'&'</a>"#), "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;");
+        assert_eq!(escape_html("plain é"), "plain é");
+    }
+
+    #[test]
+    fn outline_badges_count_the_changes() {
+        let a = "<ul>\n<li>a</li>\n<li>b</li>\n<li>c</li>\n</ul>\n<p class=\"x\">t</p>\n<footer>same</footer>\n";
+        let b = "<ul>\n<li>a</li>\n<li>c</li>\n<li>d</li>\n</ul>\n<p class=\"y\">t</p>\n<footer>same</footer>\n";
+        let page = report("a.html", a, "b.html", b);
+
+        let badges = |added: usize, removed: usize, modified: usize| {
+            format!(
+                "<span class=\"badge added\">+{}</span><span class=\"badge removed\">-{}</span><span class=\"badge modified\">~{}</span>",
+                added, removed, modified
+            )
+        };
+        assert!(page.contains(&format!("<h2>DOM outline{}</h2>", badges(1, 1, 1))), "{page}");
+        let list = "<span class=\"badge added\">+1</span><span class=\"badge removed\">-1</span>";
+        assert!(page.contains(&format!("<li><details open><summary>&lt;ul&gt;{}<span class=\"lines\">L1</span></summary>", list)));
+        assert!(page.contains("<li><span class=\"badge removed\">removed</span> &lt;li&gt;<span class=\"lines\">L3</span>"));
+        assert!(page.contains("<li><span class=\"badge added\">added</span> &lt;li&gt;<span class=\"lines\">L4</span>"));
+        assert!(page.contains("&lt;p class=&quot;x&quot;&gt; → &lt;p class=&quot;y&quot;&gt;<span class=\"badge modified\">~1</span>"));
+        assert!(page.contains("<li class=\"same\">&lt;footer&gt;<span class=\"lines\">L7</span><span class=\"count\">2 nodes, unchanged</span></li>"));
+
+        // Changed source lines are highlighted and linked from the change list
+        assert!(page.contains("<tr id=\"a-L3\" class=\"removed\">"));
+        assert!(page.contains("<tr id=\"b-L4\" class=\"added\">"));
+        assert!(page.contains("<a href=\"#b-L4\">L4</a>"));
+
+        let same = report("a.html", a, "b.html", a);
+        assert!(same.contains("<h2>DOM outline</h2>\n<p>Both documents have the same DOM.</p>"));
+        assert!(same.contains("<h2>Line changes (0)</h2>"));
+    }
+}
@@ -0,0 +1,407 @@
+use crate::tokenizer::HtmlToken;
+
+// Minimal CSS selector support for ignore rules and assertions.
+//
+// Supported: type and universal selectors, `#id`, `.class`, attribute
+// selectors (`[a]`, `[a=v]`, `[a~=v]`, `[a^=v]`, `[a$=v]`, `[a*=v]`, `[a|=v]`),
+// descendant and child (`>`) combinators, and comma-separated lists.
+
+// Anything a selector can be matched against
+pub trait SelectorElement {
+    fn name(&self) -> &str;
+    fn attribute_value(&self, name: &str) -> Option<&str>;
+}
+
+impl SelectorElement for HtmlToken<'_> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn attribute_value(&self, name: &str) -> Option<&str> {
+        self.attribute(name).map(|a| a.value.as_deref().unwrap_or(""))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttributeOp {
+    Exists,
+    Equals,
+    Includes,
+    Prefix,
+    Suffix,
+    Substring,
+    DashMatch,
+}
+
+#[derive(Debug, Clone)]
+struct AttributeSelector {
+    name: String,
+    op: AttributeOp,
+    value: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Compound {
+    tag: Option<String>,
+    ids: Vec<String>,
+    classes: Vec<String>,
+    attributes: Vec<AttributeSelector>,
+}
+
+// One complex selector; each part's combinator relates it to the part before it
+#[derive(Debug, Clone)]
+struct Complex {
+    parts: Vec<(Combinator, Compound)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Selector {
+    source: String,
+    alternatives: Vec<Complex>,
+}
+
+impl Selector {
+    pub fn parse(source: &str) -> Result<Selector, String> {
+        let invalid = |e: String| format!("invalid selector '{}': {}", source, e);
+        let mut alternatives = Vec::new();
+        for part in split_top_level(source).map_err(invalid)? {
+            alternatives.push(parse_complex(part.trim()).map_err(invalid)?);
+        }
+        Ok(Selector {
+            source: source.to_string(),
+            alternatives,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    // `ancestors` runs from the document root down to the element's parent
+    pub fn matches<E: SelectorElement>(&self, element: &E, ancestors: &[&E]) -> bool {
+        self.alternatives
+            .iter()
+            .any(|complex| matches_from(&complex.parts, complex.parts.len() - 1, element, ancestors))
+    }
+}
+
+fn matches_from<E: SelectorElement>(parts: &[(Combinator, Compound)], idx: usize, element: &E, ancestors: &[&E]) -> bool {
+    let (combinator, compound) = &parts[idx];
+    if !compound.matches(element) {
+        return false;
+    }
+    if idx == 0 {
+        return true;
+    }
+    match combinator {
+        Combinator::Child => match ancestors.split_last() {
+            Some((parent, rest)) => matches_from(parts, idx - 1, *parent, rest),
+            None => false,
+        },
+        Combinator::Descendant => {
+            (0..ancestors.len()).rev().any(|k| matches_from(parts, idx - 1, ancestors[k], &ancestors[..k]))
+        }
+    }
+}
+
+impl Compound {
+    fn matches<E: SelectorElement>(&self, element: &E) -> bool {
+        if let Some(ref tag) = self.tag
+            && !element.name().eq_ignore_ascii_case(tag)
+        {
+            return false;
+        }
+        if !self.ids.iter().all(|id| element.attribute_value("id") == Some(id.as_str())) {
+            return false;
+        }
+        if !self.classes.is_empty() {
+            let classes = element.attribute_value("class").unwrap_or("");
+            if !self.classes.iter().all(|c| classes.split_whitespace().any(|have| have == c)) {
+                return false;
+            }
+        }
+        self.attributes.iter().all(|attr| {
+            let Some(value) = element.attribute_value(&attr.name) else {
+                return false;
+            };
+            match attr.op {
+                AttributeOp::Exists => true,
+                AttributeOp::Equals => value == attr.value,
+                AttributeOp::Includes => value.split_whitespace().any(|w| w == attr.value),
+                AttributeOp::Prefix => !attr.value.is_empty() && value.starts_with(&attr.value),
+                AttributeOp::Suffix => !attr.value.is_empty() && value.ends_with(&attr.value),
+                AttributeOp::Substring => !attr.value.is_empty() && value.contains(&attr.value),
+                AttributeOp::DashMatch => value == attr.value || value.starts_with(&format!("{}-", attr.value)),
+            }
+        })
+    }
+}
+
+// Split a selector list at commas outside `[...]` and quoted attribute values
+fn split_top_level(source: &str) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_brackets = false;
+    let mut quote = None;
+    for (i, c) in source.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if in_brackets => quote = Some(c),
+            (None, '[') => in_brackets = true,
+            (None, ']') => in_brackets = false,
+            (None, ',') if !in_brackets => {
+                parts.push(&source[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated quote".to_string());
+    }
+    parts.push(&source[start..]);
+    Ok(parts)
+}
+
+// Index of the `]` closing the attribute selector opened at `open`, skipping quoted values
+fn attribute_end(chars: &[char], open: usize) -> Option<usize> {
+    let mut quote = None;
+    for (i, &c) in chars.iter().enumerate().skip(open + 1) {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, ']') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || !c.is_ascii()
+}
+
+fn parse_complex(source: &str) -> Result<Complex, String> {
+    if source.is_empty() {
+        return Err("empty selector".to_string());
+    }
+
+    let chars: Vec<char> = source.chars().collect();
+    let mut parts = Vec::new();
+    let mut pos = 0;
+    let mut combinator = Combinator::Descendant;
+
+    loop {
+        let (compound, next) = parse_compound(&chars, pos)?;
+        parts.push((combinator, compound));
+        pos = next;
+
+        let mut saw_space = false;
+        while pos < chars.len() && chars[pos].is_whitespace() {
+            pos += 1;
+            saw_space = true;
+        }
+        if pos >= chars.len() {
+            break;
+        }
+        if chars[pos] == '>' {
+            combinator = Combinator::Child;
+            pos += 1;
+            while pos < chars.len() && chars[pos].is_whitespace() {
+                pos += 1;
+            }
+        } else if saw_space {
+            combinator = Combinator::Descendant;
+        } else {
+            return Err(format!("unexpected '{}'", chars[pos]));
+        }
+    }
+
+    Ok(Complex { parts })
+}
+
+fn parse_ident(chars: &[char], mut pos: usize) -> (String, usize) {
+    let start = pos;
+    while pos < chars.len() && is_ident_char(chars[pos]) {
+        pos += 1;
+    }
+    (chars[start..pos].iter().collect(), pos)
+}
+
+fn parse_compound(chars: &[char], mut pos: usize) -> Result<(Compound, usize), String> {
+    let mut compound = Compound::default();
+    let start = pos;
+
+    if pos < chars.len() && chars[pos] == '*' {
+        pos += 1;
+    } else if pos < chars.len() && is_ident_char(chars[pos]) {
+        let (tag, next) = parse_ident(chars, pos);
+        compound.tag = Some(tag.to_ascii_lowercase());
+        pos = next;
+    }
+
+    while pos < chars.len() {
+        match chars[pos] {
+            '#' | '.' => {
+                let (ident, next) = parse_ident(chars, pos + 1);
+                if ident.is_empty() {
+                    return Err(format!("expected a name after '{}'", chars[pos]));
+                }
+                if chars[pos] == '#' {
+                    compound.ids.push(ident);
+                } else {
+                    compound.classes.push(ident);
+                }
+                pos = next;
+            }
+            '[' => {
+                let close = attribute_end(chars, pos).ok_or("unterminated '['")?;
+                compound.attributes.push(parse_attribute(&chars[pos + 1..close].iter().collect::<String>())?);
+                pos = close + 1;
+            }
+            _ => break,
+        }
+    }
+
+    if pos == start {
+        return Err("expected a selector".to_string());
+    }
+    Ok((compound, pos))
+}
+
+fn parse_attribute(body: &str) -> Result<AttributeSelector, String> {
+    let body = body.trim();
+    let Some(eq) = body.find('=') else {
+        if body.is_empty() {
+            return Err("attribute selector without a name".to_string());
+        }
+        return Ok(AttributeSelector {
+            name: body.to_ascii_lowercase(),
+            op: AttributeOp::Exists,
+            value: String::new(),
+        });
+    };
+
+    let (name, op) = match body[..eq].chars().last() {
+        Some('~') => (&body[..eq - 1], AttributeOp::Includes),
+        Some('^') => (&body[..eq - 1], AttributeOp::Prefix),
+        Some('$') => (&body[..eq - 1], AttributeOp::Suffix),
+        Some('*') => (&body[..eq - 1], AttributeOp::Substring),
+        Some('|') => (&body[..eq - 1], AttributeOp::DashMatch),
+        _ => (&body[..eq], AttributeOp::Equals),
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("attribute selector without a name".to_string());
+    }
+
+    let value = body[eq + 1..].trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value);
+
+    Ok(AttributeSelector {
+        name: name.to_ascii_lowercase(),
+        op,
+        value: value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{TokenKind, tokenize};
+
+    // Match `selector` against the element opened by the last start tag in `html`,
+    // with every earlier unclosed start tag as an ancestor
+    fn matches(selector: &str, html: &str) -> bool {
+        let tokens = tokenize(html);
+        let opens: Vec<&HtmlToken> = tokens.iter().filter(|t| t.kind == TokenKind::StartTag).collect();
+        let (element, ancestors) = opens.split_last().unwrap();
+        Selector::parse(selector).unwrap().matches(*element, ancestors)
+    }
+
+    #[test]
+    fn matches_simple_selectors() {
+        let html = r#"<DIV id="main" class="card  featured" data-kind="promo-banner" lang="en-US">"#;
+        for selector in [
+            "div",
+            "*",
+            "#main",
+            ".card",
+            ".card.featured",
+            "div#main.featured",
+            "[data-kind]",
+            "[DATA-KIND]",
+            "[data-kind=promo-banner]",
+            "[data-kind='promo-banner']",
+            "[class~=featured]",
+            "[data-kind^=promo]",
+            "[data-kind$=banner]",
+            "[data-kind*=o-b]",
+            "[lang|=en]",
+        ] {
+            assert!(matches(selector, html), "{selector} should match");
+        }
+        for selector in [
+            "span",
+            "#other",
+            ".card.missing",
+            "[title]",
+            "[data-kind=promo]",
+            "[class~=feat]",
+            "[data-kind^='']",
+            "[lang|=en-U]",
+        ] {
+            assert!(!matches(selector, html), "{selector} should not match");
+        }
+    }
+
+    #[test]
+    fn matches_descendant_and_child_combinators() {
+        let html = r#"<body><main class="page"><section><p class="note">"#;
+        assert!(matches("main p", html));
+        assert!(matches("body main section p", html));
+        assert!(matches("section > p.note", html));
+        assert!(matches("main > section > p", html));
+        assert!(!matches("main > p", html));
+        assert!(!matches("p section", html));
+        assert!(!matches("article p", html));
+    }
+
+    #[test]
+    fn matches_any_alternative_in_a_list() {
+        let html = r#"<p class="note">"#;
+        assert!(matches("span, p.note", html));
+        assert!(matches("p.other , .note", html));
+        assert!(!matches("span, div", html));
+    }
+
+    #[test]
+    fn splits_lists_only_at_top_level_commas() {
+        let selector = Selector::parse(r#"[data-x="a,b"], [title='x, y']"#).unwrap();
+        assert_eq!(selector.alternatives.len(), 2);
+        assert!(matches(r#"[data-x="a,b"]"#, r#"<div data-x="a,b">"#));
+        assert!(!matches(r#"[data-x="a,b"]"#, r#"<div data-x="a">"#));
+        assert!(matches(r#"span, [title="]"]"#, r#"<div title="]">"#));
+    }
+
+    #[test]
+    fn rejects_malformed_selectors() {
+        for source in ["", "div,", "[]", "[ ]", "[=x]", "[data-x", "[title=\"x]", "#", ".", "div >", "div ~ p", "p!"] {
+            assert!(Selector::parse(source).is_err(), "{source:?} should be rejected");
+        }
+        let err = Selector::parse("p, []").unwrap_err();
+        assert!(err.contains("'p, []'") && err.contains("without a name"), "{err}");
+    }
+}
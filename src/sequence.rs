@@ -0,0 +1,337 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+// Order-aware diffing of hash sequences using Myers' O(ND) algorithm in its
+// linear-space (middle snake) form, so long documents with many changes do
+// not need a quadratic table.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOp {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+// Longest common subsequence of `a` and `b` as matched index pairs, in order
+pub fn lcs_pairs<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    diff_range(a, b, 0, 0, &mut pairs);
+    pairs
+}
+
+// Edit script turning `a` into `b`, in document order. Deletions come before
+// insertions at the same position.
+pub fn diff_sequences<T: PartialEq>(a: &[T], b: &[T]) -> Vec<DiffOp> {
+    let mut ops = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    for (pi, pj) in lcs_pairs(a, b).into_iter().chain(std::iter::once((a.len(), b.len()))) {
+        ops.extend((i..pi).map(DiffOp::Delete));
+        ops.extend((j..pj).map(DiffOp::Insert));
+        if pi < a.len() {
+            ops.push(DiffOp::Equal(pi, pj));
+        }
+        i = pi + 1;
+        j = pj + 1;
+    }
+    ops
+}
+
+fn diff_range<T: PartialEq>(a: &[T], b: &[T], off_a: usize, off_b: usize, pairs: &mut Vec<(usize, usize)>) {
+    // Common prefix and suffix are matched directly
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    pairs.extend((0..prefix).map(|i| (off_a + i, off_b + i)));
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let (off_a, off_b) = (off_a + prefix, off_b + prefix);
+
+    let suffix = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    if !a_mid.is_empty()
+        && !b_mid.is_empty()
+        && let Some((x, y)) = middle_snake(a_mid, b_mid)
+    {
+        diff_range(&a_mid[..x], &b_mid[..y], off_a, off_b, pairs);
+        diff_range(&a_mid[x..], &b_mid[y..], off_a + x, off_b + y, pairs);
+    }
+
+    let (tail_a, tail_b) = (off_a + a_mid.len(), off_b + b_mid.len());
+    pairs.extend((0..suffix).map(|i| (tail_a + i, tail_b + i)));
+}
+
+// Find the point where the forward and backward searches meet; splitting
+// there yields two independent subproblems. None if nothing is in common.
+fn middle_snake<T: PartialEq>(a: &[T], b: &[T]) -> Option<(usize, usize)> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max_d = (n + m + 1) / 2;
+    let v_offset = max_d;
+    let v_length = 2 * max_d + 2;
+    let mut v1 = vec![-1isize; v_length as usize];
+    let mut v2 = vec![-1isize; v_length as usize];
+    v1[(v_offset + 1) as usize] = 0;
+    v2[(v_offset + 1) as usize] = 0;
+
+    let delta = n - m;
+    let front = delta % 2 != 0;
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let k1_offset = (v_offset + k1) as usize;
+            let mut x1 = if k1 == -d || (k1 != d && v1[k1_offset - 1] < v1[k1_offset + 1]) {
+                v1[k1_offset + 1]
+            } else {
+                v1[k1_offset - 1] + 1
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && a[x1 as usize] == b[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            v1[k1_offset] = x1;
+            if x1 > n {
+                k1_end += 2;
+            } else if y1 > m {
+                k1_start += 2;
+            } else if front {
+                let k2_offset = v_offset + delta - k1;
+                if k2_offset >= 0 && k2_offset < v_length && v2[k2_offset as usize] != -1 {
+                    let x2 = n - v2[k2_offset as usize];
+                    if x1 >= x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let k2_offset = (v_offset + k2) as usize;
+            let mut x2 = if k2 == -d || (k2 != d && v2[k2_offset - 1] < v2[k2_offset + 1]) {
+                v2[k2_offset + 1]
+            } else {
+                v2[k2_offset - 1] + 1
+            };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && a[(n - x2 - 1) as usize] == b[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            v2[k2_offset] = x2;
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !front {
+                let k1_offset = v_offset + delta - k2;
+                if k1_offset >= 0 && k1_offset < v_length && v1[k1_offset as usize] != -1 {
+                    let x1 = v1[k1_offset as usize];
+                    let y1 = v_offset + x1 - k1_offset;
+                    if x1 >= n - x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceEdit {
+    pub change_type: String,       // "inserted", "deleted", "moved"
+    pub index_a: Option<usize>,    // first chunk in version A
+    pub index_b: Option<usize>,    // first chunk in version B
+    pub length: usize,             // number of consecutive chunks
+    pub line_a: Option<usize>,
+    pub line_b: Option<usize>,
+    pub content_preview: String,
+}
+
+// Statistics of an ordered comparison
+#[derive(Debug, Clone)]
+pub struct OrderedDiff {
+    pub matched: usize,
+    pub inserted: usize,
+    pub deleted: usize,
+    pub moved: usize,
+    pub edits: Vec<SequenceEdit>,
+}
+
+impl OrderedDiff {
+    // Share of chunk occurrences not matched in order: repeated chunks count
+    // every time, and a moved chunk counts as removed from A and added to B
+    pub fn difference_percent(&self) -> f64 {
+        let total = 2 * self.matched + self.inserted + self.deleted;
+        if total == 0 {
+            0.0
+        } else {
+            ((self.inserted + self.deleted) as f64 / total as f64) * 100.0
+        }
+    }
+}
+
+// Order-aware comparison of two hash sequences. Deleted chunks that reappear
+// among the insertions are reported as moves. `describe` supplies the line
+// number and preview for a chunk of A (`false`) or B (`true`).
+pub fn ordered_diff<T, F>(hashes_a: &[T], hashes_b: &[T], describe: F) -> OrderedDiff
+where
+    T: Eq + Hash,
+    F: Fn(bool, usize) -> (Option<usize>, String),
+{
+    let ops = diff_sequences(hashes_a, hashes_b);
+
+    // Pair each deleted chunk with the first unclaimed insertion of the same hash
+    let mut inserted_at: HashMap<&T, VecDeque<usize>> = HashMap::new();
+    for op in &ops {
+        if let DiffOp::Insert(j) = *op {
+            inserted_at.entry(&hashes_b[j]).or_default().push_back(j);
+        }
+    }
+    let mut move_target: HashMap<usize, usize> = HashMap::new();
+    let mut move_source: HashMap<usize, usize> = HashMap::new();
+    for op in &ops {
+        if let DiffOp::Delete(i) = *op
+            && let Some(j) = inserted_at.get_mut(&hashes_a[i]).and_then(|q| q.pop_front())
+        {
+            move_target.insert(i, j);
+            move_source.insert(j, i);
+        }
+    }
+
+    let mut diff = OrderedDiff {
+        matched: 0,
+        inserted: 0,
+        deleted: 0,
+        moved: move_target.len(),
+        edits: Vec::new(),
+    };
+
+    for op in ops {
+        let (change_type, index_a, index_b) = match op {
+            DiffOp::Equal(_, _) => {
+                diff.matched += 1;
+                continue;
+            }
+            DiffOp::Delete(i) => {
+                diff.deleted += 1;
+                match move_target.get(&i) {
+                    Some(&j) => ("moved", Some(i), Some(j)),
+                    None => ("deleted", Some(i), None),
+                }
+            }
+            DiffOp::Insert(j) => {
+                diff.inserted += 1;
+                if move_source.contains_key(&j) {
+                    continue; // reported at its source position
+                }
+                ("inserted", None, Some(j))
+            }
+        };
+
+        // Extend the previous edit if this one continues it
+        if let Some(last) = diff.edits.last_mut()
+            && last.change_type == change_type
+            && index_a.is_none_or(|i| last.index_a.map(|s| s + last.length) == Some(i))
+            && index_b.is_none_or(|j| last.index_b.map(|s| s + last.length) == Some(j))
+        {
+            last.length += 1;
+            continue;
+        }
+
+        let (line_a, preview_a) = index_a.map(|i| describe(false, i)).unwrap_or((None, String::new()));
+        let (line_b, preview_b) = index_b.map(|j| describe(true, j)).unwrap_or((None, String::new()));
+        diff.edits.push(SequenceEdit {
+            change_type: change_type.to_string(),
+            index_a,
+            index_b,
+            length: 1,
+            line_a,
+            line_b,
+            content_preview: if preview_a.is_empty() { preview_b } else { preview_a },
+        });
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic xorshift, enough to vary the inputs
+    fn random_sequences(seed: u64, count: usize) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> {
+        let mut state = seed;
+        let mut next = move |bound: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+        (0..count).map(move |_| {
+            let alphabet = 2 + next(4);
+            let a = (0..next(14)).map(|_| next(alphabet) as u8).collect();
+            let b = (0..next(14)).map(|_| next(alphabet) as u8).collect();
+            (a, b)
+        })
+    }
+
+    fn dp_lcs_length(a: &[u8], b: &[u8]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                table[i][j] = if a[i - 1] == b[j - 1] {
+                    table[i - 1][j - 1] + 1
+                } else {
+                    table[i - 1][j].max(table[i][j - 1])
+                };
+            }
+        }
+        table[a.len()][b.len()]
+    }
+
+    #[test]
+    fn lcs_pairs_match_a_dynamic_programming_lcs() {
+        for (a, b) in random_sequences(0x9e37_79b9_7f4a_7c15, 2000) {
+            let pairs = lcs_pairs(&a, &b);
+            assert_eq!(pairs.len(), dp_lcs_length(&a, &b), "{a:?} {b:?}");
+            assert!(pairs.iter().all(|&(i, j)| a[i] == b[j]), "{a:?} {b:?}");
+            assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1), "{a:?} {b:?}");
+        }
+    }
+
+    #[test]
+    fn diff_sequences_rebuilds_both_sides() {
+        for (a, b) in random_sequences(42, 500) {
+            let ops = diff_sequences(&a, &b);
+            let mut old = Vec::new();
+            let mut new = Vec::new();
+            for op in ops {
+                match op {
+                    DiffOp::Equal(i, j) => {
+                        old.push(a[i]);
+                        new.push(b[j]);
+                    }
+                    DiffOp::Delete(i) => old.push(a[i]),
+                    DiffOp::Insert(j) => new.push(b[j]),
+                }
+            }
+            assert_eq!((old, new), (a, b));
+        }
+    }
+
+    #[test]
+    fn moved_chunks_are_reported_once() {
+        let describe = |_, _| (None, String::new());
+        let diff = ordered_diff(&["a", "b", "c", "d"], &["b", "c", "d", "a"], describe);
+        assert_eq!((diff.matched, diff.moved), (3, 1));
+        assert_eq!(diff.edits.len(), 1);
+        assert_eq!(diff.edits[0].change_type, "moved");
+        assert_eq!((diff.edits[0].index_a, diff.edits[0].index_b), (Some(0), Some(3)));
+    }
+}
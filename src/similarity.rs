@@ -0,0 +1,664 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::chunking::Chunking;
+use crate::hashing::{HashAlgorithm, get_perf_config};
+use crate::ignore::IgnoreRules;
+use crate::stream::hash_chunks_reader;
+
+// Near-duplicate detection across many pages. Comparing every pair with
+// `detailed_diff` is quadratic, so each page is reduced to a fixed-size
+// sketch of its chunk hashes and only pages whose sketches collide in an LSH
+// bucket are compared:
+//
+//   - MinHash estimates the Jaccard similarity of the distinct chunk sets,
+//     which is exactly `1 - difference_percent / 100` of a set-based
+//     `merkle_lite` comparison. Signatures are split into bands; pages that
+//     agree on every row of some band become candidates.
+//   - SimHash folds all chunks (repeats weigh more) into 64 bits whose
+//     Hamming distance tracks similarity. Pages agreeing on one of
+//     `max_distance + 1` bit blocks become candidates, which finds every pair
+//     within `max_distance` bits.
+//
+// Candidates are then checked against the threshold with their sketches and
+// linked into groups (single linkage).
+
+pub const DEFAULT_SIGNATURE_SIZE: usize = 128;
+pub const DEFAULT_THRESHOLD: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SketchKind {
+    #[default]
+    MinHash,
+    SimHash,
+}
+
+impl SketchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SketchKind::MinHash => "minhash",
+            SketchKind::SimHash => "simhash",
+        }
+    }
+}
+
+impl FromStr for SketchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minhash" => Ok(SketchKind::MinHash),
+            "simhash" => Ok(SketchKind::SimHash),
+            _ => Err(format!("unknown sketch '{}' (expected minhash or simhash)", s)),
+        }
+    }
+}
+
+// Finalizer of splitmix64: a cheap, well-mixed 64-bit permutation
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// One minimum per hash function, over the distinct chunk hashes of a page
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MinHashSignature {
+    pub values: Vec<u64>,
+}
+
+impl MinHashSignature {
+    pub fn new(size: usize) -> Self {
+        MinHashSignature { values: vec![u64::MAX; size.max(1)] }
+    }
+
+    pub fn from_hashes<I: IntoIterator<Item = u64>>(hashes: I, size: usize) -> Self {
+        let mut signature = MinHashSignature::new(size);
+        for hash in hashes {
+            signature.insert(hash);
+        }
+        signature
+    }
+
+    // Repeats of a chunk hash leave the signature unchanged
+    pub fn insert(&mut self, chunk_hash: u64) {
+        for (i, value) in self.values.iter_mut().enumerate() {
+            let hashed = mix(chunk_hash.wrapping_add((i as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15)));
+            *value = (*value).min(hashed);
+        }
+    }
+
+    // Whether no chunk was inserted; every value is still the initial maximum
+    pub fn is_empty(&self) -> bool {
+        self.values.iter().all(|&v| v == u64::MAX)
+    }
+
+    // Estimated Jaccard similarity: the share of hash functions with the same
+    // minimum. Empty pages share no chunks with anything, including each other.
+    pub fn similarity(&self, other: &MinHashSignature) -> f64 {
+        let size = self.values.len().min(other.values.len());
+        if size == 0 || self.is_empty() || other.is_empty() {
+            return 0.0;
+        }
+        let equal = self.values.iter().zip(&other.values).filter(|(a, b)| a == b).count();
+        equal as f64 / size as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SimHash(pub u64);
+
+impl SimHash {
+    pub fn distance(&self, other: &SimHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+
+    // Share of the 64 bits the two hashes agree on
+    pub fn similarity(&self, other: &SimHash) -> f64 {
+        1.0 - self.distance(other) as f64 / 64.0
+    }
+}
+
+// Running bit votes of a SimHash
+#[derive(Debug, Clone)]
+pub struct SimHasher {
+    votes: [i64; 64],
+}
+
+impl Default for SimHasher {
+    fn default() -> Self {
+        SimHasher { votes: [0; 64] }
+    }
+}
+
+impl SimHasher {
+    pub fn insert(&mut self, chunk_hash: u64) {
+        // Chunk hashes of weak algorithms are not uniform enough on their own
+        let hash = mix(chunk_hash);
+        for (bit, vote) in self.votes.iter_mut().enumerate() {
+            *vote += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+
+    pub fn finish(&self) -> SimHash {
+        let bits = self.votes.iter().enumerate().filter(|(_, v)| **v > 0).fold(0u64, |acc, (bit, _)| acc | 1 << bit);
+        SimHash(bits)
+    }
+}
+
+// Both sketches of one page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageSketch {
+    pub minhash: MinHashSignature,
+    pub simhash: SimHash,
+    pub chunks: usize,
+}
+
+impl PageSketch {
+    pub fn from_hashes<I: IntoIterator<Item = u64>>(hashes: I, signature_size: usize) -> Self {
+        let mut minhash = MinHashSignature::new(signature_size);
+        let mut simhash = SimHasher::default();
+        let mut chunks = 0;
+        for hash in hashes {
+            minhash.insert(hash);
+            simhash.insert(hash);
+            chunks += 1;
+        }
+        PageSketch { minhash, simhash: simhash.finish(), chunks }
+    }
+
+    pub fn similarity(&self, other: &PageSketch, kind: SketchKind) -> f64 {
+        match kind {
+            SketchKind::MinHash => self.minhash.similarity(&other.minhash),
+            SketchKind::SimHash => self.simhash.similarity(&other.simhash),
+        }
+    }
+}
+
+// How MinHash signatures are cut into bands for LSH
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LshParams {
+    pub bands: usize,
+    pub rows: usize,
+}
+
+impl LshParams {
+    // Banding of a `signature_size` signature whose candidate probability
+    // rises most steeply around `threshold`, i.e. `(1 / bands) ^ (1 / rows)`
+    // is as close to it as possible
+    pub fn for_threshold(signature_size: usize, threshold: f64) -> Self {
+        let signature_size = signature_size.max(1);
+        (1..=signature_size)
+            .map(|rows| LshParams { bands: signature_size / rows, rows })
+            .min_by(|a, b| {
+                let distance = |p: &LshParams| ((1.0 / p.bands as f64).powf(1.0 / p.rows as f64) - threshold).abs();
+                distance(a).total_cmp(&distance(b))
+            })
+            .expect("at least one banding")
+    }
+
+    // Probability that two pages with this similarity share a bucket
+    pub fn candidate_probability(&self, similarity: f64) -> f64 {
+        1.0 - (1.0 - similarity.powi(self.rows as i32)).powi(self.bands as i32)
+    }
+}
+
+// Pairs (i < j) of signatures that agree on every row of at least one band.
+// Empty signatures would all agree, so they are never candidates.
+pub fn minhash_candidates(signatures: &[&MinHashSignature], params: LshParams) -> Vec<(usize, usize)> {
+    let mut pairs = HashSet::new();
+    for band in 0..params.bands {
+        let rows = band * params.rows..(band + 1) * params.rows;
+        let mut buckets: HashMap<&[u64], Vec<usize>> = HashMap::new();
+        for (i, signature) in signatures.iter().enumerate().filter(|(_, s)| !s.is_empty()) {
+            if let Some(key) = signature.values.get(rows.clone()) {
+                buckets.entry(key).or_default().push(i);
+            }
+        }
+        collect_bucket_pairs(buckets.into_values(), &mut pairs);
+    }
+    sorted_pairs(pairs)
+}
+
+// Pairs (i < j) of SimHashes that agree on at least one of `max_distance + 1`
+// bit blocks, which includes every pair at most `max_distance` bits apart
+pub fn simhash_candidates(hashes: &[SimHash], max_distance: u32) -> Vec<(usize, usize)> {
+    let blocks = (max_distance as usize + 1).min(64);
+    let mut pairs = HashSet::new();
+    for block in 0..blocks {
+        let (start, end) = (block * 64 / blocks, (block + 1) * 64 / blocks);
+        let mask = if end - start == 64 { u64::MAX } else { ((1u64 << (end - start)) - 1) << start };
+        let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, hash) in hashes.iter().enumerate() {
+            buckets.entry(hash.0 & mask).or_default().push(i);
+        }
+        collect_bucket_pairs(buckets.into_values(), &mut pairs);
+    }
+    sorted_pairs(pairs)
+}
+
+fn collect_bucket_pairs<I: Iterator<Item = Vec<usize>>>(buckets: I, pairs: &mut HashSet<(usize, usize)>) {
+    for members in buckets {
+        for (n, &i) in members.iter().enumerate() {
+            for &j in &members[n + 1..] {
+                pairs.insert((i, j));
+            }
+        }
+    }
+}
+
+fn sorted_pairs(pairs: HashSet<(usize, usize)>) -> Vec<(usize, usize)> {
+    let mut pairs: Vec<_> = pairs.into_iter().collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SimilarPair {
+    pub a: usize,
+    pub b: usize,
+    pub similarity: f64,
+}
+
+// Pairs of pages at least `threshold` similar, found through LSH candidates.
+// Pages without chunks (empty or whitespace-only) are never near-duplicates.
+pub fn near_duplicates(sketches: &[PageSketch], kind: SketchKind, threshold: f64) -> Vec<SimilarPair> {
+    let candidates = match kind {
+        SketchKind::MinHash => {
+            let signatures: Vec<&MinHashSignature> = sketches.iter().map(|s| &s.minhash).collect();
+            let size = signatures.iter().map(|s| s.values.len()).min().unwrap_or(1);
+            minhash_candidates(&signatures, LshParams::for_threshold(size, threshold))
+        }
+        SketchKind::SimHash => {
+            let hashes: Vec<SimHash> = sketches.iter().map(|s| s.simhash).collect();
+            simhash_candidates(&hashes, simhash_max_distance(threshold))
+        }
+    };
+    candidates
+        .into_iter()
+        .filter(|&(a, b)| sketches[a].chunks > 0 && sketches[b].chunks > 0)
+        .map(|(a, b)| SimilarPair { a, b, similarity: sketches[a].similarity(&sketches[b], kind) })
+        .filter(|pair| pair.similarity >= threshold)
+        .collect()
+}
+
+// Largest Hamming distance a SimHash pair may have at `threshold` similarity
+pub fn simhash_max_distance(threshold: f64) -> u32 {
+    ((1.0 - threshold.clamp(0.0, 1.0)) * 64.0).floor() as u32
+}
+
+// Connected groups of pages linked by `pairs`, largest first; pages without
+// a similar page are left out
+pub fn group_pairs(page_count: usize, pairs: &[SimilarPair]) -> Vec<Vec<usize>> {
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    let mut parent: Vec<usize> = (0..page_count).collect();
+    for pair in pairs {
+        let (a, b) = (find(&mut parent, pair.a), find(&mut parent, pair.b));
+        if a != b {
+            parent[a.max(b)] = a.min(b);
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for page in 0..page_count {
+        let root = find(&mut parent, page);
+        groups.entry(root).or_default().push(page);
+    }
+    let mut groups: Vec<Vec<usize>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    groups.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+    groups
+}
+
+// Sketch one page, reading it in bounded memory
+pub fn sketch_file<P: AsRef<Path>>(
+    path: P,
+    chunking: Chunking,
+    rules: &IgnoreRules,
+    algorithm: HashAlgorithm,
+    signature_size: usize,
+) -> io::Result<PageSketch> {
+    let reader = BufReader::new(fs::File::open(path)?);
+    let mut minhash = MinHashSignature::new(signature_size);
+    let mut simhash = SimHasher::default();
+    let mut chunks = 0;
+    hash_chunks_reader(reader, chunking, rules, algorithm, |digest| {
+        let hash = digest.to_u64();
+        minhash.insert(hash);
+        simhash.insert(hash);
+        chunks += 1;
+    })?;
+    Ok(PageSketch { minhash, simhash: simhash.finish(), chunks })
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterOptions {
+    pub kind: SketchKind,
+    pub threshold: f64,
+    pub signature_size: usize,
+    pub chunking: Chunking,
+    pub rules: IgnoreRules,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        ClusterOptions {
+            kind: SketchKind::default(),
+            threshold: DEFAULT_THRESHOLD,
+            signature_size: DEFAULT_SIGNATURE_SIZE,
+            chunking: Chunking::default(),
+            rules: IgnoreRules::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PageGroup {
+    pub pages: Vec<String>,
+    pub min_similarity: f64,       // weakest link that joined the group
+    pub max_similarity: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClusterReport {
+    pub directory: String,
+    pub sketch: String,
+    pub threshold: f64,
+    pub signature_size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lsh: Option<LshParams>,    // MinHash banding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_distance: Option<u32>, // SimHash bits
+    pub chunk_size: Chunking,
+    pub hash_algorithm: String,
+    pub pages: usize,
+    pub similar_pairs: usize,
+    pub groups: Vec<PageGroup>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,      // files that could not be read, with the reason
+    pub processing_time_ms: u128,
+}
+
+// Every `.html` / `.htm` file under `dir`, sorted. Symlinks to directories
+// are not followed, so a link back up the tree cannot repeat pages; symlinks
+// to files are listed like the files themselves.
+pub fn html_files<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.as_ref().to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("html") || e.eq_ignore_ascii_case("htm"))
+                && (file_type.is_file() || path.is_file())
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Sketch every page under `dir` in parallel and group the near-duplicates.
+// Chunks are hashed with the algorithm of the global performance config.
+pub fn cluster_directory<P: AsRef<Path>>(dir: P, options: &ClusterOptions) -> io::Result<ClusterReport> {
+    let start = std::time::Instant::now();
+    let algorithm = get_perf_config().hash_algorithm;
+    let files = html_files(&dir)?;
+    let sketched: Vec<(String, io::Result<PageSketch>)> = files
+        .par_iter()
+        .map(|path| {
+            let sketch = sketch_file(path, options.chunking, &options.rules, algorithm, options.signature_size);
+            (path.display().to_string(), sketch)
+        })
+        .collect();
+
+    let mut names = Vec::with_capacity(sketched.len());
+    let mut sketches = Vec::with_capacity(sketched.len());
+    let mut skipped = Vec::new();
+    for (name, sketch) in sketched {
+        match sketch {
+            Ok(sketch) => {
+                names.push(name);
+                sketches.push(sketch);
+            }
+            Err(e) => skipped.push(format!("{}: {}", name, e)),
+        }
+    }
+
+    let pairs = near_duplicates(&sketches, options.kind, options.threshold);
+    let groups = group_pairs(sketches.len(), &pairs)
+        .into_iter()
+        .map(|members| {
+            let similarities: Vec<f64> = pairs
+                .iter()
+                .filter(|p| members.binary_search(&p.a).is_ok())
+                .map(|p| p.similarity)
+                .collect();
+            PageGroup {
+                pages: members.iter().map(|&i| names[i].clone()).collect(),
+                min_similarity: similarities.iter().copied().fold(1.0, f64::min),
+                max_similarity: similarities.iter().copied().fold(0.0, f64::max),
+            }
+        })
+        .collect();
+
+    Ok(ClusterReport {
+        directory: dir.as_ref().display().to_string(),
+        sketch: options.kind.as_str().to_string(),
+        threshold: options.threshold,
+        signature_size: options.signature_size,
+        lsh: (options.kind == SketchKind::MinHash)
+            .then(|| LshParams::for_threshold(options.signature_size, options.threshold)),
+        max_distance: (options.kind == SketchKind::SimHash).then(|| simhash_max_distance(options.threshold)),
+        chunk_size: options.chunking,
+        hash_algorithm: algorithm.as_str().to_string(),
+        pages: sketches.len(),
+        similar_pairs: pairs.len(),
+        groups,
+        skipped,
+        processing_time_ms: start.elapsed().as_millis(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_directories_are_not_walked() {
+        let dir = std::env::temp_dir().join(format!("merkle-domdiff-walk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::write(dir.join("index.html"), "<p>only page</p>").unwrap();
+        use std::os::unix::fs::symlink;
+        symlink("..", dir.join("a/up")).unwrap();
+        symlink("../index.html", dir.join("a/alias.html")).unwrap();
+        symlink(".", dir.join("a/loop.html")).unwrap();
+
+        let files = html_files(&dir).unwrap();
+        let report = cluster_directory(&dir, &ClusterOptions::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files, [dir.join("a/alias.html"), dir.join("index.html")]);
+        assert_eq!(report.pages, 2);
+    }
+
+    fn signature(range: std::ops::Range<u64>) -> MinHashSignature {
+        MinHashSignature::from_hashes(range.map(mix), 256)
+    }
+
+    fn simhash(range: std::ops::Range<u64>) -> SimHash {
+        let mut hasher = SimHasher::default();
+        range.for_each(|h| hasher.insert(h));
+        hasher.finish()
+    }
+
+    fn pair(a: usize, b: usize) -> SimilarPair {
+        SimilarPair { a, b, similarity: 1.0 }
+    }
+
+    #[test]
+    fn minhash_estimates_jaccard_similarity() {
+        // |A ∩ B| / |A ∪ B| for ranges of 1000 hashes overlapping by `overlap`
+        for overlap in [0u64, 250, 500, 800, 1000] {
+            let jaccard = overlap as f64 / (2000 - overlap) as f64;
+            let estimate = signature(0..1000).similarity(&signature(1000 - overlap..2000 - overlap));
+            assert!((estimate - jaccard).abs() < 0.08, "overlap {overlap}: {estimate} vs {jaccard}");
+        }
+
+        // Repeats and insertion order do not matter
+        let repeated = MinHashSignature::from_hashes((0..1000).chain(0..500).rev().map(mix), 256);
+        assert_eq!(repeated, signature(0..1000));
+    }
+
+    #[test]
+    fn empty_signatures_are_similar_to_nothing() {
+        let empty = MinHashSignature::new(64);
+        assert!(empty.is_empty() && !signature(0..1).is_empty());
+        assert_eq!(empty.similarity(&MinHashSignature::new(64)), 0.0);
+        assert_eq!(empty.similarity(&signature(0..10)), 0.0);
+
+        let pages = [
+            PageSketch::from_hashes(std::iter::empty(), 64),
+            PageSketch::from_hashes(std::iter::empty(), 64),
+            PageSketch::from_hashes((0..50).map(mix), 64),
+            PageSketch::from_hashes((0..50).map(mix), 64),
+        ];
+        for kind in [SketchKind::MinHash, SketchKind::SimHash] {
+            let pairs: Vec<_> = near_duplicates(&pages, kind, 0.8).iter().map(|p| (p.a, p.b)).collect();
+            assert_eq!(pairs, [(2, 3)], "{}", kind.as_str());
+        }
+        let signatures: Vec<&MinHashSignature> = pages.iter().map(|p| &p.minhash).collect();
+        assert_eq!(minhash_candidates(&signatures, LshParams { bands: 8, rows: 8 }), [(2, 3)]);
+    }
+
+    #[test]
+    fn simhash_distance_tracks_similarity() {
+        let base = simhash(0..1000);
+        assert_eq!(base.distance(&simhash(0..1000)), 0);
+        assert_eq!(base.similarity(&base), 1.0);
+        let close = base.distance(&simhash(0..1050));
+        let far = base.distance(&simhash(5000..6000));
+        assert!(close < 12 && far > 16, "close {close}, far {far}");
+        assert_eq!(SimHash(0).distance(&SimHash(u64::MAX)), 64);
+        assert_eq!(SimHash(0b1011).similarity(&SimHash(0)), 1.0 - 3.0 / 64.0);
+    }
+
+    #[test]
+    fn lsh_bands_center_the_candidate_curve_on_the_threshold() {
+        assert_eq!(LshParams::for_threshold(128, 0.8), LshParams { bands: 11, rows: 11 });
+        assert_eq!(LshParams::for_threshold(0, 0.5), LshParams { bands: 1, rows: 1 });
+        for size in [16, 64, 128, 200] {
+            for threshold in [0.3, 0.5, 0.8, 0.9] {
+                let params = LshParams::for_threshold(size, threshold);
+                assert!(params.bands * params.rows <= size && params.bands >= 1);
+                let p = |s: f64| params.candidate_probability(s);
+                assert!(p(0.0) == 0.0 && p(1.0) == 1.0);
+                assert!(p(threshold - 0.2) < p(threshold) && p(threshold) < p((threshold + 0.2).min(1.0)));
+            }
+        }
+        let params = LshParams::for_threshold(128, 0.8);
+        assert!(params.candidate_probability(0.95) > 0.99);
+        assert!(params.candidate_probability(0.5) < 0.01);
+    }
+
+    #[test]
+    fn minhash_candidates_share_a_band() {
+        let signatures = [signature(0..1000), signature(0..1000), signature(10..1010), signature(5000..6000)];
+        let refs: Vec<&MinHashSignature> = signatures.iter().collect();
+        let params = LshParams::for_threshold(256, 0.8);
+        assert_eq!(minhash_candidates(&refs, params), [(0, 1), (0, 2), (1, 2)]);
+
+        // A single band of the whole signature only pairs equal signatures
+        assert_eq!(minhash_candidates(&refs, LshParams { bands: 1, rows: 256 }), [(0, 1)]);
+    }
+
+    #[test]
+    fn simhash_candidates_include_every_pair_within_the_distance() {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = || {
+            state = mix(state);
+            state
+        };
+        let base: Vec<u64> = (0..8).map(|_| next()).collect();
+        // Variants of a few base hashes with up to 6 random bits flipped
+        let hashes: Vec<SimHash> = (0..200)
+            .map(|_| {
+                let mut h = base[(next() % 8) as usize];
+                for _ in 0..next() % 7 {
+                    h ^= 1 << (next() % 64);
+                }
+                SimHash(h)
+            })
+            .collect();
+        for max_distance in [0, 3, 6, 10] {
+            let candidates: HashSet<(usize, usize)> = simhash_candidates(&hashes, max_distance).into_iter().collect();
+            for i in 0..hashes.len() {
+                for j in i + 1..hashes.len() {
+                    if hashes[i].distance(&hashes[j]) <= max_distance {
+                        assert!(candidates.contains(&(i, j)), "missed ({i}, {j}) at {max_distance}");
+                    }
+                }
+            }
+        }
+        assert_eq!(simhash_max_distance(0.8), 12);
+        assert_eq!(simhash_max_distance(1.0), 0);
+        assert_eq!(simhash_max_distance(0.0), 64);
+    }
+
+    #[test]
+    fn groups_link_pairs_transitively() {
+        let groups = group_pairs(8, &[pair(5, 6), pair(0, 3), pair(3, 7), pair(6, 5), pair(1, 7)]);
+        assert_eq!(groups, [vec![0, 1, 3, 7], vec![5, 6]]);
+        assert!(group_pairs(3, &[]).is_empty());
+
+        // Equal sizes are ordered by their first page
+        assert_eq!(group_pairs(6, &[pair(4, 5), pair(1, 2)]), [vec![1, 2], vec![4, 5]]);
+    }
+
+    #[test]
+    fn clusters_near_duplicate_pages_in_a_directory() {
+        let dir = std::env::temp_dir().join(format!("merkle-domdiff-cluster-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("blog")).unwrap();
+        let article = |n: usize| {
+            let items: String = (0..40).map(|i| format!("<li>item {i}</li>")).collect();
+            format!("<html><body><h1>Article</h1><ul>{items}</ul><p>footer {n}</p></body></html>")
+        };
+        fs::write(dir.join("a.html"), article(1)).unwrap();
+        fs::write(dir.join("blog/b.htm"), article(2)).unwrap();
+        fs::write(dir.join("c.html"), "<html><body><table><tr><td>unrelated</td></tr></table></body></html>").unwrap();
+        fs::write(dir.join("empty.html"), "").unwrap();
+        fs::write(dir.join("blank.html"), " \n\t\n").unwrap();
+        fs::write(dir.join("notes.txt"), article(1)).unwrap();
+
+        for kind in [SketchKind::MinHash, SketchKind::SimHash] {
+            let options = ClusterOptions { kind, ..ClusterOptions::default() };
+            let report = cluster_directory(&dir, &options).unwrap();
+            assert_eq!(report.pages, 5);
+            assert_eq!(report.groups.len(), 1, "{}: {:?}", kind.as_str(), report.groups);
+            let group = &report.groups[0];
+            assert_eq!(group.pages, [dir.join("a.html").display().to_string(), dir.join("blog/b.htm").display().to_string()]);
+            assert!(group.min_similarity >= 0.8 && group.min_similarity < 1.0);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
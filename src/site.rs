@@ -0,0 +1,255 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::chunking::Chunking;
+use crate::diff::ComparisonResult;
+use crate::differ::DomDiffer;
+use crate::hashing::get_perf_config;
+use crate::similarity::html_files;
+
+// Whole-site comparison: pages of two snapshots of a site are matched by
+// their path relative to the snapshot directory and each pair is compared
+// in parallel. Pages only in the new snapshot are added, pages only in the
+// old one removed; both are compared against an empty document so that all
+// of their chunks count as different.
+//
+// The site score weighs every page by its chunks: the different chunks of
+// all pages over their common and different chunks together. Large pages
+// count for more than small ones, and an added or removed page counts fully.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageStatus {
+    Added,
+    Removed,
+    Changed,
+    Unchanged,
+}
+
+impl PageStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PageStatus::Added => "added",
+            PageStatus::Removed => "removed",
+            PageStatus::Changed => "changed",
+            PageStatus::Unchanged => "unchanged",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageDiff {
+    pub path: String,              // relative to both directories, `/`-separated
+    pub status: PageStatus,
+    pub difference_percent: f64,
+    pub total_chunks_old: usize,
+    pub total_chunks_new: usize,
+    pub common_chunks: usize,
+    pub different_chunks: usize,
+    // The full comparison, with line diffs if `differ` produces them; not saved
+    #[serde(skip)]
+    pub result: Option<ComparisonResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteDiff {
+    pub old_dir: String,
+    pub new_dir: String,
+    pub method: String,
+    pub hash_algorithm: String,
+    pub chunk_size: Chunking,
+    pub difference_percent: f64,   // chunk-weighted over all pages
+    pub pages_old: usize,
+    pub pages_new: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    pub pages: Vec<PageDiff>,      // sorted by path
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,      // pages that could not be read, with the reason
+    pub processing_time_ms: u128,
+}
+
+// `.html` / `.htm` files under `dir` by their `/`-separated relative path
+fn relative_pages(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    Ok(html_files(dir)?
+        .into_iter()
+        .map(|path| {
+            let relative = path.strip_prefix(dir).unwrap_or(&path);
+            let key = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+            (key, path)
+        })
+        .collect())
+}
+
+// Compare every page of `old_dir` with the page at the same relative path in
+// `new_dir`, using the chunking, method and rules of `differ`
+pub fn diff_directories<P: AsRef<Path>, Q: AsRef<Path>>(old_dir: P, new_dir: Q, differ: &DomDiffer) -> io::Result<SiteDiff> {
+    let start = Instant::now();
+    let old_pages = relative_pages(old_dir.as_ref())?;
+    let new_pages = relative_pages(new_dir.as_ref())?;
+    let old_paths: HashMap<&str, &PathBuf> = old_pages.iter().map(|(key, path)| (key.as_str(), path)).collect();
+    let new_paths: HashMap<&str, &PathBuf> = new_pages.iter().map(|(key, path)| (key.as_str(), path)).collect();
+    let keys: BTreeSet<&str> = old_paths.keys().chain(new_paths.keys()).copied().collect();
+    let keys: Vec<&str> = keys.into_iter().collect();
+
+    let compared: Vec<Result<PageDiff, String>> = keys
+        .par_iter()
+        .map(|&key| {
+            let old = old_paths.get(key);
+            let new = new_paths.get(key);
+            let read = |path: Option<&&PathBuf>| match path {
+                Some(path) => fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e)),
+                None => Ok(String::new()),
+            };
+            let (html_old, html_new) = (read(old)?, read(new)?);
+            let result = differ.compare_named(key, &html_old, key, &html_new);
+            let status = match (old.is_some(), new.is_some()) {
+                (false, _) => PageStatus::Added,
+                (_, false) => PageStatus::Removed,
+                _ if result.different_chunks == 0 && result.difference_percent == 0.0 => PageStatus::Unchanged,
+                _ => PageStatus::Changed,
+            };
+            Ok(PageDiff {
+                path: key.to_string(),
+                status,
+                difference_percent: result.difference_percent,
+                total_chunks_old: result.total_chunks_a,
+                total_chunks_new: result.total_chunks_b,
+                common_chunks: result.common_chunks,
+                different_chunks: result.different_chunks,
+                result: Some(result),
+            })
+        })
+        .collect();
+
+    let mut pages = Vec::with_capacity(compared.len());
+    let mut skipped = Vec::new();
+    for page in compared {
+        match page {
+            Ok(page) => pages.push(page),
+            Err(e) => skipped.push(e),
+        }
+    }
+
+    let count = |status: PageStatus| pages.iter().filter(|p| p.status == status).count();
+    let different: usize = pages.iter().map(|p| p.different_chunks).sum();
+    let total: usize = pages.iter().map(|p| p.common_chunks + p.different_chunks).sum();
+    Ok(SiteDiff {
+        old_dir: old_dir.as_ref().display().to_string(),
+        new_dir: new_dir.as_ref().display().to_string(),
+        method: differ.method.as_str().to_string(),
+        hash_algorithm: get_perf_config().hash_algorithm.as_str().to_string(),
+        chunk_size: differ.chunking,
+        difference_percent: if total == 0 { 0.0 } else { different as f64 / total as f64 * 100.0 },
+        pages_old: old_pages.len(),
+        pages_new: new_pages.len(),
+        added: count(PageStatus::Added),
+        removed: count(PageStatus::Removed),
+        changed: count(PageStatus::Changed),
+        unchanged: count(PageStatus::Unchanged),
+        pages,
+        skipped,
+        processing_time_ms: start.elapsed().as_millis(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, relative: &str, content: &str) {
+        let path = dir.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn pairs_pages_by_relative_path_and_weighs_them_by_chunks() {
+        let root = std::env::temp_dir().join(format!("merkle-domdiff-site-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (old, new) = (root.join("old"), root.join("new"));
+
+        let index = "<html><body><h1>Home</h1><p>Welcome</p></body></html>";
+        write(&old, "index.html", index);
+        write(&new, "index.html", index);
+        write(&old, "about/team.html", "<ul><li>Ann</li><li>Bo</li></ul>");
+        write(&new, "about/team.html", "<ul><li>Ann</li><li>Cy</li></ul>");
+        write(&old, "gone.html", "<p>old news</p>");
+        write(&new, "blog/first.htm", "<article><h2>First</h2></article>");
+        write(&old, "notes.txt", "not a page");
+        write(&new, "about/team.html.bak", "<p>not a page either</p>");
+
+        let differ = DomDiffer::new();
+        let site = diff_directories(&old, &new, &differ).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let statuses: Vec<_> = site.pages.iter().map(|p| (p.path.as_str(), p.status)).collect();
+        assert_eq!(
+            statuses,
+            [
+                ("about/team.html", PageStatus::Changed),
+                ("blog/first.htm", PageStatus::Added),
+                ("gone.html", PageStatus::Removed),
+                ("index.html", PageStatus::Unchanged),
+            ]
+        );
+        assert_eq!((site.pages_old, site.pages_new), (3, 3));
+        assert_eq!((site.added, site.removed, site.changed, site.unchanged), (1, 1, 1, 1));
+        assert!(site.skipped.is_empty());
+
+        // Each page matches a direct comparison; added and removed pages are
+        // compared with an empty document
+        let team = differ.compare("<ul><li>Ann</li><li>Bo</li></ul>", "<ul><li>Ann</li><li>Cy</li></ul>");
+        let page = &site.pages[0];
+        assert_eq!(page.difference_percent, team.difference_percent);
+        assert_eq!((page.common_chunks, page.different_chunks), (team.common_chunks, team.different_chunks));
+        let added = &site.pages[1];
+        assert_eq!((added.total_chunks_old, added.common_chunks, added.difference_percent), (0, 0, 100.0));
+        assert_eq!(added.different_chunks, added.total_chunks_new);
+        let removed = &site.pages[2];
+        assert_eq!((removed.total_chunks_new, removed.different_chunks), (0, removed.total_chunks_old));
+
+        let different: usize = site.pages.iter().map(|p| p.different_chunks).sum();
+        let total: usize = site.pages.iter().map(|p| p.common_chunks + p.different_chunks).sum();
+        assert_eq!(different, team.different_chunks + added.total_chunks_new + removed.total_chunks_old);
+        assert_eq!(site.difference_percent, different as f64 / total as f64 * 100.0);
+        assert!(site.difference_percent > 0.0 && site.difference_percent < 100.0);
+    }
+
+    #[test]
+    fn identical_and_empty_sites_score_zero() {
+        let root = std::env::temp_dir().join(format!("merkle-domdiff-site-same-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (old, new) = (root.join("old"), root.join("new"));
+        fs::create_dir_all(&old).unwrap();
+        fs::create_dir_all(&new).unwrap();
+
+        let empty = diff_directories(&old, &new, &DomDiffer::new()).unwrap();
+        assert!(empty.pages.is_empty());
+        assert_eq!(empty.difference_percent, 0.0);
+
+        write(&old, "a/b.html", "<p>same</p>");
+        write(&new, "a/b.html", "<p  >same</p>");
+        let same = diff_directories(&old, &new, &DomDiffer::new()).unwrap();
+        assert_eq!(same.unchanged, 1);
+        assert_eq!(same.difference_percent, 0.0);
+
+        // Each page keeps its comparison, with line diffs when the differ makes them
+        write(&new, "a/c.html", "<p>new</p>");
+        let lines = diff_directories(&old, &new, &DomDiffer::new().line_diffs(true)).unwrap();
+        let added = lines.pages.iter().find(|p| p.path == "a/c.html").unwrap();
+        let result = added.result.as_ref().unwrap();
+        assert!(!result.line_diffs.is_empty() && result.line_diffs.iter().all(|d| d.change_type == "added"));
+        assert!(lines.pages.iter().all(|p| p.status != PageStatus::Unchanged || p.result.as_ref().unwrap().line_diffs.is_empty()));
+
+        assert!(diff_directories(root.join("missing"), &new, &DomDiffer::new()).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
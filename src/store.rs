@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+use crate::chunking::{Chunking, chunk_ranges};
+use crate::diff::detailed_diff;
+use crate::hashing::{HashAlgorithm, hash_chunk_with, to_hex};
+use crate::ignore::IgnoreRules;
+use crate::merkle::{MerkleNode, TreeHasher, build_merkle_tree_from_hashes, merkle_levels};
+use crate::normalize::normalize_html_with_rules;
+
+// Content-addressed store of full Merkle trees for re-diffing the same pages
+// over time. Each snapshot is kept once under its root hash:
+//
+//     <store>/objects/<root>.json    chunks, their lines and every tree level
+//     <store>/refs/<sha256 of name>  name and root of the latest snapshot of a page
+//
+// A new snapshot taken against a stored baseline reuses the hashes of chunks
+// and internal nodes the baseline already has, and the comparison only
+// descends into subtrees whose hashes differ.
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub root: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default = "crate::merkle::legacy_tree_version")]
+    pub tree_version: u32,
+    pub chunk_size: Chunking,
+    pub chunks: Vec<String>,
+    pub lines: Vec<usize>,         // first line of each chunk
+    pub levels: Vec<Vec<String>>,  // tree hashes, leaf hashes first and the root last
+}
+
+// How much work a snapshot took, and what the baseline saved
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SnapshotStats {
+    pub hashed_leaves: usize,
+    pub reused_leaves: usize,
+    pub hashed_nodes: usize,
+    pub reused_nodes: usize,
+}
+
+impl Snapshot {
+    // Normalize and hash `html` with the configured algorithm and tree
+    // version, taking already known chunk and node hashes from `baseline`
+    pub fn build(
+        html: &str,
+        chunking: impl Into<Chunking>,
+        rules: &IgnoreRules,
+        baseline: Option<&Snapshot>,
+    ) -> (Snapshot, SnapshotStats) {
+        let chunk_size = chunking.into();
+        let hasher = TreeHasher::from_config();
+        let (chunks, tokens) = normalize_html_with_rules(html, chunk_size, rules);
+        let lines: Vec<usize> = chunk_ranges(&tokens, chunk_size).into_iter().map(|r| tokens[r.start].line_number).collect();
+
+        // A baseline with another chunk size or tree hashing shares no hashes with this snapshot
+        let baseline = baseline.filter(|b| b.chunk_size == chunk_size && b.tree_hasher() == hasher);
+        let known_leaves: HashMap<&str, &str> = baseline
+            .map(|b| b.chunks.iter().map(String::as_str).zip(b.leaf_hashes().iter().map(String::as_str)).collect())
+            .unwrap_or_default();
+        let known_nodes = baseline.map(|b| b.internal_nodes()).unwrap_or_default();
+
+        let mut stats = SnapshotStats::default();
+        let leaf_hashes: Vec<String> = chunks
+            .iter()
+            .map(|chunk| match known_leaves.get(chunk.as_str()) {
+                Some(&hash) => {
+                    stats.reused_leaves += 1;
+                    hash.to_string()
+                }
+                None => {
+                    stats.hashed_leaves += 1;
+                    hasher.leaf(chunk)
+                }
+            })
+            .collect();
+
+        let tree = build_merkle_tree_from_hashes(leaf_hashes, hasher, |left, right| {
+            let known = known_nodes.get(&(left, right)).map(|&hash| hash.to_string());
+            match known {
+                Some(_) => stats.reused_nodes += 1,
+                None => stats.hashed_nodes += 1,
+            }
+            known
+        });
+        let levels = tree.as_ref().map(merkle_levels).unwrap_or_default();
+
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            root: tree.map(|t| t.hash.clone()).unwrap_or_else(|| hash_chunk_with(hasher.algorithm, "")),
+            hash_algorithm: hasher.algorithm,
+            tree_version: hasher.version,
+            chunk_size,
+            chunks,
+            lines,
+            levels,
+        };
+        (snapshot, stats)
+    }
+
+    pub fn tree_hasher(&self) -> TreeHasher {
+        TreeHasher {
+            algorithm: self.hash_algorithm,
+            version: self.tree_version,
+        }
+    }
+
+    pub fn leaf_hashes(&self) -> &[String] {
+        self.levels.first().map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // Hash of every two-child internal node, by its children's hashes
+    fn internal_nodes(&self) -> HashMap<(&str, &str), &str> {
+        let mut nodes = HashMap::new();
+        for pair in self.levels.windows(2) {
+            for (i, hash) in pair[1].iter().enumerate() {
+                if let Some(right) = pair[0].get(2 * i + 1) {
+                    nodes.insert((pair[0][2 * i].as_str(), right.as_str()), hash.as_str());
+                }
+            }
+        }
+        nodes
+    }
+
+    // Recompute every leaf and node hash from the chunks with the snapshot's
+    // own tree hashing, and check them against the stored levels and root
+    pub fn verify(&self) -> Result<(), String> {
+        let hasher = self.tree_hasher();
+        if self.lines.len() != self.chunks.len() {
+            return Err(format!("{} lines recorded for {} chunks", self.lines.len(), self.chunks.len()));
+        }
+        let leaf_hashes: Vec<String> = self.chunks.iter().map(|chunk| hasher.leaf(chunk)).collect();
+        if leaf_hashes != self.leaf_hashes() {
+            return Err("leaf hashes do not match the chunks".to_string());
+        }
+        let tree = build_merkle_tree_from_hashes(leaf_hashes, hasher, |_, _| None);
+        let levels = tree.as_ref().map(merkle_levels).unwrap_or_default();
+        let root = tree.map(|t| t.hash.clone()).unwrap_or_else(|| hash_chunk_with(hasher.algorithm, ""));
+        if levels != self.levels || root != self.root {
+            return Err(format!("tree hashes do not match the root {}", self.root));
+        }
+        Ok(())
+    }
+
+    // Rebuild the `MerkleNode` tree from the stored levels, without hashing
+    pub fn tree(&self) -> Option<Arc<MerkleNode>> {
+        let known = self.internal_nodes();
+        build_merkle_tree_from_hashes(self.leaf_hashes().to_vec(), self.tree_hasher(), |left, right| {
+            known.get(&(left, right)).map(|&hash| hash.to_string())
+        })
+    }
+}
+
+// Result of comparing a snapshot against its baseline
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotDiff {
+    pub baseline_root: String,
+    pub root: String,
+    pub difference_percent: f64,
+    pub compared_nodes: usize,     // tree nodes visited; identical subtrees are not entered
+    pub changed_chunks: Vec<usize>, // chunks of the new snapshot that differ from the baseline's at the same position
+}
+
+// Walk both trees top-down, comparing nodes that cover the same chunk range,
+// and collect the chunks of `new` under every differing subtree
+pub fn diff_snapshots(old: &Snapshot, new: &Snapshot) -> SnapshotDiff {
+    let (percent, _, _, _, _) = detailed_diff(old.leaf_hashes(), new.leaf_hashes());
+    let mut diff = SnapshotDiff {
+        baseline_root: old.root.clone(),
+        root: new.root.clone(),
+        difference_percent: percent,
+        compared_nodes: 0,
+        changed_chunks: Vec::new(),
+    };
+    if !new.levels.is_empty() {
+        let top = new.levels.len() - 1;
+        diff_level(old, new, top, 0, &mut diff);
+    }
+    diff
+}
+
+fn diff_level(old: &Snapshot, new: &Snapshot, level: usize, index: usize, diff: &mut SnapshotDiff) {
+    let Some(hash) = new.levels[level].get(index) else {
+        return;
+    };
+    diff.compared_nodes += 1;
+    if old.levels.get(level).and_then(|l| l.get(index)) == Some(hash) {
+        return;
+    }
+    if level == 0 {
+        diff.changed_chunks.push(index);
+    } else {
+        diff_level(old, new, level - 1, 2 * index, diff);
+        diff_level(old, new, level - 1, 2 * index + 1, diff);
+    }
+}
+
+// Contents of a ref file; the file name only holds a hash of the page name
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredRef {
+    name: String,
+    root: String,
+}
+
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl SnapshotStore {
+    // Open (and create if needed) a store directory
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("objects"))?;
+        fs::create_dir_all(dir.join("refs"))?;
+        Ok(SnapshotStore { dir })
+    }
+
+    fn object_path(&self, root: &str) -> PathBuf {
+        self.dir.join("objects").join(format!("{}.json", root))
+    }
+
+    fn ref_path(&self, name: &str) -> PathBuf {
+        // Page names are often URLs or paths; name the file after a hash of the
+        // exact name, so that names differing only in punctuation never collide
+        self.dir.join("refs").join(to_hex(&Sha256::digest(name.as_bytes())))
+    }
+
+    pub fn contains(&self, root: &str) -> bool {
+        self.object_path(root).exists()
+    }
+
+    // Store a snapshot under its root hash. Snapshots with the same root can
+    // still differ in their chunk lines, so the object is rewritten whenever
+    // its stored bytes change.
+    pub fn save(&self, snapshot: &Snapshot) -> io::Result<()> {
+        let json = serde_json::to_string(snapshot).map_err(io::Error::other)?;
+        let path = self.object_path(&snapshot.root);
+        if fs::read(&path).is_ok_and(|stored| stored == json.as_bytes()) {
+            return Ok(());
+        }
+        write_atomically(&path, json.as_bytes())
+    }
+
+    // Load the snapshot stored under `root`, rejecting objects whose content
+    // does not hash to it
+    pub fn load(&self, root: &str) -> io::Result<Snapshot> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("snapshot {}: {}", root, message));
+        let content = fs::read_to_string(self.object_path(root))?;
+        let snapshot: Snapshot = serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(invalid(format!("unsupported version {}", snapshot.version)));
+        }
+        TreeHasher::new(snapshot.hash_algorithm, snapshot.tree_version).map_err(invalid)?;
+        if snapshot.root != root {
+            return Err(invalid(format!("object holds snapshot {}", snapshot.root)));
+        }
+        snapshot.verify().map_err(invalid)?;
+        Ok(snapshot)
+    }
+
+    // Root of the latest snapshot recorded for `name`
+    pub fn resolve(&self, name: &str) -> io::Result<Option<String>> {
+        let content = match fs::read_to_string(self.ref_path(name)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let stored: StoredRef = serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if stored.name != name {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("ref for {} holds the name {}", name, stored.name),
+            ));
+        }
+        Ok(Some(stored.root))
+    }
+
+    pub fn set_ref(&self, name: &str, root: &str) -> io::Result<()> {
+        let stored = StoredRef {
+            name: name.to_string(),
+            root: root.to_string(),
+        };
+        let json = serde_json::to_string(&stored).map_err(io::Error::other)?;
+        write_atomically(&self.ref_path(name), json.as_bytes())
+    }
+
+    // Latest snapshot of `name`, if any
+    pub fn baseline(&self, name: &str) -> io::Result<Option<Snapshot>> {
+        match self.resolve(name)? {
+            Some(root) => self.load(&root).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// Write through a temporary file in the same directory and rename it over
+// `path`, so readers never see a partly written file
+fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".tmp-{}", std::process::id()));
+    let temp = PathBuf::from(temp);
+    fs::write(&temp, content)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(name: &str) -> (PathBuf, SnapshotStore) {
+        let dir = std::env::temp_dir().join(format!("merkle-domdiff-store-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = SnapshotStore::open(&dir).unwrap();
+        (dir, store)
+    }
+
+    fn snapshot(html: &str) -> Snapshot {
+        Snapshot::build(html, 1, &IgnoreRules::default(), None).0
+    }
+
+    #[test]
+    fn refs_differing_only_in_punctuation_are_kept_apart() {
+        let (dir, store) = temp_store("refs");
+        let names = ["https://x/a/b", "https://x/a_b", "https://x/a.b", "https://x/a?b"];
+        for (i, name) in names.iter().enumerate() {
+            store.set_ref(name, &format!("root{}", i)).unwrap();
+        }
+        let resolved: Vec<_> = names.iter().map(|name| store.resolve(name).unwrap()).collect();
+        let missing = store.resolve("https://x/a-b").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(resolved, ["root0", "root1", "root2", "root3"].map(|r| Some(r.to_string())));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn saving_the_same_root_with_moved_lines_updates_them() {
+        let (dir, store) = temp_store("lines");
+        let first = snapshot("<p>a</p><p>b</p>");
+        let moved = snapshot("<p>a</p>\n\n\n<p>b</p>");
+        assert_eq!(first.root, moved.root);
+        assert_ne!(first.lines, moved.lines);
+
+        store.save(&first).unwrap();
+        store.save(&moved).unwrap();
+        let loaded = store.load(&first.root);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.unwrap().lines, moved.lines);
+    }
+
+    #[test]
+    fn load_rejects_objects_that_do_not_hash_to_their_root() {
+        let (dir, store) = temp_store("tamper");
+        let original = snapshot("<div><p>one</p><p>two</p><p>three</p></div>");
+        store.save(&original).unwrap();
+        let path = store.object_path(&original.root);
+
+        let mut edited = original.clone();
+        edited.chunks[2] = "TEXT:tampered".to_string();
+        fs::write(&path, serde_json::to_string(&edited).unwrap()).unwrap();
+        let edited_err = store.load(&original.root).unwrap_err();
+
+        let mut relabeled = original.clone();
+        relabeled.levels.last_mut().unwrap()[0] = "0".repeat(original.root.len());
+        fs::write(&path, serde_json::to_string(&relabeled).unwrap()).unwrap();
+        let relabeled_err = store.load(&original.root).unwrap_err();
+
+        let json = serde_json::to_string(&original).unwrap();
+        fs::write(&path, &json[..json.len() / 2]).unwrap();
+        let truncated_err = store.load(&original.root).unwrap_err();
+
+        store.save(&original).unwrap();
+        let restored = store.load(&original.root);
+        fs::remove_dir_all(&dir).unwrap();
+
+        for err in [edited_err, relabeled_err, truncated_err] {
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{err}");
+        }
+        assert_eq!(restored.unwrap().chunks, original.chunks);
+    }
+}
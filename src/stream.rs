@@ -0,0 +1,339 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead};
+use serde::{Deserialize, Serialize};
+
+use crate::chunking::{ChunkJoiner, Chunking};
+use crate::hashing::{HashAlgorithm, HashDigest};
+use crate::ignore::IgnoreRules;
+use crate::normalize::{NormalizedToken, Normalizer};
+use crate::tokenizer::{tokenize, tokenize_prefix};
+
+// Streaming comparison for documents too large to hold in memory. Input is
+// read through a `BufRead` a block at a time; each block is tokenized as far
+// as its tokens are final and normalized with the element state carried
+// over, and only the unfinished tail is kept for the next block. Memory is
+// bounded by the block size plus the longest single token (typically an
+// inline script), not by the document.
+//
+// The set-based percentage needs the distinct chunk hashes of both documents.
+// Rather than keeping all of them, a `ChunkSketch` keeps the smallest
+// `capacity` ones (a bottom-k sketch): documents with fewer distinct chunks
+// are still compared exactly, larger ones get an estimate whose error shrinks
+// with the capacity (about `sqrt(p * (1 - p) / capacity)` for a difference
+// ratio `p`).
+
+pub const BLOCK_SIZE: usize = 64 * 1024;
+pub const DEFAULT_SKETCH_CAPACITY: usize = 16_384;
+
+// Call `visit` for every normalized token of the document read from `reader`,
+// exactly as `walk_normalized_tokens` would for the whole document at once
+pub fn walk_normalized_reader<R, F>(reader: R, rules: &IgnoreRules, visit: F) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(NormalizedToken<'_>),
+{
+    walk_normalized_blocks(reader, rules, BLOCK_SIZE, visit)
+}
+
+// `walk_normalized_reader` reading `min_block` bytes at a time
+fn walk_normalized_blocks<R, F>(mut reader: R, rules: &IgnoreRules, min_block: usize, mut visit: F) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(NormalizedToken<'_>),
+{
+    let mut normalizer = Normalizer::new(rules);
+    let mut buffer: Vec<u8> = Vec::with_capacity(min_block);
+    let mut block_size = min_block;
+    let mut line = 1;
+    let mut eof = false;
+
+    while !eof {
+        let target = buffer.len() + block_size;
+        while buffer.len() < target {
+            let available = reader.fill_buf()?;
+            if available.is_empty() {
+                eof = true;
+                break;
+            }
+            let n = available.len().min(target - buffer.len());
+            buffer.extend_from_slice(&available[..n]);
+            reader.consume(n);
+        }
+
+        let text = match std::str::from_utf8(&buffer) {
+            Ok(text) => text,
+            // A character split by the block boundary is completed by the next block
+            Err(e) if e.error_len().is_none() && !eof => {
+                std::str::from_utf8(&buffer[..e.valid_up_to()]).expect("valid up to here")
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let (tokens, covered) = if eof { (tokenize(text), text.len()) } else { tokenize_prefix(text) };
+        normalizer.visit_tokens(&text[..covered], &tokens, line, &mut visit);
+        line += text.as_bytes()[..covered].iter().filter(|&&b| b == b'\n').count();
+
+        // A token longer than the buffer needs more input at once; grow the
+        // blocks so it is not re-tokenized once per block
+        block_size = if covered == 0 { block_size * 2 } else { min_block };
+        buffer.drain(..covered);
+    }
+    Ok(())
+}
+
+// Bottom-k sketch of the distinct chunk hashes of one document
+#[derive(Debug, Clone)]
+pub struct ChunkSketch {
+    capacity: usize,
+    values: BTreeSet<u64>,         // smallest distinct hashes seen
+    chunks: usize,                 // chunks inserted, repeats included
+    saturated: bool,               // some distinct hash did not fit
+}
+
+impl ChunkSketch {
+    pub fn new(capacity: usize) -> Self {
+        ChunkSketch {
+            capacity: capacity.max(1),
+            values: BTreeSet::new(),
+            chunks: 0,
+            saturated: false,
+        }
+    }
+
+    pub fn insert(&mut self, hash: u64) {
+        self.chunks += 1;
+        if self.values.contains(&hash) {
+            return;
+        }
+        if self.values.len() < self.capacity {
+            self.values.insert(hash);
+            return;
+        }
+        self.saturated = true;
+        if self.values.last().is_some_and(|&max| hash < max) {
+            self.values.pop_last();
+            self.values.insert(hash);
+        }
+    }
+
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    // Whether the sketch holds every distinct hash, so comparisons are exact
+    pub fn is_exact(&self) -> bool {
+        !self.saturated
+    }
+}
+
+// How a streamed comparison was computed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SketchSummary {
+    pub capacity: usize,
+    pub exact: bool,               // both documents fit their sketches
+}
+
+// Call `visit` with the digest of every chunk of the document read from
+// `reader`. Chunks are joined and hashed exactly as in `normalize_html_with_rules`.
+pub fn hash_chunks_reader<R, F>(
+    reader: R,
+    chunking: impl Into<Chunking>,
+    rules: &IgnoreRules,
+    algorithm: HashAlgorithm,
+    mut visit: F,
+) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(HashDigest),
+{
+    let hasher = algorithm.hasher();
+    let mut joiner = ChunkJoiner::new(chunking.into());
+
+    walk_normalized_reader(reader, rules, |normalized| {
+        joiner.push(normalized.content, |chunk| visit(hasher.digest(chunk.as_bytes())));
+    })?;
+    joiner.finish(|chunk| visit(hasher.digest(chunk.as_bytes())));
+    Ok(())
+}
+
+// Hash the chunks of the document read from `reader` into a sketch
+pub fn sketch_reader<R: BufRead>(
+    reader: R,
+    chunking: impl Into<Chunking>,
+    rules: &IgnoreRules,
+    algorithm: HashAlgorithm,
+    capacity: usize,
+) -> io::Result<ChunkSketch> {
+    let mut sketch = ChunkSketch::new(capacity);
+    hash_chunks_reader(reader, chunking, rules, algorithm, |digest| sketch.insert(digest.to_u64()))?;
+    Ok(sketch)
+}
+
+// Set comparison of two sketches, in the shape of `detailed_diff`:
+// (percent, total_a, total_b, common, different). Common and different
+// chunks are estimates unless both sketches are exact.
+pub fn compare_sketches(a: &ChunkSketch, b: &ChunkSketch) -> (f64, usize, usize, usize, usize) {
+    if a.is_exact() && b.is_exact() {
+        let common = a.values.intersection(&b.values).count();
+        let different = a.values.symmetric_difference(&b.values).count();
+        let total = common + different;
+        let percent = if total == 0 { 0.0 } else { (different as f64 / total as f64) * 100.0 };
+        return (percent, a.chunks, b.chunks, common, different);
+    }
+
+    // The k smallest hashes of the union are a uniform sample of it. Each
+    // sketch holds all of its document's hashes up to the sample's largest,
+    // so membership in both is known for every sampled hash.
+    let k = a.capacity.min(b.capacity);
+    let sample: Vec<u64> = a.values.union(&b.values).take(k).copied().collect();
+    let in_both = sample.iter().filter(|h| a.values.contains(h) && b.values.contains(h)).count();
+    let similarity = in_both as f64 / sample.len() as f64;
+
+    // Distinct hashes in the union, from how densely the sample fills the hash space
+    let largest = sample.last().copied().unwrap_or(u64::MAX) as f64;
+    let union = ((sample.len() - 1) as f64 * 2f64.powi(64) / (largest + 1.0)).round() as usize;
+    let common = (similarity * union as f64).round() as usize;
+
+    ((1.0 - similarity) * 100.0, a.chunks, b.chunks, common, union - common)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ignore::IgnoreConfig;
+    use crate::normalize::{hash_spans, walk_normalized_tokens};
+
+    // Hands out 1, 2 or 3 bytes per read, so characters and tokens are split
+    // at every possible position
+    struct Trickle<'a> {
+        data: &'a [u8],
+        pos: usize,
+        reads: usize,
+    }
+
+    impl<'a> Trickle<'a> {
+        fn new(data: &'a str) -> Self {
+            Trickle { data: data.as_bytes(), pos: 0, reads: 0 }
+        }
+    }
+
+    impl io::Read for Trickle<'_> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let available = self.fill_buf()?;
+            let n = available.len().min(out.len());
+            out[..n].copy_from_slice(&available[..n]);
+            self.consume(n);
+            Ok(n)
+        }
+    }
+
+    impl BufRead for Trickle<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            let n = (1 + self.reads % 3).min(self.data.len() - self.pos);
+            Ok(&self.data[self.pos..self.pos + n])
+        }
+
+        fn consume(&mut self, amount: usize) {
+            self.pos += amount;
+            self.reads += 1;
+        }
+    }
+
+    // Every construct whose end the tokenizer has to look ahead for
+    const TRICKY: &str = "<!DOCTYPE html>\n<html lang=\"en\"><head><title>A &amp; B &lt;tag&gt;</title>\n\
+        <style>p > a { content: \"</p>\"; }</style>\n\
+        <script>if (a < b && c</d) { x = \"</div>\"; } // </scrip</script>\n</head>\n\
+        <body class=\"x\" data-json='{\"a\": \"1 > 0\"}'>\n\
+        <!-- a comment with <div> tags --> <!--> <!---> <?pi?> </>\n\
+        <p title=\"caf\u{e9} &eacute; &notin\">caf\u{e9} \u{20ac}5 &frac12; &#x1F600; \u{1F600} &ampx &copy</p>\n\
+        <svg><![CDATA[ <b>raw</b> ]]></svg>\n\
+        <textarea>&lt;b&gt; <i>not a tag</i></textarea>\n\
+        <ul><li>one<li>two<li data-id=\"123\">three</ul>\n\
+        <div   id = unquoted   hidden >a < b</div>\n\
+        <p>unterminated & ampersand; &unknown; <a href=\"?a=1&copy=2\">q</a></p>\n\
+        <div class=\"ad\"><p>masked <b>away</b></p></div><img src=x alt=\"y\"/>\n\
+        </body></html>\n";
+
+    fn tricky_document(repeat: usize) -> String {
+        TRICKY.repeat(repeat)
+    }
+
+    fn rules() -> IgnoreRules {
+        IgnoreRules::new(&IgnoreConfig {
+            selectors: vec!["div.ad".to_string()],
+            attributes: vec!["data-*".to_string()],
+            values: vec![r"\d{3}".to_string()],
+        })
+        .unwrap()
+    }
+
+    type Normalized = (String, usize, bool);
+
+    fn in_memory(html: &str, rules: &IgnoreRules) -> Vec<Normalized> {
+        let mut tokens = Vec::new();
+        walk_normalized_tokens(html, rules, |t| tokens.push((t.content.to_string(), t.line_number, t.masked_subtree)));
+        tokens
+    }
+
+    fn streamed<R: BufRead>(reader: R, rules: &IgnoreRules, min_block: usize) -> Vec<Normalized> {
+        let mut tokens = Vec::new();
+        walk_normalized_blocks(reader, rules, min_block, |t| {
+            tokens.push((t.content.to_string(), t.line_number, t.masked_subtree))
+        })
+        .unwrap();
+        tokens
+    }
+
+    #[test]
+    fn small_blocks_produce_the_same_tokens() {
+        let html = tricky_document(2);
+        for rules in [IgnoreRules::default(), rules()] {
+            let expected = in_memory(&html, &rules);
+            assert!(expected.len() > 50);
+            for min_block in (1..=17).chain([64, 333]) {
+                let tokens = streamed(Trickle::new(&html), &rules, min_block);
+                assert!(tokens == expected, "tokens differ with {min_block}-byte blocks");
+            }
+        }
+    }
+
+    #[test]
+    fn every_split_point_produces_the_same_tokens() {
+        // Two blocks, cut at each byte of the document in turn
+        let html = TRICKY;
+        let expected = in_memory(html, &IgnoreRules::default());
+        for cut in 1..html.len() {
+            let tokens = streamed(Trickle::new(html), &IgnoreRules::default(), cut);
+            assert!(tokens == expected, "tokens differ when the first block ends at byte {cut}");
+        }
+    }
+
+    #[test]
+    fn full_size_blocks_split_tokens_and_characters() {
+        // Large enough for several 64 KiB blocks, whose boundaries fall
+        // inside tags, comments, references, scripts and multi-byte characters
+        let html = tricky_document(BLOCK_SIZE * 3 / TRICKY.len() + 1);
+        assert!(html.len() > 3 * BLOCK_SIZE);
+        let rules = rules();
+        let mut tokens = Vec::new();
+        walk_normalized_reader(Trickle::new(&html), &rules, |t| {
+            tokens.push((t.content.to_string(), t.line_number, t.masked_subtree))
+        })
+        .unwrap();
+        assert!(tokens == in_memory(&html, &rules));
+
+        for chunking in [Chunking::Fixed(1), Chunking::Fixed(7), crate::chunking::ContentDefined::around(8).into()] {
+            let mut streamed = Vec::new();
+            hash_chunks_reader(Trickle::new(&html), chunking, &rules, HashAlgorithm::Xxh3_64, |d| streamed.push(d)).unwrap();
+            let expected = hash_spans(&html, chunking, &rules, HashAlgorithm::Xxh3_64).chunk_hashes;
+            assert!(streamed == expected, "chunk hashes differ with {chunking}");
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let mut bytes = b"<p>ok</p>".to_vec();
+        bytes.extend_from_slice(&[0xff, 0xfe]);
+        let err = walk_normalized_blocks(&bytes[..], &IgnoreRules::default(), 4, |_| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::diff::{ComparisonResult, LineDiff, parse_line_range};
+use crate::sequence::{DiffOp, diff_sequences};
+
+// Text renderings of a comparison over the original source lines. The DOM
+// comparison decides how different two documents are; these show where, as
+// a line diff of the sources:
+//
+//   - `unified_diff` is a standard unified diff (`---`/`+++` headers, `@@`
+//     hunks with context), accepted by `patch`, `git apply` and review tools.
+//   - `side_by_side` puts both versions next to each other, optionally with
+//     ANSI colours for terminals.
+//
+// The text after a hunk's second `@@` (where `diff -p` puts the function
+// name) is the first DOM change reported within the hunk's lines; tools that
+// apply patches ignore it.
+
+pub const DEFAULT_CONTEXT: usize = 3;
+pub const DEFAULT_WIDTH: usize = 160;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+// A run of line changes with the unchanged lines around it
+#[derive(Debug, Clone)]
+pub struct TextHunk {
+    pub old_start: usize,          // 1-based; the line before the hunk if it has no old lines
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub ops: Vec<DiffOp>,          // indices into the line lists of both documents
+}
+
+impl TextHunk {
+    // `@@ -old +new @@` in the form of GNU diff, which leaves out a count of one
+    pub fn header(&self) -> String {
+        let range = |start: usize, lines: usize| match lines {
+            1 => start.to_string(),
+            _ => format!("{},{}", start, lines),
+        };
+        format!("@@ -{} +{} @@", range(self.old_start, self.old_lines), range(self.new_start, self.new_lines))
+    }
+
+    // First DOM change whose lines fall within the hunk on either side
+    fn heading<'a>(&self, line_diffs: &'a [LineDiff]) -> Option<&'a str> {
+        let old = self.old_start..self.old_start + self.old_lines.max(1);
+        let new = self.new_start..self.new_start + self.new_lines.max(1);
+        let overlaps = |range: Option<&str>, lines: &std::ops::Range<usize>| {
+            range.and_then(parse_line_range).is_some_and(|(start, end)| start < lines.end && end >= lines.start)
+        };
+        line_diffs
+            .iter()
+            .find(|d| match d.change_type.as_str() {
+                "removed" => overlaps(Some(&d.line_range), &old),
+                "added" => overlaps(Some(&d.line_range), &new),
+                _ => overlaps(d.old_line_range.as_deref(), &old) || overlaps(d.new_line_range.as_deref(), &new),
+            })
+            .map(|d| d.content_preview.as_str())
+    }
+}
+
+// Lines of `text` with their line terminators; the last one lacks it if the
+// text does not end with a newline
+pub fn source_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+// Line changes between `old` and `new` grouped into hunks, each with up to
+// `context` unchanged lines on both sides. Changes closer than twice the
+// context share a hunk.
+pub fn text_hunks(old: &[&str], new: &[&str], context: usize) -> Vec<TextHunk> {
+    let ops = diff_sequences(old, new);
+
+    // Old and new line index before each operation
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in &ops {
+        positions.push((i, j));
+        match op {
+            DiffOp::Equal(_, _) => (i, j) = (i + 1, j + 1),
+            DiffOp::Delete(_) => i += 1,
+            DiffOp::Insert(_) => j += 1,
+        }
+    }
+    positions.push((i, j));
+
+    let changes: Vec<usize> = (0..ops.len()).filter(|&k| !matches!(ops[k], DiffOp::Equal(_, _))).collect();
+    let mut hunks = Vec::new();
+    let mut n = 0;
+    while n < changes.len() {
+        let first = changes[n];
+        let mut last = first;
+        while n + 1 < changes.len() && changes[n + 1] - last <= 2 * context + 1 {
+            n += 1;
+            last = changes[n];
+        }
+        n += 1;
+
+        let start = first.saturating_sub(context);
+        let end = (last + 1 + context).min(ops.len());
+        let (old_from, new_from) = positions[start];
+        let (old_to, new_to) = positions[end];
+        let (old_lines, new_lines) = (old_to - old_from, new_to - new_from);
+        hunks.push(TextHunk {
+            old_start: if old_lines == 0 { old_from } else { old_from + 1 },
+            old_lines,
+            new_start: if new_lines == 0 { new_from } else { new_from + 1 },
+            new_lines,
+            ops: ops[start..end].to_vec(),
+        });
+    }
+    hunks
+}
+
+// Unified diff of the sources of a comparison, labelled with its version
+// names. Identical sources give an empty diff.
+pub fn unified_diff(result: &ComparisonResult, old: &str, new: &str, context: usize) -> String {
+    let (old, new) = (source_lines(old), source_lines(new));
+    let hunks = text_hunks(&old, &new, context);
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", result.version_a, result.version_b);
+    for hunk in &hunks {
+        out.push_str(&hunk.header());
+        if let Some(heading) = hunk.heading(&result.line_diffs) {
+            out.push(' ');
+            out.extend(heading.chars().take(80).filter(|c| !c.is_control()));
+        }
+        out.push('\n');
+        for op in &hunk.ops {
+            let (marker, line) = match *op {
+                DiffOp::Equal(i, _) => (' ', old[i]),
+                DiffOp::Delete(i) => ('-', old[i]),
+                DiffOp::Insert(j) => ('+', new[j]),
+            };
+            out.push(marker);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+// Both sources next to each other, `width` columns in total, showing the
+// hunks of `text_hunks`. The marker between the columns is `<` for a removed
+// line, `>` for an added one and `|` for a changed one. `color` adds ANSI
+// colours: removed text red, added text green, hunk headers cyan.
+pub fn side_by_side(result: &ComparisonResult, old: &str, new: &str, context: usize, width: usize, color: bool) -> String {
+    let (old, new) = (source_lines(old), source_lines(new));
+    let hunks = text_hunks(&old, &new, context);
+
+    // Two line-number gutters, two separating spaces and the marker column
+    let column = width.saturating_sub(2 * 6 + 3).max(20) / 2;
+    // Colour the text of a fitted column, not its padding
+    let paint = |text: String, code: &str| {
+        let len = text.trim_end().len();
+        if color { format!("{}{}{}{}", code, &text[..len], RESET, &text[len..]) } else { text }
+    };
+
+    let mut out = String::new();
+    for hunk in &hunks {
+        let mut header = hunk.header();
+        if let Some(heading) = hunk.heading(&result.line_diffs) {
+            header.push(' ');
+            header.extend(heading.chars().filter(|c| !c.is_control()));
+        }
+        out.push_str(&paint(fit(&header, width).trim_end().to_string(), CYAN));
+        out.push('\n');
+
+        let mut ops = hunk.ops.iter().peekable();
+        while let Some(op) = ops.next() {
+            if let DiffOp::Equal(i, j) = *op {
+                let (left, right) = (fit(old[i], column), fit(new[j], column));
+                out.push_str(&format!("{:>5} {}   {:>5} {}\n", i + 1, left, j + 1, right.trim_end()));
+                continue;
+            }
+
+            // A run of changes: removed lines pair up with added lines row by row
+            let mut removed = Vec::new();
+            let mut added = Vec::new();
+            let mut next = Some(op);
+            while let Some(&change) = next {
+                match change {
+                    DiffOp::Delete(i) => removed.push(i),
+                    DiffOp::Insert(j) => added.push(j),
+                    DiffOp::Equal(_, _) => unreachable!("runs stop at equal lines"),
+                }
+                next = ops.next_if(|op| !matches!(op, DiffOp::Equal(_, _)));
+            }
+            for row in 0..removed.len().max(added.len()) {
+                let left = match removed.get(row) {
+                    Some(&i) => format!("{:>5} {}", i + 1, paint(fit(old[i], column), RED)),
+                    None => " ".repeat(6 + column),
+                };
+                let (marker, right) = match (removed.get(row), added.get(row)) {
+                    (_, Some(&j)) => {
+                        let marker = if row < removed.len() { '|' } else { '>' };
+                        (marker, format!("{:>5} {}", j + 1, paint(fit(new[j], column).trim_end().to_string(), GREEN)))
+                    }
+                    (_, None) => ('<', String::new()),
+                };
+                out.push_str(&format!("{} {} {}\n", left, marker, right).replace(" \n", "\n"));
+            }
+        }
+    }
+    out
+}
+
+// `line` without its terminator, tabs expanded, cut or padded to `width` characters
+fn fit(line: &str, width: usize) -> String {
+    let line = line.trim_end_matches(['\n', '\r']).replace('\t', "    ");
+    let mut fitted: String = line.chars().filter(|c| !c.is_control()).take(width).collect();
+    let len = fitted.chars().count();
+    fitted.extend(std::iter::repeat_n(' ', width - len));
+    fitted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DomDiffer;
+
+    fn hunk_ranges(old: &str, new: &str, context: usize) -> Vec<String> {
+        text_hunks(&source_lines(old), &source_lines(new), context).iter().map(TextHunk::header).collect()
+    }
+
+    fn numbered(lines: std::ops::Range<usize>) -> String {
+        lines.map(|n| format!("line {}\n", n)).collect()
+    }
+
+    // Apply a unified diff the way `patch` does, checking every context and
+    // removed line against `old`
+    fn apply(old: &str, diff: &str) -> String {
+        let old = source_lines(old);
+        let mut out = String::new();
+        let mut next = 0;
+        let mut lines = diff.split_inclusive('\n').skip(2).peekable();
+        while let Some(header) = lines.next() {
+            let old_range = header.strip_prefix("@@ -").unwrap().split(' ').next().unwrap();
+            let (start, count) = old_range.split_once(',').unwrap_or((old_range, "1"));
+            let (start, count): (usize, usize) = (start.parse().unwrap(), count.parse().unwrap());
+            let first = if count == 0 { start } else { start - 1 };
+            old[next..first].iter().for_each(|line| out.push_str(line));
+            next = first;
+            while let Some(line) = lines.next_if(|line| !line.starts_with("@@")) {
+                let no_newline = lines.next_if(|l| l.starts_with("\\ No newline")).is_some();
+                let text = if no_newline { line[1..].trim_end_matches('\n') } else { &line[1..] };
+                match &line[..1] {
+                    " " | "-" => {
+                        assert_eq!(old[next], text);
+                        next += 1;
+                        if line.starts_with(' ') {
+                            out.push_str(text);
+                        }
+                    }
+                    "+" => out.push_str(text),
+                    other => panic!("unexpected line marker {other:?}"),
+                }
+            }
+        }
+        old[next..].iter().for_each(|line| out.push_str(line));
+        out
+    }
+
+    #[test]
+    fn merges_changes_at_most_twice_the_context_apart() {
+        let old = numbered(0..20);
+        // Changes at lines 3 and 10 leave six unchanged lines between them
+        let new = old.replace("line 2\n", "two\n").replace("line 9\n", "nine\n");
+        assert_eq!(hunk_ranges(&old, &new, 3), ["@@ -1,13 +1,13 @@"]);
+        // Seven unchanged lines between lines 3 and 11 split the hunk
+        let new = old.replace("line 2\n", "two\n").replace("line 10\n", "ten\n");
+        assert_eq!(hunk_ranges(&old, &new, 3), ["@@ -1,6 +1,6 @@", "@@ -8,7 +8,7 @@"]);
+        assert_eq!(hunk_ranges(&old, &new, 0), ["@@ -3 +3 @@", "@@ -11 +11 @@"]);
+        assert!(hunk_ranges(&old, &old, 3).is_empty());
+    }
+
+    #[test]
+    fn pure_inserts_and_deletes_have_empty_ranges() {
+        let old = "a\nb\nc\n";
+        assert_eq!(hunk_ranges(old, "a\nX\nb\nc\n", 0), ["@@ -1,0 +2 @@"]);
+        assert_eq!(hunk_ranges(old, "a\nc\n", 0), ["@@ -2 +1,0 @@"]);
+        assert_eq!(hunk_ranges(old, "X\nY\na\nb\nc\n", 0), ["@@ -0,0 +1,2 @@"]);
+        assert_eq!(hunk_ranges(old, "", 1), ["@@ -1,3 +0,0 @@"]);
+        assert_eq!(hunk_ranges("", old, 3), ["@@ -0,0 +1,3 @@"]);
+        assert_eq!(hunk_ranges(old, "a\nX\nb\nc\n", 1), ["@@ -1,2 +1,3 @@"]);
+    }
+
+    #[test]
+    fn unified_diffs_apply_to_the_old_source() {
+        let old = "<html>\n<body>\n<h1>Title</h1>\n<p>one</p>\n<p>two</p>\n<p>three</p>\n</body>\n</html>\n";
+        let cases = [
+            "<html>\n<body>\n<h1>Title</h1>\n<p>one</p>\n<p>2</p>\n<p>three</p>\n</body>\n</html>\n",
+            "<html>\n<body>\n<p>one</p>\n<p>two</p>\n<p>three</p>\n<p>four</p>\n</body>\n</html>\n",
+            "<html>\n<body>\n<h1>Title</h1>\n</body>\n</html>",
+            "<p>new</p>\n",
+            "",
+        ];
+        for new in cases {
+            let result = DomDiffer::new().line_diffs(true).compare_named("old.html", old, "new.html", new);
+            for context in [0, 1, 3] {
+                let diff = unified_diff(&result, old, new, context);
+                assert!(diff.starts_with("--- old.html\n+++ new.html\n@@ -"), "{diff}");
+                assert_eq!(apply(old, &diff), new, "context {context}:\n{diff}");
+                assert_eq!(apply(new, &unified_diff(&result, new, old, context)), old);
+            }
+        }
+        let same = DomDiffer::new().compare(old, old);
+        assert_eq!(unified_diff(&same, old, old, 3), "");
+    }
+
+    #[test]
+    fn marks_a_missing_final_newline() {
+        let result = DomDiffer::new().compare("<p>a</p>", "<p>b</p>");
+        assert_eq!(
+            unified_diff(&result, "<p>a</p>\n<p>x</p>", "<p>a</p>\n<p>y</p>", 3),
+            "--- a\n+++ b\n@@ -1,2 +1,2 @@\n <p>a</p>\n-<p>x</p>\n\\ No newline at end of file\n+<p>y</p>\n\\ No newline at end of file\n"
+        );
+        // Only the side that lacks the newline is marked
+        let diff = unified_diff(&result, "x\n", "x", 0);
+        assert_eq!(diff, "--- a\n+++ b\n@@ -1 +1 @@\n-x\n+x\n\\ No newline at end of file\n");
+    }
+
+    #[test]
+    fn labels_hunks_with_the_first_dom_change() {
+        let old = "<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n";
+        let new = "<ul>\n<li>one</li>\n<li>2</li>\n</ul>\n";
+        let result = DomDiffer::new().line_diffs(true).compare(old, new);
+        let diff = unified_diff(&result, old, new, 1);
+        assert!(diff.contains("@@ -2,3 +2,3 @@ ~ TEXT:two -> TEXT:2\n"), "{diff}");
+    }
+
+    #[test]
+    fn side_by_side_marks_removed_added_and_changed_rows() {
+        let old = "keep\nold one\nold two\ngone\nkeep too\n";
+        let new = "keep\nnew one\nkeep too\nadded\n";
+        let result = DomDiffer::new().compare(old, new);
+        let text = side_by_side(&result, old, new, 1, 60, false);
+        let rows: Vec<&str> = text.lines().collect();
+        let column = (60 - 15) / 2;
+        let cell = |n: usize, s: &str| format!("{:>5} {:<width$}", n, s, width = column);
+        assert_eq!(
+            rows,
+            [
+                "@@ -1,5 +1,4 @@".to_string(),
+                format!("{}   {:>5} keep", cell(1, "keep"), 1),
+                format!("{} | {:>5} new one", cell(2, "old one"), 2),
+                format!("{} <", cell(3, "old two")),
+                format!("{} <", cell(4, "gone")),
+                format!("{}   {:>5} keep too", cell(5, "keep too"), 3),
+                format!("{} > {:>5} added", " ".repeat(6 + column), 4),
+            ]
+        );
+
+        let colored = side_by_side(&result, old, new, 1, 60, true);
+        assert!(colored.starts_with(&format!("{}@@ -1,5 +1,4 @@{}\n", CYAN, RESET)));
+        assert!(colored.contains(&format!("{}old two{}", RED, RESET)));
+        assert!(colored.contains(&format!("{}new one{}", GREEN, RESET)));
+    }
+}
@@ -0,0 +1,603 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::entities;
+
+// Elements whose content is raw text: no tags and no character references inside
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "xmp", "iframe", "noembed", "noframes", "noscript"];
+
+// Elements whose content is text with character references but no tags
+const RCDATA_ELEMENTS: &[&str] = &["title", "textarea"];
+
+// Elements that never have content or an end tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    StartTag,
+    EndTag,
+    Text,
+    // Content of script/style-like elements, never entity-decoded
+    RawText,
+    Comment,
+    Doctype,
+    CData,
+}
+
+// Names and values borrow from the source unless lower-casing or decoding
+// had to change them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute<'a> {
+    pub name: Cow<'a, str>,          // lower-cased
+    pub value: Option<Cow<'a, str>>, // decoded; None for `<input disabled>`
+}
+
+impl Attribute<'_> {
+    pub fn into_owned(self) -> Attribute<'static> {
+        Attribute {
+            name: Cow::Owned(self.name.into_owned()),
+            value: self.value.map(|v| Cow::Owned(v.into_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HtmlToken<'a> {
+    pub kind: TokenKind,
+    pub span: Range<usize>,    // byte range in the source, including delimiters
+    pub name: Cow<'a, str>,    // lower-cased tag name, empty for non-tags
+    pub attributes: Vec<Attribute<'a>>,
+    pub self_closing: bool,
+}
+
+impl<'a> HtmlToken<'a> {
+    fn new(kind: TokenKind, span: Range<usize>) -> Self {
+        HtmlToken {
+            kind,
+            span,
+            name: Cow::Borrowed(""),
+            attributes: Vec::new(),
+            self_closing: false,
+        }
+    }
+
+    pub fn into_owned(self) -> HtmlToken<'static> {
+        HtmlToken {
+            kind: self.kind,
+            span: self.span,
+            name: Cow::Owned(self.name.into_owned()),
+            attributes: self.attributes.into_iter().map(Attribute::into_owned).collect(),
+            self_closing: self.self_closing,
+        }
+    }
+
+    pub fn source<'h>(&self, html: &'h str) -> &'h str {
+        &html[self.span.clone()]
+    }
+
+    // Body of a comment, doctype or CDATA section without its delimiters
+    pub fn inner<'h>(&self, html: &'h str) -> &'h str {
+        let src = self.source(html);
+        let (prefix, suffix) = match self.kind {
+            TokenKind::Comment if src.starts_with("<!--") => ("<!--", "-->"),
+            TokenKind::Comment => ("<", ">"),
+            TokenKind::Doctype => ("<!", ">"),
+            TokenKind::CData => ("<![CDATA[", "]]>"),
+            _ => return src,
+        };
+        let body = &src[prefix.len().min(src.len())..];
+        body.strip_suffix(suffix).unwrap_or(body)
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&Attribute<'a>> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+}
+
+pub fn is_void_element(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name)
+}
+
+// Start tags that implicitly close an open `<p>`
+const P_CLOSERS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "details", "div", "dl", "fieldset", "figcaption", "figure",
+    "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hgroup", "hr", "main", "menu", "nav", "ol",
+    "p", "pre", "section", "table", "ul",
+];
+
+// Whether opening `next` implicitly ends the currently open `open` element
+// (`<li>a<li>b`, `<p>text<div>`, `<td>1<td>2`, ...)
+pub fn closes_implicitly(open: &str, next: &str) -> bool {
+    match open {
+        "p" => P_CLOSERS.contains(&next),
+        "li" => next == "li",
+        "dt" | "dd" => matches!(next, "dt" | "dd"),
+        "option" => matches!(next, "option" | "optgroup"),
+        "td" | "th" => matches!(next, "td" | "th" | "tr"),
+        "tr" => next == "tr",
+        _ => false,
+    }
+}
+
+// Track the stack of open elements the way a (simplified) tree builder would:
+// start tags of non-void elements open, common implied end tags are applied,
+// end tags close back to their match, and stray end tags are ignored. Open
+// elements are owned copies, so the stack can outlive the buffer they came from.
+pub fn update_open_elements(open: &mut Vec<HtmlToken<'static>>, token: &HtmlToken<'_>) {
+    match token.kind {
+        TokenKind::StartTag => {
+            while open.last().is_some_and(|top| closes_implicitly(&top.name, &token.name)) {
+                open.pop();
+            }
+            if !token.self_closing && !is_void_element(&token.name) {
+                open.push(token.clone().into_owned());
+            }
+        }
+        TokenKind::EndTag => {
+            if let Some(idx) = open.iter().rposition(|t| t.name == token.name) {
+                open.truncate(idx);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Split an HTML document into tags, text, comments, doctypes and CDATA sections.
+//
+// Follows the HTML5 tokenizer closely enough for diffing: quoted attribute values
+// may contain `>`, raw-text elements (`<script>`, `<style>`, ...) run until their
+// matching end tag, comments run until `-->`, and a `<` that cannot start markup
+// is kept as text. Every byte of the input belongs to exactly one token.
+pub fn tokenize(html: &str) -> Vec<HtmlToken<'_>> {
+    tokenize_tracking(html).0
+}
+
+// Tokens of the beginning of a document whose end has not been read yet, and
+// the number of bytes they cover. Tokens that more input could still change
+// are left out: the last one, anything from markup cut off by the end of the
+// buffer on, and raw-text or RCDATA elements whose end tag is not complete.
+// Tokenizing the rest of the document from that byte on gives the same
+// tokens as one pass over the whole of it.
+pub fn tokenize_prefix(html: &str) -> (Vec<HtmlToken<'_>>, usize) {
+    let (mut tokens, incomplete) = tokenize_tracking(html);
+    tokens.pop();
+    if let Some(incomplete) = incomplete {
+        while tokens.last().is_some_and(|t| t.span.end > incomplete) {
+            tokens.pop();
+        }
+    }
+    // A kept element body must be followed by its end tag, or the rest would be tokenized as markup
+    loop {
+        let n = tokens.len();
+        let open_body = match tokens.last() {
+            None => false,
+            Some(last) if last.kind == TokenKind::StartTag => has_text_content(&last.name),
+            Some(last) if last.kind == TokenKind::RawText => true,
+            Some(last) if last.kind == TokenKind::Text => {
+                n >= 2 && tokens[n - 2].kind == TokenKind::StartTag && RCDATA_ELEMENTS.contains(&tokens[n - 2].name.as_ref())
+            }
+            Some(_) => false,
+        };
+        if !open_body {
+            break;
+        }
+        tokens.pop();
+    }
+    let covered = tokens.last().map_or(0, |t| t.span.end);
+    (tokens, covered)
+}
+
+// Elements whose content the tokenizer reads as a single text token
+fn has_text_content(name: &str) -> bool {
+    name == "plaintext" || RAW_TEXT_ELEMENTS.contains(&name) || RCDATA_ELEMENTS.contains(&name)
+}
+
+// Tokenize, also returning the first `<` that only failed to parse as markup
+// because the input ended. An end tag cut off by the end of the input is one,
+// so a raw-text body that might run on is always followed by such a `<`.
+fn tokenize_tracking(html: &str) -> (Vec<HtmlToken<'_>>, Option<usize>) {
+    let bytes = html.as_bytes();
+    let mut tokens = Vec::with_capacity(html.len() / 20);
+    let mut text_start = 0;
+    let mut pos = 0;
+    let mut incomplete = None;
+
+    while pos < bytes.len() {
+        if bytes[pos] != b'<' {
+            pos = find_byte(bytes, pos, b'<').unwrap_or(bytes.len());
+            continue;
+        }
+
+        let Some(token) = parse_markup(html, pos) else {
+            // Only `<` followed by anything but `!`, `?`, `/` or a letter is text for certain
+            let could_be_markup = bytes
+                .get(pos + 1)
+                .is_none_or(|&b| matches!(b, b'!' | b'?' | b'/') || b.is_ascii_alphabetic());
+            if could_be_markup && incomplete.is_none() {
+                incomplete = Some(pos);
+            }
+            pos += 1;
+            continue;
+        };
+
+        if text_start < pos {
+            tokens.push(HtmlToken::new(TokenKind::Text, text_start..pos));
+        }
+        pos = token.span.end;
+        text_start = pos;
+
+        let content_kind = if token.kind != TokenKind::StartTag {
+            None
+        } else if token.name == "plaintext" {
+            Some((TokenKind::RawText, bytes.len()))
+        } else if RAW_TEXT_ELEMENTS.contains(&token.name.as_ref()) {
+            Some((TokenKind::RawText, find_end_tag(html, pos, &token.name)))
+        } else if RCDATA_ELEMENTS.contains(&token.name.as_ref()) {
+            Some((TokenKind::Text, find_end_tag(html, pos, &token.name)))
+        } else {
+            None
+        };
+        tokens.push(token);
+
+        if let Some((kind, end)) = content_kind {
+            if pos < end {
+                tokens.push(HtmlToken::new(kind, pos..end));
+            }
+            pos = end;
+            text_start = end;
+        }
+    }
+
+    if text_start < bytes.len() {
+        tokens.push(HtmlToken::new(TokenKind::Text, text_start..bytes.len()));
+    }
+
+    (tokens, incomplete)
+}
+
+fn find_byte(bytes: &[u8], from: usize, needle: u8) -> Option<usize> {
+    bytes[from..].iter().position(|&b| b == needle).map(|i| from + i)
+}
+
+fn find_str(html: &str, from: usize, needle: &str) -> Option<usize> {
+    html[from..].find(needle).map(|i| from + i)
+}
+
+fn starts_with_ignore_case(html: &str, pos: usize, prefix: &str) -> bool {
+    html.as_bytes()
+        .get(pos..pos + prefix.len())
+        .is_some_and(|b| b.eq_ignore_ascii_case(prefix.as_bytes()))
+}
+
+fn is_tag_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r' | b'\x0c')
+}
+
+// Position of `</name` closing a raw-text element, or the end of input
+fn find_end_tag(html: &str, from: usize, name: &str) -> usize {
+    let bytes = html.as_bytes();
+    let mut pos = from;
+    while let Some(lt) = find_byte(bytes, pos, b'<') {
+        if bytes.get(lt + 1) == Some(&b'/') && starts_with_ignore_case(html, lt + 2, name) {
+            let after = lt + 2 + name.len();
+            if after >= bytes.len() || is_tag_whitespace(bytes[after]) || bytes[after] == b'/' || bytes[after] == b'>' {
+                return lt;
+            }
+        }
+        pos = lt + 1;
+    }
+    bytes.len()
+}
+
+// Parse the markup construct starting at `<`, or None if it is literal text
+fn parse_markup(html: &str, pos: usize) -> Option<HtmlToken<'_>> {
+    let bytes = html.as_bytes();
+    let next = *bytes.get(pos + 1)?;
+
+    match next {
+        b'!' => {
+            if html[pos..].starts_with("<!--") {
+                // `<!-->` and `<!--->` are complete (empty) comments
+                let end = if html[pos..].starts_with("<!-->") {
+                    pos + 5
+                } else if html[pos..].starts_with("<!--->") {
+                    pos + 6
+                } else {
+                    find_str(html, pos + 4, "-->").map_or(bytes.len(), |i| i + 3)
+                };
+                Some(HtmlToken::new(TokenKind::Comment, pos..end))
+            } else if html[pos..].starts_with("<![CDATA[") {
+                let end = find_str(html, pos + 9, "]]>").map_or(bytes.len(), |i| i + 3);
+                Some(HtmlToken::new(TokenKind::CData, pos..end))
+            } else if starts_with_ignore_case(html, pos + 2, "doctype") {
+                let end = find_byte(bytes, pos, b'>').map_or(bytes.len(), |i| i + 1);
+                Some(HtmlToken::new(TokenKind::Doctype, pos..end))
+            } else {
+                Some(bogus_comment(bytes, pos))
+            }
+        }
+        b'?' => Some(bogus_comment(bytes, pos)),
+        b'/' => {
+            let first = *bytes.get(pos + 2)?;
+            if first == b'>' {
+                // `</>` is dropped by browsers; treat it as an empty comment
+                Some(HtmlToken::new(TokenKind::Comment, pos..pos + 3))
+            } else if first.is_ascii_alphabetic() {
+                parse_tag(html, pos, pos + 2, TokenKind::EndTag)
+            } else {
+                Some(bogus_comment(bytes, pos))
+            }
+        }
+        b if b.is_ascii_alphabetic() => parse_tag(html, pos, pos + 1, TokenKind::StartTag),
+        _ => None,
+    }
+}
+
+fn bogus_comment<'a>(bytes: &[u8], pos: usize) -> HtmlToken<'a> {
+    let end = find_byte(bytes, pos, b'>').map_or(bytes.len(), |i| i + 1);
+    HtmlToken::new(TokenKind::Comment, pos..end)
+}
+
+// Parse a start or end tag whose name begins at `name_start`.
+// An unterminated tag at end of input is treated as text.
+fn parse_tag(html: &str, pos: usize, name_start: usize, kind: TokenKind) -> Option<HtmlToken<'_>> {
+    let bytes = html.as_bytes();
+    let mut i = name_start;
+    while i < bytes.len() && !is_tag_whitespace(bytes[i]) && bytes[i] != b'/' && bytes[i] != b'>' {
+        i += 1;
+    }
+    let name = lowercase(&html[name_start..i]);
+    let mut attributes: Vec<Attribute> = Vec::new();
+    let mut self_closing = false;
+
+    loop {
+        while i < bytes.len() && is_tag_whitespace(bytes[i]) {
+            i += 1;
+        }
+        match bytes.get(i) {
+            None => return None,
+            Some(b'>') => {
+                i += 1;
+                break;
+            }
+            Some(b'/') => {
+                i += 1;
+                if bytes.get(i) == Some(&b'>') {
+                    self_closing = true;
+                    i += 1;
+                    break;
+                }
+                continue;
+            }
+            Some(_) => {}
+        }
+
+        // Attribute name; a leading `=` is part of the name per the spec
+        let attr_start = i;
+        i += 1;
+        while i < bytes.len() && !is_tag_whitespace(bytes[i]) && !matches!(bytes[i], b'/' | b'>' | b'=') {
+            i += 1;
+        }
+        let attr_name = lowercase(&html[attr_start..i]);
+
+        let mut j = i;
+        while j < bytes.len() && is_tag_whitespace(bytes[j]) {
+            j += 1;
+        }
+        let mut value = None;
+        if bytes.get(j) == Some(&b'=') {
+            j += 1;
+            while j < bytes.len() && is_tag_whitespace(bytes[j]) {
+                j += 1;
+            }
+            match bytes.get(j) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    let close = find_byte(bytes, j + 1, quote)?;
+                    value = Some(decode_character_references(&html[j + 1..close], true));
+                    i = close + 1;
+                }
+                Some(_) => {
+                    let start = j;
+                    while j < bytes.len() && !is_tag_whitespace(bytes[j]) && bytes[j] != b'>' {
+                        j += 1;
+                    }
+                    value = Some(decode_character_references(&html[start..j], true));
+                    i = j;
+                }
+                None => return None,
+            }
+        }
+
+        // Duplicate attributes are ignored, keeping the first occurrence
+        if !attributes.iter().any(|a| a.name == attr_name) {
+            attributes.push(Attribute { name: attr_name, value });
+        }
+    }
+
+    Some(HtmlToken {
+        kind,
+        span: pos..i,
+        name,
+        attributes: if kind == TokenKind::EndTag { Vec::new() } else { attributes },
+        self_closing,
+    })
+}
+
+// ASCII lower-case, borrowing when there is nothing to change
+fn lowercase(text: &str) -> Cow<'_, str> {
+    if text.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(text.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+// Code points 0x80..=0x9F in numeric references map to windows-1252
+const WINDOWS_1252: [u32; 32] = [
+    0x20ac, 0x81, 0x201a, 0x192, 0x201e, 0x2026, 0x2020, 0x2021, 0x2c6, 0x2030, 0x160, 0x2039, 0x152, 0x8d, 0x17d, 0x8f,
+    0x90, 0x2018, 0x2019, 0x201c, 0x201d, 0x2022, 0x2013, 0x2014, 0x2dc, 0x2122, 0x161, 0x203a, 0x153, 0x9d, 0x17e, 0x178,
+];
+
+// Decode numeric and named character references (`&#38;`, `&#x26;`, `&amp;`).
+// In attribute values a legacy reference without `;` followed by an
+// alphanumeric or `=` is left alone, as the spec requires for URLs like `?a=1&copy=2`.
+pub fn decode_character_references(text: &str, in_attribute: bool) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    let mut pos = 0;
+
+    while let Some(amp) = find_byte(bytes, pos, b'&') {
+        pos = amp + 1;
+        let Some((decoded, end)) = decode_reference(text, amp, in_attribute) else {
+            continue;
+        };
+        out.push_str(&text[last..amp]);
+        out.push_str(&decoded);
+        last = end;
+        pos = end;
+    }
+
+    out.push_str(&text[last..]);
+    Cow::Owned(out)
+}
+
+fn decode_reference(text: &str, amp: usize, in_attribute: bool) -> Option<(String, usize)> {
+    let bytes = text.as_bytes();
+    let mut i = amp + 1;
+
+    if bytes.get(i) == Some(&b'#') {
+        i += 1;
+        let hex = matches!(bytes.get(i), Some(b'x') | Some(b'X'));
+        if hex {
+            i += 1;
+        }
+        let digits_start = i;
+        while i < bytes.len() && (if hex { bytes[i].is_ascii_hexdigit() } else { bytes[i].is_ascii_digit() }) {
+            i += 1;
+        }
+        if i == digits_start {
+            return None;
+        }
+        let code = u32::from_str_radix(&text[digits_start..i], if hex { 16 } else { 10 }).unwrap_or(u32::MAX);
+        if bytes.get(i) == Some(&b';') {
+            i += 1;
+        }
+        let code = match code {
+            0x80..=0x9f => WINDOWS_1252[(code - 0x80) as usize],
+            _ => code,
+        };
+        let ch = if code == 0 { None } else { char::from_u32(code) };
+        return Some((ch.unwrap_or('\u{fffd}').to_string(), i));
+    }
+
+    let name_start = i;
+    while i < bytes.len() && bytes[i].is_ascii_alphanumeric() {
+        i += 1;
+    }
+    if bytes.get(i) == Some(&b';')
+        && let Some(value) = entities::lookup(&text[name_start..=i])
+    {
+        return Some((value.to_string(), i + 1));
+    }
+
+    // Without a `;`, the longest legacy reference the name starts with applies (`&notin` is `¬in`)
+    let name = &text[name_start..i];
+    let (value, end) = (1..=name.len().min(entities::LONGEST_NAME))
+        .rev()
+        .find_map(|len| entities::lookup(&name[..len]).map(|value| (value, name_start + len)))?;
+    if in_attribute && matches!(bytes.get(end), Some(b) if b.is_ascii_alphanumeric() || *b == b'=') {
+        return None;
+    }
+    Some((value.to_string(), end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DomDiffer;
+
+    fn kinds(html: &str) -> Vec<TokenKind> {
+        tokenize(html).iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn quoted_attribute_values_may_contain_gt() {
+        let html = r#"<a title="a>b" data-x='1 > 0' href=x>t</a>"#;
+        let tokens = tokenize(html);
+        assert_eq!(kinds(html), [TokenKind::StartTag, TokenKind::Text, TokenKind::EndTag]);
+        assert_eq!(tokens[0].attribute("title").unwrap().value.as_deref(), Some("a>b"));
+        assert_eq!(tokens[0].attribute("data-x").unwrap().value.as_deref(), Some("1 > 0"));
+        assert_eq!(tokens[1].source(html), "t");
+    }
+
+    #[test]
+    fn script_body_runs_until_its_own_end_tag() {
+        let html = r#"<script>if (a</b) x = "</div>";</script><p>"#;
+        let tokens = tokenize(html);
+        assert_eq!(
+            kinds(html),
+            [TokenKind::StartTag, TokenKind::RawText, TokenKind::EndTag, TokenKind::StartTag]
+        );
+        assert_eq!(tokens[1].source(html), r#"if (a</b) x = "</div>";"#);
+        assert_eq!(tokens[2].name, "script");
+    }
+
+    #[test]
+    fn tags_inside_comments_are_not_markup() {
+        let html = "<!-- <div class=\"x\"> </div> --><p>";
+        let tokens = tokenize(html);
+        assert_eq!(kinds(html), [TokenKind::Comment, TokenKind::StartTag]);
+        assert_eq!(tokens[0].inner(html), " <div class=\"x\"> </div> ");
+    }
+
+    #[test]
+    fn cdata_section_is_one_token() {
+        let html = "<svg><![CDATA[<b>x</b> & y]]></svg>";
+        let tokens = tokenize(html);
+        assert_eq!(kinds(html), [TokenKind::StartTag, TokenKind::CData, TokenKind::EndTag]);
+        assert_eq!(tokens[1].inner(html), "<b>x</b> & y");
+    }
+
+    #[test]
+    fn decodes_the_full_named_reference_table() {
+        assert_eq!(entities::NAMED_REFERENCES.len(), 2231);
+        assert!(entities::NAMED_REFERENCES.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(
+            decode_character_references("&frac12; &zwj;&Aacute; &notin; &CounterClockwiseContourIntegral;", false),
+            "\u{bd} \u{200d}\u{c1} \u{2209} \u{2233}"
+        );
+        assert_eq!(decode_character_references("&#38; &#x26; &#x80; &#0;", false), "& & \u{20ac} \u{fffd}");
+        assert_eq!(decode_character_references("&bogus; &amp", false), "&bogus; &");
+    }
+
+    #[test]
+    fn legacy_references_match_their_longest_prefix() {
+        assert_eq!(decode_character_references("&notin", false), "\u{ac}in");
+        assert_eq!(decode_character_references("&noti;", false), "\u{ac}i;");
+        assert_eq!(decode_character_references("&ampx &lt3", false), "&x <3");
+        // Semicolon-only references need their semicolon
+        assert_eq!(decode_character_references("&zwj &Aacutex", false), "&zwj \u{c1}x");
+    }
+
+    #[test]
+    fn legacy_references_in_attributes_keep_url_parameters() {
+        assert_eq!(decode_character_references("?a=1&copy=2&notx", true), "?a=1&copy=2&notx");
+        assert_eq!(decode_character_references("&copy 2024&amp;x", true), "\u{a9} 2024&x");
+    }
+
+    #[test]
+    fn references_and_literal_text_compare_equal() {
+        let result = DomDiffer::new().compare(
+            "<p>&frac12; &zwj;&Aacute; &notin;</p>",
+            "<p>\u{bd} \u{200d}\u{c1} \u{2209}</p>",
+        );
+        assert_eq!(result.difference_percent, 0.0);
+    }
+}
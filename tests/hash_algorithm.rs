@@ -0,0 +1,41 @@
+// Changes the process-wide PerformanceConfig, so it runs as its own test binary
+// instead of next to the unit tests.
+
+use std::io::Cursor;
+
+use merkle_domdiff::{DiffMethod, DomDiffer, HashAlgorithm, PerformanceConfig, set_perf_config};
+
+#[test]
+fn comparison_results_record_the_configured_algorithm() {
+    let a = "<main><p>one</p><p>two</p></main>";
+    let b = "<main><p>one</p><p>three</p></main>";
+
+    for algorithm in [HashAlgorithm::Xxh3_64, HashAlgorithm::Xxh3_128, HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+        set_perf_config(PerformanceConfig {
+            hash_algorithm: algorithm,
+            ..PerformanceConfig::default()
+        });
+
+        let differs = [
+            DomDiffer::new().line_diffs(false),
+            DomDiffer::new(),
+            DomDiffer::new().method(DiffMethod::MerkleTree),
+            DomDiffer::new().method(DiffMethod::DomTree),
+            DomDiffer::new().method(DiffMethod::TreeEdit),
+        ];
+        for differ in &differs {
+            let result = differ.compare(a, b);
+            assert_eq!(result.hash_algorithm, algorithm.as_str(), "{}", result.method);
+            assert!(result.difference_percent > 0.0);
+        }
+
+        let streamed = DomDiffer::new()
+            .line_diffs(false)
+            .compare_readers("a", Cursor::new(a), "b", Cursor::new(b))
+            .unwrap();
+        assert_eq!(streamed.hash_algorithm, algorithm.as_str());
+
+        let json = serde_json::to_value(differs[0].compare(a, a)).unwrap();
+        assert_eq!(json["hash_algorithm"], algorithm.as_str());
+    }
+}